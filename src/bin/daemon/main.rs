@@ -30,7 +30,13 @@ async fn main() -> Result<(), BlockchainError> {
     let prompt = Prompt::new(config.debug, config.disable_file_logging, command_manager)?;
     info!("Xelis Blockchain running version: {}", VERSION);
     info!("----------------------------------------------");
-    let blockchain = Blockchain::new(config.nested).await?;
+    let blockchain = match Blockchain::new(config.nested).await {
+        Ok(blockchain) => blockchain,
+        Err(e) => {
+            error!("Couldn't start the node: {}", e);
+            return Err(e)
+        }
+    };
 
     tokio::select! {
         Err(e) = run_prompt(&prompt, blockchain.clone()) => {
@@ -1,5 +1,6 @@
 use serde_json::Value;
-use xelis_blockchain::{core::{json_rpc::JsonRPCClient, block::Block, serializer::Serializer, difficulty::check_difficulty}, rpc::rpc::{GetBlockTemplateParams, GetBlockTemplateResult, SubmitBlockParams}, config::DEV_ADDRESS, globals::get_current_timestamp, crypto::{hash::Hashable, address::Address}};
+use xelis_blockchain::{core::{json_rpc::JsonRPCClient, block::Block, serializer::Serializer, }, rpc::rpc::{GetBlockTemplateParams, GetBlockTemplateResult, SubmitBlockParams}, config::{DEV_ADDRESS, MINING_ITERATIONS}, crypto::address::Address};
+use std::sync::atomic::AtomicBool;
 use xelis_blockchain::config::VERSION;
 use clap::Parser;
 
@@ -20,16 +21,16 @@ fn main() {
     let config: MinerConfig = MinerConfig::parse();
     let client = JsonRPCClient::new(format!("{}/json_rpc", config.daemon_address));
     let get_block_template = GetBlockTemplateParams { address: Address::from_string(&config.miner_address).unwrap() };
+    let stop = AtomicBool::new(false);
     loop {
         println!("Requesting block template");
         let block_template: GetBlockTemplateResult = client.call_with("get_block_template", &get_block_template).unwrap();
         let mut block = Block::from_hex(block_template.template).unwrap();
-        let mut hash = block.hash();
-        while !check_difficulty(&hash, block_template.difficulty).unwrap() {
-            block.nonce += 1;
-            block.timestamp = get_current_timestamp();
-            hash = block.hash();
-        }
+        // request a new template regularly to not keep mining on an outdated block
+        let hash = match block.mine(block_template.difficulty, MINING_ITERATIONS, &stop).unwrap() {
+            Some(hash) => hash,
+            None => continue
+        };
 
         println!("Sending block with hash {}", hash);
         match client.call_with::<SubmitBlockParams, Value>("submit_block", &SubmitBlockParams { block_template: block.to_hex(), block_hashing_blob: "".into()}) {
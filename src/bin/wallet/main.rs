@@ -1,7 +1,13 @@
 use xelis_blockchain::wallet::Wallet;
 
 fn main() {
-    let wallet = Wallet::new(format!("http://{}/json_rpc", "127.0.0.1:8080"));
+    let wallet = match Wallet::new(format!("http://{}/json_rpc", "127.0.0.1:8080")) {
+        Ok(wallet) => wallet,
+        Err(e) => {
+            println!("Error while creating wallet: {}", e);
+            return;
+        }
+    };
     println!("New address: {}", wallet.get_address());
     let tx_registration = wallet.create_tx_registration();
     if let Err(e) = wallet.send_transaction(&tx_registration) {
@@ -1,14 +1,28 @@
 pub const VERSION: &str = "alpha-0.0.1";
 pub const NETWORK_ID: [u8; 16] = [0xA, 0xB, 0xC, 0xD, 0xE, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xF];
+pub const PROTOCOL_VERSION: u8 = 1; // p2p protocol version, 0 is the original one without handshake extension
 pub const SEED_NODES: [&str; 1] = ["127.0.0.1:2125"]; // ["127.0.0.1:2125", "127.0.0.1:2126", "127.0.0.1:2127", "127.0.0.1:2128"];
 pub const DEFAULT_P2P_BIND_ADDRESS: &str = "0.0.0.0:2125";
 pub const DEFAULT_RPC_BIND_ADDRESS: &str = "0.0.0.0:8080";
 
 pub const BLOCK_TIME: u64 = 15 * 1000; // Block Time in milliseconds
+pub const TIMESTAMP_FUTURE_TOLERANCE: u128 = 30 * 1000; // maximum time in milliseconds a block timestamp can be ahead of our time
+pub const MEDIAN_TIME_PAST_WINDOW: usize = 11; // block timestamp must be greater than the median timestamp of the last X blocks
 pub const MINIMUM_DIFFICULTY: u64 = BLOCK_TIME * 10;
+pub const REGTEST_MINIMUM_DIFFICULTY: u64 = 1; // any hash is valid, blocks are mined instantly
 pub const REGISTRATION_DIFFICULTY: u64 = 1/*0_000_000*/;
 pub const MAX_BLOCK_SIZE: usize = (1024 * 1024) + (256 * 1024); // 1.25 MB
 pub const FEE_PER_KB: u64 = 1000; // 0.01000 XLS per KB
+pub const DEFAULT_RBF_MIN_FEE_INCREMENT: u64 = 10; // minimum fee increase in percent for a tx to replace the conflicting ones in mempool
+pub const DEFAULT_MIN_RELAY_FEE_PER_BYTE: u64 = 0; // default minimum fee per byte for a tx to be accepted in mempool and relayed (0 to only require the network fee)
+pub const DEFAULT_DUST_THRESHOLD: u64 = 100; // default minimum amount of a tx output to be accepted in mempool and relayed (0.00100 XLS)
+pub const DEFAULT_SIGNATURE_CACHE_SIZE: usize = 16384; // default number of signature verification results kept in cache
+pub const MAX_TRANSACTION_INPUTS: usize = 32; // maximum previous outputs referenced by a transaction
+pub const MAX_TRANSACTION_DATA_SIZE: usize = 256; // maximum bytes of the memo attached to a transaction
+pub const COINBASE_MATURITY: u64 = 60; // confirmations required before a coinbase output can be spent
+pub const REGTEST_COINBASE_MATURITY: u64 = 1; // coinbase outputs are spendable from the next block
+pub const IMPORT_PROGRESS_INTERVAL: u64 = 1000; // log the import progress every X blocks
+pub const MINING_ITERATIONS: u64 = 100_000; // nonces tried on a block template before checking if it must be refreshed
 pub const DEV_FEE_PERCENT: u64 = 5; // 5% per block going to dev address
 
 pub const PREFIX_ADDRESS: &str = "xel"; // mainnet prefix address
@@ -18,14 +32,42 @@ pub const MAX_SUPPLY: u64 = 18_400_000 * COIN_VALUE; // 18.4M full coin
 pub const EMISSION_SPEED_FACTOR: u64 = 21;
 
 pub const GENESIS_BLOCK: &str = "0000000000000001000000000000000000000181a6c150b90000000000000000000000000000000000000000000000000000000000000000000000000000a8ac000000000000000000000000000000000000000000000000000000000000000000006c24cdc1c8ee8f028b8cafe7b79a66a0902f26d89dd54eeff80abcf251a9a3bd0200000000000249f0"; // Genesis block in hexadecimal format
-pub const DEV_ADDRESS: &str = "xel1qyqxcfxdc8ywarcz3wx2leahnfn2pyp0ymvfm42waluq408j2x5680g05xfx5"; // Dev address
+pub const DEV_ADDRESS: &str = "xel1qqqsqmpyehqu3m50q29cetl8k7dxdgys9und38w4fmhlsz4u7fg6ngaamttjk4"; // Dev address
+pub const MAINNET_CHECKPOINTS: [(u64, &str); 1] = [(1, "0000286768574eeb6876382ff3aa346eb919b01b1a68656e45991444cb13e69c")]; // (height, block hash) that can't be reorganized
 
-pub const MAX_BLOCK_REWIND: u64 = 5; // maximum X blocks can be rewinded
+pub const DEFAULT_MAX_REORG_DEPTH: u64 = 5; // default maximum X blocks which can be rewinded to switch to another chain
 pub const CHAIN_SYNC_TIMEOUT_SECS: u64 = 3; // wait maximum between each chain sync request to peers
 pub const CHAIN_SYNC_DELAY: u64 = 3; // minimum X seconds between each chain sync request per peer
+pub const CHAIN_TIP_DISAGREEMENT_PERCENT: usize = 50; // warn if at least X% of the peers at our height report another top hash
+pub const CHAIN_SYNC_STALL_TIMEOUT: u64 = 30; // time in seconds without any new block before switching to another sync peer
 pub const CHAIN_SYNC_REQUEST_MAX_BLOCKS: usize = 64; // allows up to X blocks id (hash + height) 
 pub const P2P_PING_DELAY: u64 = 10; // time between each ping
 pub const P2P_PING_PEER_LIST_DELAY: u64 = 15; // time in seconds between each update of peerlist
 pub const P2P_PING_PEER_LIST_LIMIT: usize = 16; // maximum number of addresses to be send
+pub const P2P_PEER_EXCHANGE_LIMIT: usize = 64; // maximum number of addresses in a peer exchange packet
+pub const P2P_INVENTORY_MAX_SIZE: usize = 512; // maximum number of tx hashes in an inventory packet
+pub const P2P_DEFAULT_ANNOUNCEMENT_DELAY: u64 = 0; // default millis new tx hashes are batched before being announced (0 to announce them immediately)
 pub const P2P_DEFAULT_MAX_PEERS: usize = 32; // default number of maximum peers
-pub const PEER_TIMEOUT_REQUEST_OBJECT: u64 = 1500; // millis until we timeout
\ No newline at end of file
+pub const P2P_HEIGHT_OUTLIER_THRESHOLD: u64 = 1000; // peer heights above the median of all peers by more than X blocks are ignored
+pub const P2P_DEFAULT_MAX_PEERS_PER_SUBNET: usize = 2; // default number of maximum outgoing peers in the same subnet (/16 for IPv4, /32 for IPv6)
+pub const P2P_DEFAULT_IDLE_TIMEOUT: u64 = P2P_PING_DELAY * 6; // time in seconds without any packet received before dropping a peer
+pub const P2P_PRIORITY_NODES_RETRY_DELAY: u64 = 5; // time in seconds between each reconnection attempt to a disconnected priority node
+pub const P2P_DEFAULT_MAX_OUTGOING_HANDSHAKES: usize = 8; // default number of outgoing handshakes in progress at the same time
+pub const P2P_DEFAULT_MAX_REQUESTS_PER_PEER: usize = 4; // default number of object requests in flight per peer during a sync
+pub const P2P_DEFAULT_MAX_DOWNLOAD_BYTES: usize = 64 * 1024 * 1024; // default memory in bytes for the blocks downloaded during a sync and not yet added to the chain
+pub const P2P_KNOWN_ADDRESSES_LIMIT: usize = 1024; // maximum addresses shared by peers kept to be dialed later
+pub const P2P_KNOWN_ADDRESSES_DIAL_DELAY: u64 = 10; // time in seconds between each attempt to dial known addresses when slots are available
+pub const P2P_DISCONNECT_HISTORY_SIZE: usize = 8; // disconnect reasons kept per address
+pub const P2P_DISCONNECT_MAX_ADDRESSES: usize = 1024; // maximum addresses with a disconnect history
+pub const P2P_UNKNOWN_PACKETS_LIMIT: u8 = 10; // packets with an unknown id ignored from a peer before disconnecting it
+pub const P2P_DISCONNECT_BACKOFF: u64 = 10; // time in seconds to wait before dialing again an address, per severity point of its recent disconnects
+pub const PEER_TIMEOUT_REQUEST_OBJECT: u64 = 1500; // millis until we timeout
+pub const PEER_OBJECT_PENDING_RETRY_DELAY: u64 = 250; // millis to wait before asking again an object that the peer is still downloading
+pub const PEER_OBJECT_PENDING_MAX_RETRIES: u8 = 3; // maximum retries on a pending object before giving up on this peer
+pub const P2P_BLOCKS_CHANNEL_CAPACITY: usize = 64; // maximum blocks kept for slow subscribers before they lag
+pub const P2P_REORGS_CHANNEL_CAPACITY: usize = 16; // maximum reorg events kept for slow subscribers before they lag
+pub const P2P_VIOLATIONS_CHANNEL_CAPACITY: usize = 64; // maximum peer violation events kept for slow subscribers before they lag
+pub const P2P_DEFAULT_HANDSHAKE_TIMEOUT: u64 = 5000; // millis until a new connection must have sent its handshake
+pub const P2P_PROXY_CONNECT_TIMEOUT: u64 = 10000; // millis allowed to open a connection through the proxy, Tor circuits are slow to build
+pub const P2P_LOG_PACKET_BYTES_LIMIT: usize = 256; // maximum bytes of an invalid packet written in logs
+pub const P2P_PACKET_SIZE_BUCKETS: usize = 16; // buckets of the packet size histograms, the first one is up to 64 bytes and each next one doubles it
\ No newline at end of file
@@ -0,0 +1,202 @@
+use crate::crypto::hash::Hash;
+use super::reader::ReaderError;
+use tokio::io::{AsyncRead, AsyncReadExt};
+use std::future::Future;
+
+// Async counterpart of Serializer::read
+// parse an object while its bytes are arriving instead of buffering all of them first
+// implementations must read exactly the same bytes as Serializer::read
+pub trait AsyncSerializer: Sized {
+    fn read_async<R: AsyncRead + Unpin + Send>(reader: &mut AsyncReader<R>) -> impl Future<Output = Result<Self, ReaderError>> + Send;
+}
+
+// AsyncReader is the async version of Reader over any AsyncRead
+// a maximum size is required so a stream can't make us read forever
+pub struct AsyncReader<R: AsyncRead + Unpin + Send> {
+    inner: R,
+    max_size: usize, // maximum bytes allowed to be read
+    total: usize // total read bytes
+}
+
+impl<R: AsyncRead + Unpin + Send> AsyncReader<R> {
+    pub fn new(inner: R, max_size: usize) -> Self {
+        Self {
+            inner,
+            max_size,
+            total: 0
+        }
+    }
+
+    async fn read_exact(&mut self, buf: &mut [u8]) -> Result<(), ReaderError> {
+        if buf.len() > self.size() {
+            return Err(ReaderError::InvalidSize)
+        }
+
+        // unexpected end of stream is treated like a too short Reader
+        self.inner.read_exact(buf).await.map_err(|_| ReaderError::InvalidSize)?;
+        self.total += buf.len();
+        Ok(())
+    }
+
+    pub async fn read_bool(&mut self) -> Result<bool, ReaderError> {
+        Ok(self.read_u8().await? == 1)
+    }
+
+    pub async fn read_bytes(&mut self, n: usize) -> Result<Vec<u8>, ReaderError> {
+        let mut bytes = vec![0u8; n.min(self.size())];
+        if bytes.len() != n {
+            return Err(ReaderError::InvalidSize)
+        }
+        self.read_exact(&mut bytes).await?;
+        Ok(bytes)
+    }
+
+    pub async fn read_bytes_32(&mut self) -> Result<[u8; 32], ReaderError> {
+        let mut bytes = [0u8; 32];
+        self.read_exact(&mut bytes).await?;
+        Ok(bytes)
+    }
+
+    pub async fn read_hash(&mut self) -> Result<Hash, ReaderError> {
+        Ok(Hash::new(self.read_bytes_32().await?))
+    }
+
+    pub async fn read_u8(&mut self) -> Result<u8, ReaderError> {
+        let mut bytes = [0u8; 1];
+        self.read_exact(&mut bytes).await?;
+        Ok(bytes[0])
+    }
+
+    pub async fn read_u16(&mut self) -> Result<u16, ReaderError> {
+        let mut bytes = [0u8; 2];
+        self.read_exact(&mut bytes).await?;
+        Ok(u16::from_be_bytes(bytes))
+    }
+
+    pub async fn read_u32(&mut self) -> Result<u32, ReaderError> {
+        let mut bytes = [0u8; 4];
+        self.read_exact(&mut bytes).await?;
+        Ok(u32::from_be_bytes(bytes))
+    }
+
+    pub async fn read_u64(&mut self) -> Result<u64, ReaderError> {
+        let mut bytes = [0u8; 8];
+        self.read_exact(&mut bytes).await?;
+        Ok(u64::from_be_bytes(bytes))
+    }
+
+    pub async fn read_u128(&mut self) -> Result<u128, ReaderError> {
+        let mut bytes = [0u8; 16];
+        self.read_exact(&mut bytes).await?;
+        Ok(u128::from_be_bytes(bytes))
+    }
+
+    pub async fn read_varint(&mut self) -> Result<u64, ReaderError> {
+        let mut value: u64 = 0;
+        for i in 0..10 {
+            let byte = self.read_u8().await?;
+            if i == 9 && byte > 1 {
+                return Err(ReaderError::InvalidValue)
+            }
+            value |= ((byte & 0x7F) as u64) << (i * 7);
+            if byte & 0x80 == 0 {
+                return Ok(value)
+            }
+        }
+        Err(ReaderError::InvalidValue)
+    }
+
+    pub async fn read_var_bytes(&mut self, max: usize) -> Result<Vec<u8>, ReaderError> {
+        let len = self.read_varint().await?;
+        if len > max as u64 {
+            return Err(ReaderError::InvalidSize)
+        }
+        self.read_bytes(len as usize).await
+    }
+
+    pub async fn read_string_with_size(&mut self, size: usize) -> Result<String, ReaderError> {
+        let bytes = self.read_bytes(size).await?;
+        String::from_utf8(bytes).map_err(|_| ReaderError::InvalidValue)
+    }
+
+    pub async fn read_string(&mut self) -> Result<String, ReaderError> {
+        let size = self.read_u8().await?;
+        self.read_string_with_size(size as usize).await
+    }
+
+    // same as read_string but the size is checked before reading the string
+    pub async fn read_string_max(&mut self, max: usize) -> Result<String, ReaderError> {
+        let size = self.read_u8().await? as usize;
+        if size > max {
+            return Err(ReaderError::InvalidSize)
+        }
+        self.read_string_with_size(size).await
+    }
+
+    // bytes left before reaching the maximum size
+    pub fn size(&self) -> usize {
+        self.max_size - self.total
+    }
+
+    pub fn total_read(&self) -> usize {
+        self.total
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::block::{Block, CompleteBlock};
+    use crate::core::reader::Reader;
+    use crate::core::serializer::Serializer;
+    use crate::core::transaction::{SmartContractTx, Transaction, TransactionData, TransactionVariant, Tx};
+    use crate::crypto::hash::Hashable;
+    use crate::crypto::key::KeyPair;
+    use std::collections::BTreeMap;
+
+    // block with a tx of each format, so both readers go through all of them
+    fn create_block() -> CompleteBlock {
+        let owner = KeyPair::new().get_public_key().clone();
+        let transfer = Transaction::new(owner.clone(), TransactionVariant::Normal { nonce: 1, fee: 1000, data: TransactionData::Normal(vec![Tx { amount: 10, to: owner.clone() }]) });
+        let mut params = BTreeMap::new();
+        params.insert("a".to_owned(), "1".to_owned());
+        params.insert("b".to_owned(), "2".to_owned());
+        let mut extended = Transaction::new(owner.clone(), TransactionVariant::Normal { nonce: 2, fee: 1000, data: TransactionData::SmartContract(SmartContractTx { contract: "test".to_owned(), amount: 5, params }) });
+        extended.add_input(Hash::max(), 1).unwrap();
+        extended.set_expire_at_height(Some(100)).unwrap();
+        extended.set_data(Some(vec![1, 2, 3])).unwrap();
+        let registration = Transaction::new(owner.clone(), TransactionVariant::Registration);
+
+        let txs = vec![transfer, extended, registration];
+        let hashes = txs.iter().map(|tx| tx.hash()).collect();
+        let block = Block::new(2, 1000, Hash::zero(), [1u8; 32], Transaction::new(owner, TransactionVariant::Coinbase), hashes);
+        CompleteBlock::new(block, 42, txs)
+    }
+
+    #[tokio::test]
+    async fn test_read_async_matches_read() {
+        let bytes = create_block().to_bytes();
+        let block = CompleteBlock::read(&mut Reader::new(&bytes)).unwrap();
+
+        let mut reader = AsyncReader::new(&bytes[..], bytes.len());
+        let read = CompleteBlock::read_async(&mut reader).await.unwrap();
+        assert_eq!(reader.total_read(), bytes.len());
+        assert_eq!(read.hash(), block.hash());
+        assert_eq!(read.to_bytes(), bytes);
+    }
+
+    #[tokio::test]
+    async fn test_read_async_bounded() {
+        let bytes = create_block().to_bytes();
+        // stream ending too early
+        let mut reader = AsyncReader::new(&bytes[..bytes.len() - 1], bytes.len());
+        assert!(matches!(CompleteBlock::read_async(&mut reader).await, Err(ReaderError::InvalidSize)));
+        // more bytes than allowed
+        let mut reader = AsyncReader::new(&bytes[..], bytes.len() - 1);
+        assert!(matches!(CompleteBlock::read_async(&mut reader).await, Err(ReaderError::InvalidSize)));
+    }
+}
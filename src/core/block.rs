@@ -1,9 +1,15 @@
 use crate::crypto::hash::{Hash, Hashable, hash};
 use crate::crypto::key::PublicKey;
+use crate::globals::get_current_timestamp;
 use super::transaction::Transaction;
 use super::serializer::Serializer;
+use super::async_serializer::{AsyncSerializer, AsyncReader};
 use super::reader::{Reader, ReaderError};
 use super::writer::Writer;
+use super::difficulty::check_difficulty;
+use super::error::BlockchainError;
+use tokio::io::AsyncRead;
+use std::sync::atomic::{AtomicBool, Ordering};
 
 const EXTRA_NONCE_SIZE: usize = 32;
 const BLOCK_WORK_SIZE: usize = 160;
@@ -41,6 +47,37 @@ impl Block {
         }
     }
 
+    // search a nonce giving a hash which meets the difficulty, the extra nonce is incremented when the nonce wraps
+    // returns None once max_iterations are done or stop is set, so the caller can refresh the template
+    pub fn mine(&mut self, difficulty: u64, max_iterations: u64, stop: &AtomicBool) -> Result<Option<Hash>, BlockchainError> {
+        for _ in 0..max_iterations {
+            if stop.load(Ordering::Relaxed) {
+                break;
+            }
+
+            let hash = self.hash();
+            if check_difficulty(&hash, difficulty)? {
+                return Ok(Some(hash))
+            }
+
+            self.nonce = self.nonce.wrapping_add(1);
+            if self.nonce == 0 {
+                self.increment_extra_nonce();
+            }
+            self.timestamp = get_current_timestamp();
+        }
+        Ok(None)
+    }
+
+    fn increment_extra_nonce(&mut self) {
+        for byte in self.extra_nonce.iter_mut() {
+            *byte = byte.wrapping_add(1);
+            if *byte != 0 {
+                break;
+            }
+        }
+    }
+
     pub fn get_txs_hash(&self) -> Hash {
         let mut bytes = vec![];
 
@@ -83,6 +120,16 @@ impl CompleteBlock {
         }
     }
 
+    // mine the block at its own difficulty, see Block::mine
+    pub fn mine(&mut self, max_iterations: u64, stop: &AtomicBool) -> Result<Option<Hash>, BlockchainError> {
+        self.block.mine(self.difficulty, max_iterations, stop)
+    }
+
+    // block without its transactions, as sent in a block propagation
+    pub fn get_header(&self) -> &Block {
+        &self.block
+    }
+
     pub fn get_height(&self) -> u64 {
         self.block.height
     }
@@ -123,6 +170,11 @@ impl CompleteBlock {
         self.transactions.len()
     }
 
+    // a block with an extended tx can't be sent to a peer at protocol version 0
+    pub fn has_extended_transactions(&self) -> bool {
+        self.transactions.iter().any(|tx| tx.is_extended())
+    }
+
     pub fn get_transactions(&self) -> &Vec<Transaction> {
         &self.transactions
     }
@@ -175,6 +227,34 @@ impl Hashable for Block {
     }
 }
 
+impl AsyncSerializer for Block {
+    async fn read_async<R: AsyncRead + Unpin + Send>(reader: &mut AsyncReader<R>) -> Result<Self, ReaderError> {
+        let height = reader.read_u64().await?;
+        let timestamp = reader.read_u128().await?;
+        let previous_hash = reader.read_hash().await?;
+        let nonce = reader.read_u64().await?;
+        let extra_nonce: [u8; 32] = reader.read_bytes_32().await?;
+        let txs_count = reader.read_u16().await?;
+        let mut txs_hashes = vec![];
+        for _ in 0..txs_count {
+            txs_hashes.push(reader.read_hash().await?);
+        }
+        let miner_tx = Transaction::read_async(reader).await?;
+
+        Ok(
+            Block {
+                extra_nonce,
+                height,
+                timestamp,
+                previous_hash,
+                miner_tx,
+                nonce,
+                txs_hashes
+            }
+        )
+    }
+}
+
 impl Serializer for CompleteBlock {
     fn write(&self, writer: &mut Writer) {
         self.block.write(writer);
@@ -197,6 +277,20 @@ impl Serializer for CompleteBlock {
     }
 }
 
+impl AsyncSerializer for CompleteBlock {
+    async fn read_async<R: AsyncRead + Unpin + Send>(reader: &mut AsyncReader<R>) -> Result<Self, ReaderError> {
+        let block = Block::read_async(reader).await?;
+        let difficulty = reader.read_u64().await?;
+        let mut txs: Vec<Transaction> = Vec::new();
+        for _ in 0..block.get_txs_count() {
+            let tx = Transaction::read_async(reader).await?;
+            txs.push(tx);
+        }
+
+        Ok(CompleteBlock::new(block, difficulty, txs))
+    }
+}
+
 impl Hashable for CompleteBlock {
     fn hash(&self) -> Hash {
         self.block.hash()
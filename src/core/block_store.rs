@@ -0,0 +1,157 @@
+use crate::crypto::hash::{Hash, Hashable, hash};
+use super::error::BlockchainError;
+use super::reader::{Reader, ReaderError};
+use super::serializer::Serializer;
+use super::block::CompleteBlock;
+use super::writer::Writer;
+use std::collections::HashMap;
+
+// Each block is stored as a record with the following layout:
+// magic (4 bytes) | version (1 byte) | body length (4 bytes) | body | checksum (4 bytes)
+// the checksum is the first 4 bytes of the hash of the body
+pub const BLOCK_RECORD_MAGIC: [u8; 4] = *b"XELB";
+pub const BLOCK_RECORD_VERSION: u8 = 1;
+const CHECKSUM_SIZE: usize = 4;
+
+const BLOCK_KEY_PREFIX: u8 = b'b'; // hash -> record
+const HEIGHT_KEY_PREFIX: u8 = b'h'; // height -> hash
+
+// Minimal interface of an embedded key-value store
+// so the storage engine used by BlockStore can be swapped easily
+pub trait KeyValueStore {
+    fn put(&mut self, key: Vec<u8>, value: Vec<u8>) -> Result<(), BlockchainError>;
+
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, BlockchainError>;
+
+    fn contains(&self, key: &[u8]) -> Result<bool, BlockchainError> {
+        Ok(self.get(key)?.is_some())
+    }
+}
+
+// In-memory implementation of KeyValueStore
+#[derive(Default)]
+pub struct MemoryStore {
+    data: HashMap<Vec<u8>, Vec<u8>>
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self {
+            data: HashMap::new()
+        }
+    }
+}
+
+impl KeyValueStore for MemoryStore {
+    fn put(&mut self, key: Vec<u8>, value: Vec<u8>) -> Result<(), BlockchainError> {
+        self.data.insert(key, value);
+        Ok(())
+    }
+
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, BlockchainError> {
+        Ok(self.data.get(key).cloned())
+    }
+}
+
+pub struct BlockStore<S: KeyValueStore> {
+    store: S
+}
+
+impl<S: KeyValueStore> BlockStore<S> {
+    pub fn new(store: S) -> Self {
+        Self {
+            store
+        }
+    }
+
+    // store the block and index it by its height, returns its hash
+    pub fn put_block(&mut self, block: &CompleteBlock) -> Result<Hash, BlockchainError> {
+        let hash = block.hash();
+        let record = encode_record(&block.to_bytes());
+        self.store.put(block_key(&hash), record)?;
+        self.store.put(height_key(block.get_height()), hash.as_bytes().to_vec())?;
+        Ok(hash)
+    }
+
+    pub fn has_block(&self, hash: &Hash) -> Result<bool, BlockchainError> {
+        self.store.contains(&block_key(hash))
+    }
+
+    pub fn get_block_by_hash(&self, hash: &Hash) -> Result<CompleteBlock, BlockchainError> {
+        let record = self.store.get(&block_key(hash))?.ok_or_else(|| BlockchainError::BlockNotFound(hash.clone()))?;
+        let body = decode_record(&record)?;
+        let mut reader = Reader::new(&body);
+        let block = CompleteBlock::read(&mut reader)?;
+        if reader.size() != 0 {
+            return Err(BlockchainError::CorruptedBlockRecord)
+        }
+        Ok(block)
+    }
+
+    pub fn get_block_by_height(&self, height: u64) -> Result<CompleteBlock, BlockchainError> {
+        let bytes = self.store.get(&height_key(height))?.ok_or(BlockchainError::BlockHeightNotFound(height))?;
+        let mut reader = Reader::new(&bytes);
+        let hash = reader.read_hash()?;
+        self.get_block_by_hash(&hash)
+    }
+
+    pub fn get_store(&self) -> &S {
+        &self.store
+    }
+}
+
+fn block_key(hash: &Hash) -> Vec<u8> {
+    let mut key = Vec::with_capacity(1 + 32);
+    key.push(BLOCK_KEY_PREFIX);
+    key.extend(hash.as_bytes());
+    key
+}
+
+fn height_key(height: u64) -> Vec<u8> {
+    let mut key = Vec::with_capacity(1 + 8);
+    key.push(HEIGHT_KEY_PREFIX);
+    key.extend(height.to_be_bytes());
+    key
+}
+
+fn checksum(body: &[u8]) -> [u8; CHECKSUM_SIZE] {
+    let mut result = [0u8; CHECKSUM_SIZE];
+    result.copy_from_slice(&hash(body).as_bytes()[0..CHECKSUM_SIZE]);
+    result
+}
+
+pub fn encode_record(body: &[u8]) -> Vec<u8> {
+    let mut writer = Writer::new();
+    writer.write_bytes(&BLOCK_RECORD_MAGIC);
+    writer.write_u8(BLOCK_RECORD_VERSION);
+    writer.write_u32(&(body.len() as u32));
+    writer.write_bytes(body);
+    writer.write_bytes(&checksum(body));
+    writer.bytes()
+}
+
+// verify the record header & checksum and returns its body
+pub fn decode_record(record: &[u8]) -> Result<Vec<u8>, BlockchainError> {
+    let mut reader = Reader::new(record);
+    let magic: [u8; 4] = reader.read_bytes(4)?;
+    if magic != BLOCK_RECORD_MAGIC {
+        return Err(BlockchainError::InvalidRecordMagic)
+    }
+
+    let version = reader.read_u8()?;
+    if version != BLOCK_RECORD_VERSION {
+        return Err(BlockchainError::UnsupportedRecordVersion(version))
+    }
+
+    let len = reader.read_u32()? as usize;
+    if len + CHECKSUM_SIZE != reader.size() {
+        return Err(BlockchainError::ErrorOnReader(ReaderError::InvalidSize))
+    }
+
+    let body: Vec<u8> = reader.read_bytes(len)?;
+    let expected: [u8; CHECKSUM_SIZE] = reader.read_bytes(CHECKSUM_SIZE)?;
+    if checksum(&body) != expected {
+        return Err(BlockchainError::CorruptedBlockRecord)
+    }
+    Ok(body)
+}
@@ -1,21 +1,29 @@
-use crate::config::{DEFAULT_P2P_BIND_ADDRESS, P2P_DEFAULT_MAX_PEERS, DEFAULT_RPC_BIND_ADDRESS, MAX_BLOCK_SIZE, EMISSION_SPEED_FACTOR, FEE_PER_KB, MAX_SUPPLY, REGISTRATION_DIFFICULTY, DEV_FEE_PERCENT, MINIMUM_DIFFICULTY, GENESIS_BLOCK, DEV_ADDRESS};
+use crate::config::{DEFAULT_P2P_BIND_ADDRESS, P2P_DEFAULT_MAX_PEERS, P2P_DEFAULT_MAX_OUTGOING_HANDSHAKES, P2P_DEFAULT_HANDSHAKE_TIMEOUT, P2P_DEFAULT_IDLE_TIMEOUT, P2P_DEFAULT_MAX_PEERS_PER_SUBNET, P2P_DEFAULT_MAX_REQUESTS_PER_PEER, P2P_DEFAULT_MAX_DOWNLOAD_BYTES, P2P_DEFAULT_ANNOUNCEMENT_DELAY, DEFAULT_RPC_BIND_ADDRESS, MAX_BLOCK_SIZE, EMISSION_SPEED_FACTOR, FEE_PER_KB, DEFAULT_RBF_MIN_FEE_INCREMENT, DEFAULT_MIN_RELAY_FEE_PER_BYTE, DEFAULT_DUST_THRESHOLD, TIMESTAMP_FUTURE_TOLERANCE, MEDIAN_TIME_PAST_WINDOW, MAX_TRANSACTION_INPUTS, DEFAULT_SIGNATURE_CACHE_SIZE, MAX_SUPPLY, REGISTRATION_DIFFICULTY, DEV_FEE_PERCENT, DEV_ADDRESS, IMPORT_PROGRESS_INTERVAL, MINING_ITERATIONS, DEFAULT_MAX_REORG_DEPTH};
 use crate::crypto::address::Address;
-use crate::crypto::hash::{Hash, Hashable};
+use crate::crypto::hash::{Hash, Hashable, HASH_SIZE};
 use crate::globals::get_current_timestamp;
 use crate::crypto::key::PublicKey;
-use crate::p2p::server::P2pServer;
+use crate::crypto::signature_cache::SignatureCache;
+use crate::p2p::server::{P2pServer, P2pConfig};
+use crate::p2p::error::P2pError;
+use crate::p2p::packet::object::ObjectRequest;
 use crate::rpc::RpcServer;
 use super::difficulty::{check_difficulty, calculate_difficulty};
 use super::block::{Block, CompleteBlock};
 use super::mempool::{Mempool, SortedTx};
+use super::fee_policy::SizeFeePolicy;
 use super::error::BlockchainError;
 use super::serializer::Serializer;
+use super::reader::Reader;
 use super::storage::Storage;
+use super::snapshot::StateSnapshot;
+use super::network::Network;
 use super::transaction::*;
 use std::net::SocketAddr;
-use std::sync::atomic::{Ordering, AtomicU64};
+use std::sync::atomic::{Ordering, AtomicU64, AtomicBool};
 use tokio::sync::Mutex;
-use std::collections::HashMap;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
 use log::{info, error, debug};
 use rand::Rng;
@@ -48,18 +56,74 @@ pub struct Config {
     /// Optional node tag
     #[clap(short, long)]
     tag: Option<String>,
-    /// P2p bind address to listen for incoming connections
-    #[clap(short, long, default_value_t = String::from(DEFAULT_P2P_BIND_ADDRESS))]
-    p2p_bind_address: String,
+    /// P2p bind address to listen for incoming connections, can be set several times (dual-stack)
+    #[clap(short, long, default_value = DEFAULT_P2P_BIND_ADDRESS)]
+    p2p_bind_address: Vec<String>,
     /// Number of maximums peers allowed
     #[clap(short, long, default_value_t = P2P_DEFAULT_MAX_PEERS)]
     max_peers: usize,
+    /// Number of maximum outgoing handshakes in progress at the same time
+    #[clap(long, default_value_t = P2P_DEFAULT_MAX_OUTGOING_HANDSHAKES)]
+    max_outgoing_handshakes: usize,
+    /// Time in milliseconds allowed for a new connection to send its handshake
+    #[clap(long, default_value_t = P2P_DEFAULT_HANDSHAKE_TIMEOUT)]
+    handshake_timeout: u64,
+    /// Time in seconds without any packet received from a peer before disconnecting it
+    #[clap(long, default_value_t = P2P_DEFAULT_IDLE_TIMEOUT)]
+    idle_timeout: u64,
+    /// Number of maximum outgoing peers in the same subnet (/16 for IPv4, /32 for IPv6)
+    #[clap(long, default_value_t = P2P_DEFAULT_MAX_PEERS_PER_SUBNET)]
+    max_peers_per_subnet: usize,
+    /// Number of maximum object requests in flight per peer while syncing
+    #[clap(long, default_value_t = P2P_DEFAULT_MAX_REQUESTS_PER_PEER)]
+    max_requests_per_peer: usize,
+    /// Maximum memory in bytes used by the blocks downloaded during a sync and not yet added to the chain
+    /// Blocks in excess are requested again on the next sync round
+    #[clap(long, default_value_t = P2P_DEFAULT_MAX_DOWNLOAD_BYTES)]
+    max_download_bytes: usize,
+    /// Log in debug the raw bytes of invalid packets received (disabled by default as it exposes network traffic)
+    #[clap(long)]
+    log_packet_bytes: bool,
+    /// Prefix each packet with a sequence number to detect dropped or reordered packets, only used with peers supporting it
+    #[clap(long)]
+    packet_sequence: bool,
+    /// Time in milliseconds new transactions are batched before being announced to peers (0 to announce them immediately)
+    #[clap(long, default_value_t = P2P_DEFAULT_ANNOUNCEMENT_DELAY)]
+    announcement_delay: u64,
+    /// Only accept incoming peers and never dial out, except to the priority nodes
+    #[clap(long)]
+    no_outbound: bool,
+    /// SOCKS5 proxy (ip:port) used for all outgoing connections, for example a local Tor client
+    #[clap(long)]
+    proxy: Option<String>,
+    /// Minimum fee increase in percent for a transaction to replace conflicting ones in mempool
+    #[clap(long, default_value_t = DEFAULT_RBF_MIN_FEE_INCREMENT)]
+    rbf_min_fee_increment: u64,
+    /// Minimum fee per byte for a transaction to be accepted in mempool and relayed to peers
+    #[clap(long, default_value_t = DEFAULT_MIN_RELAY_FEE_PER_BYTE)]
+    min_relay_fee_per_byte: u64,
+    /// Minimum amount of a transaction output to be accepted in mempool and relayed to peers
+    #[clap(long, default_value_t = DEFAULT_DUST_THRESHOLD)]
+    dust_threshold: u64,
+    /// Number of threads used to verify the transactions signatures of a block (0 for all cores, 1 to disable parallelism)
+    /// A low value leaves cores to other services on the host but slows down the sync
+    #[clap(long, default_value_t = 0)]
+    verification_threads: usize,
+    /// Maximum number of blocks rewinded to switch to another chain, a deeper reorg is refused as a likely attack
+    #[clap(long, default_value_t = DEFAULT_MAX_REORG_DEPTH)]
+    max_reorg_depth: u64,
+    /// Number of signature verification results kept in cache (0 to disable it)
+    #[clap(long, default_value_t = DEFAULT_SIGNATURE_CACHE_SIZE)]
+    signature_cache_size: usize,
     /// Rpc bind address to listen for HTTP requests
     #[clap(short, long, default_value_t = String::from(DEFAULT_RPC_BIND_ADDRESS))]
     rpc_bind_address: String,
     /// Add a priority node to connect when P2p is started
     #[clap(short = 'n', long)]
     priority_nodes: Vec<String>,
+    /// Import blocks from a file exported by another node before starting the P2p server
+    #[clap(long)]
+    import_blocks: Option<String>,
 }
 
 pub struct Blockchain {
@@ -71,31 +135,54 @@ pub struct Blockchain {
     storage: Mutex<Storage>, // storage to retrieve/add blocks
     p2p: Mutex<Option<Arc<P2pServer>>>, // P2p module
     rpc: Mutex<Option<Arc<RpcServer>>>, // Rpc module
-    dev_address: PublicKey // Dev address for block fee
+    dev_address: PublicKey, // Dev address for block fee
+    verification_threads: usize, // threads used to verify the signatures of a block
+    signature_cache: Option<Arc<SignatureCache>>, // cache of signatures already verified
+    max_reorg_depth: u64, // maximum blocks rewinded to switch to the chain of a peer
+    network: Network // network parameters (checkpoints)
 }
 
 impl Blockchain {
     pub async fn new(config: Config) -> Result<Arc<Self>, BlockchainError> {
+        Self::with_network(config, Network::mainnet()?).await
+    }
+
+    // start the node on a specific network (mainnet, regtest...)
+    pub async fn with_network(config: Config, network: Network) -> Result<Arc<Self>, BlockchainError> {
         let dev_address = Address::from_string(&DEV_ADDRESS.to_owned())?;
         let blockchain = Self {
             height: AtomicU64::new(0),
             supply: AtomicU64::new(0),
             burned: AtomicU64::new(0),
-            difficulty: AtomicU64::new(MINIMUM_DIFFICULTY),
-            mempool: Mutex::new(Mempool::new()),
+            difficulty: AtomicU64::new(network.get_minimum_difficulty()),
+            mempool: Mutex::new(Mempool::new(config.rbf_min_fee_increment, config.dust_threshold, Box::new(SizeFeePolicy::new(config.min_relay_fee_per_byte)))),
+            network,
             storage: Mutex::new(Storage::new()),
             p2p: Mutex::new(None),
             rpc: Mutex::new(None),
-            dev_address: dev_address.to_public_key()
+            dev_address: dev_address.to_public_key(),
+            verification_threads: if config.verification_threads == 0 { num_cpus::get() } else { config.verification_threads },
+            signature_cache: if config.signature_cache_size == 0 { None } else { Some(Arc::new(SignatureCache::new(config.signature_cache_size))) },
+            max_reorg_depth: config.max_reorg_depth
         };
+        debug!("Using {} threads to verify blocks", blockchain.get_verification_threads());
+        debug!("Maximum reorg depth: {} blocks", blockchain.get_max_reorg_depth());
+        debug!("Using the {} fee policy", blockchain.mempool.lock().await.get_fee_policy().get_name());
         // TODO Read blockchain from disk if exists
         // include genesis block
         blockchain.create_genesis_block().await?;
 
+        if let Some(path) = &config.import_blocks {
+            info!("Importing blocks from {}", path);
+            let file = tokio::fs::File::open(path).await?;
+            let count = blockchain.import_blocks_from(BufReader::new(file)).await?;
+            info!("{} blocks imported, height is now {}", count, blockchain.get_height());
+        }
+
         let arc = Arc::new(blockchain);
         // create P2P Server
         {
-            let p2p = P2pServer::new(config.tag, config.max_peers, config.p2p_bind_address, Arc::clone(&arc))?;
+            let mut priority_nodes: Vec<SocketAddr> = Vec::with_capacity(config.priority_nodes.len());
             for addr in config.priority_nodes {
                 let addr: SocketAddr = match addr.parse() {
                     Ok(addr) => addr,
@@ -104,8 +191,32 @@ impl Blockchain {
                         continue;
                     }
                 };
-                p2p.try_to_connect_to_peer(addr, true);
+                priority_nodes.push(addr);
             }
+            // a bad proxy stops the node, dialing without it would leak our address
+            let proxy: Option<SocketAddr> = match &config.proxy {
+                Some(proxy) => Some(proxy.parse().map_err(P2pError::from)?),
+                None => None
+            };
+            // priority nodes are dialed by the P2p server before the seed nodes
+            let p2p_config = P2pConfig {
+                tag: config.tag,
+                max_peers: config.max_peers,
+                bind_addresses: config.p2p_bind_address,
+                max_outgoing_handshakes: config.max_outgoing_handshakes,
+                handshake_timeout: config.handshake_timeout,
+                idle_timeout: config.idle_timeout,
+                max_peers_per_subnet: config.max_peers_per_subnet,
+                max_requests_per_peer: config.max_requests_per_peer,
+                max_download_bytes: config.max_download_bytes,
+                log_packet_bytes: config.log_packet_bytes,
+                packet_sequence: config.packet_sequence,
+                announcement_delay: config.announcement_delay,
+                outbound_enabled: !config.no_outbound,
+                proxy,
+                priority_nodes
+            };
+            let p2p = P2pServer::new(p2p_config, Arc::clone(&arc))?;
             *arc.p2p.lock().await = Some(p2p);
         }
 
@@ -136,17 +247,16 @@ impl Blockchain {
         let mut storage = self.storage.lock().await;
         storage.register_account(self.dev_address.clone());
 
-        if GENESIS_BLOCK.len() != 0 {
-            info!("De-serializing genesis block...");
-            match CompleteBlock::from_hex(GENESIS_BLOCK.to_owned()) {
-                Ok(block) => {
-                    if *block.get_miner() != self.dev_address {
-                        return Err(BlockchainError::GenesisBlockMiner)
-                    }
-                    self.add_new_block_for_storage(&mut storage, block, true).await?;
-                },
-                Err(_) => return Err(BlockchainError::InvalidGenesisBlock)
+        if let Some(block) = self.network.get_genesis_block() {
+            info!("Loading genesis block...");
+            if *block.get_miner() != self.dev_address {
+                // only a private network can have its genesis mined by someone else
+                if self.network.is_mainnet() {
+                    return Err(BlockchainError::GenesisBlockMiner)
+                }
+                storage.register_account(block.get_miner().clone());
             }
+            self.add_new_block_for_storage(&mut storage, block.clone(), true).await?;
         } else {
             error!("No genesis block found...");
             info!("Generating a new genesis block...");
@@ -168,20 +278,18 @@ impl Blockchain {
 
     // mine a block for current difficulty
     pub async fn mine_block(self: &Arc<Self>, key: &PublicKey) -> Result<(), BlockchainError> {
-        let mut block = self.get_block_template(key.clone()).await?;
-        let mut hash = block.hash();
-        let mut current_height = self.get_height();
-        while !check_difficulty(&hash, self.get_difficulty())? {
-            if self.get_height() != current_height {
-                current_height = self.get_height();
-                block = self.get_block_template(key.clone()).await?;
+        let stop = AtomicBool::new(false);
+        let mut complete_block = self.build_block_template(key).await?;
+        let hash = loop {
+            if let Some(hash) = complete_block.mine(MINING_ITERATIONS, &stop)? {
+                break hash;
             }
-            block.nonce += 1;
-            block.timestamp = get_current_timestamp();
-            hash = block.hash();
-        }
+            // a new block was added meanwhile, mine on top of it
+            if self.get_height() >= complete_block.get_height() {
+                complete_block = self.build_block_template(key).await?;
+            }
+        };
 
-        let complete_block = self.build_complete_block_from_block(block).await?;
         let zelf = Arc::clone(self);
         let block_height = complete_block.get_height();
         zelf.add_new_block(complete_block, true).await?;
@@ -213,14 +321,64 @@ impl Blockchain {
         &self.dev_address
     }
 
+    pub async fn median_time_past(&self, window: usize) -> u128 {
+        self.storage.lock().await.median_time_past(window)
+    }
+
+    // check if we already have this block, or this tx in mempool or in a block
+    // each lock is released before taking the next one
+    pub async fn has_object(&self, request: &ObjectRequest) -> bool {
+        match request {
+            ObjectRequest::Block(hash) => self.storage.lock().await.has_block(hash),
+            ObjectRequest::Transaction(hash) => {
+                if self.mempool.lock().await.contains_tx(hash) {
+                    return true
+                }
+                self.storage.lock().await.has_transaction(hash)
+            }
+        }
+    }
+
+    // bound of the threads spawned to verify the signatures of a block
+    pub fn get_verification_threads(&self) -> usize {
+        self.verification_threads
+    }
+
+    pub fn get_max_reorg_depth(&self) -> u64 {
+        self.max_reorg_depth
+    }
+
+    pub fn get_signature_cache(&self) -> Option<&SignatureCache> {
+        self.signature_cache.as_deref()
+    }
+
+    pub fn get_network(&self) -> &Network {
+        &self.network
+    }
+
     pub fn get_storage(&self) -> &Mutex<Storage> {
         &self.storage
     }
 
+    // None only before the genesis block is added at startup
+    // a chain bootstrapped from a snapshot doesn't store it anymore, it's the one of the network
+    pub async fn get_genesis_hash(&self) -> Option<Hash> {
+        match self.storage.lock().await.get_block_at_height(1) {
+            Ok(block) => Some(block.hash()),
+            Err(_) => self.network.get_genesis_block().map(|block| block.hash())
+        }
+    }
+
     pub async fn get_top_block_hash(&self) -> Hash {
         self.storage.lock().await.get_top_block_hash().clone()
     }
 
+    // current height and top block hash, both read under the storage lock so they always match
+    pub async fn get_chain_state(&self) -> (u64, Hash) {
+        let storage = self.storage.lock().await;
+        (self.get_height(), storage.get_top_block_hash().clone())
+    }
+
     pub fn get_mempool(&self) -> &Mutex<Mempool> {
         &self.mempool
     }
@@ -234,31 +392,74 @@ impl Blockchain {
 
         let fee = {
             let storage = self.storage.lock().await;
-            self.verify_transaction_with_hash(&storage, &tx, &hash, false)?
+            self.verify_transaction_with_hash(&storage, &tx, &hash, false, false)?
         };
+        // checked before the broadcast so a tx we don't keep is not relayed
+        mempool.verify_relay_fee(&tx, fee)?;
+        mempool.verify_dust(&tx, &hash)?;
+        mempool.verify_replacement(&tx, fee)?;
         if broadcast {
             if let Some(p2p) = self.p2p.lock().await.as_ref() {
-                p2p.broadcast_tx_hash(&hash).await;
+                p2p.broadcast_tx_hash(&hash, tx.is_extended()).await;
             }
         }
         mempool.add_tx_with_fee(hash, tx, fee)
     }
 
     pub async fn get_block_template(&self, address: PublicKey) -> Result<Block, BlockchainError> {
-        let coinbase_tx = Transaction::new(address, TransactionVariant::Coinbase);
+        let (block, _) = self.select_block_template(&address).await?;
+        Ok(block)
+    }
+
+    // block on top of the current tip with the best mempool txs and a coinbase paying the miner
+    // only the nonce has to be iterated over to mine it
+    pub async fn build_block_template(&self, address: &PublicKey) -> Result<CompleteBlock, BlockchainError> {
+        let (block, transactions) = self.select_block_template(address).await?;
+        Ok(CompleteBlock::new(block, self.get_difficulty(), transactions))
+    }
+
+    // txs are picked by fee in the order of the mempool, the selection only depends on the mempool and chain state
+    async fn select_block_template(&self, address: &PublicKey) -> Result<(Block, Vec<Transaction>), BlockchainError> {
+        let (height, top_hash) = self.get_chain_state().await;
+        let coinbase_tx = Transaction::new(address.clone(), TransactionVariant::Coinbase);
         let extra_nonce: [u8; 32] = rand::thread_rng().gen::<[u8; 32]>(); // generate random bytes
-        let mut block = Block::new(self.get_height() + 1, get_current_timestamp(), self.get_top_block_hash().await, extra_nonce, coinbase_tx, Vec::new());
-        let mempool = self.mempool.lock().await;
-        let txs: &Vec<SortedTx> = mempool.get_sorted_txs();
-        let mut tx_size = 0;
-        for tx in txs {
-            tx_size += tx.get_size();
-            if block.size() + tx_size > MAX_BLOCK_SIZE {
-                break;
+        let mut block = Block::new(height + 1, get_current_timestamp(), top_hash, extra_nonce, coinbase_tx, Vec::new());
+        let candidates: Vec<(Hash, Transaction)> = {
+            let mempool = self.mempool.lock().await;
+            let txs: &Vec<SortedTx> = mempool.get_sorted_txs();
+            txs.iter().filter_map(|sorted| mempool.view_tx(sorted.get_hash()).ok().map(|tx| (sorted.get_hash().clone(), tx.clone()))).collect()
+        };
+
+        // all txs of a block are verified against the same state, so a sender can only have one tx in it
+        // and this tx must use the current nonce of its account, later ones will go in next blocks
+        let storage = self.storage.lock().await;
+        let mut senders: HashSet<PublicKey> = HashSet::new();
+        let mut transactions: Vec<Transaction> = Vec::new();
+        let mut block_size = block.size();
+        for (hash, tx) in candidates {
+            if senders.contains(tx.get_owner()) {
+                continue;
+            }
+
+            if let TransactionVariant::Normal { nonce, .. } = tx.get_variant() {
+                match storage.get_account(tx.get_owner()) {
+                    Ok(account) if account.nonce == *nonce => {},
+                    _ => continue
+                };
+            }
+
+            // a tx too big for the remaining space is skipped, a smaller one may still fit
+            // its hash is also stored in the block, and counted with the header size during validation
+            let tx_size = tx.size() + HASH_SIZE;
+            if block_size + tx_size > MAX_BLOCK_SIZE {
+                continue;
             }
-            block.txs_hashes.push(tx.get_hash().clone());
+            block_size += tx_size;
+            senders.insert(tx.get_owner().clone());
+            block.txs_hashes.push(hash);
+            transactions.push(tx);
         }
-        Ok(block)
+        Ok((block, transactions))
     }
 
     pub async fn build_complete_block_from_block(&self, block: Block) -> Result<CompleteBlock, BlockchainError> {
@@ -314,7 +515,7 @@ impl Blockchain {
             for tx in block.get_transactions() {
                 let tx_hash = tx.hash();
                 if !tx.is_coinbase() {
-                    self.verify_transaction_with_hash(&storage, tx, &tx_hash, true)?; // TODO check when account have no more funds
+                    self.verify_transaction_with_hash(&storage, tx, &tx_hash, true, false)?; // TODO check when account have no more funds
                 } else {
                     return Err(BlockchainError::InvalidTxInBlock(tx_hash))
                 }
@@ -354,8 +555,14 @@ impl Blockchain {
             return Err(BlockchainError::InvalidBlockHeight(current_height + 1, block.get_height()));
         } else if !check_difficulty(&block_hash, current_difficulty)? {
             return Err(BlockchainError::InvalidDifficulty);
-        } else if block.get_timestamp() > get_current_timestamp() { // TODO accept a latency of max 30s
+        } else if block.get_timestamp() > get_current_timestamp() + TIMESTAMP_FUTURE_TOLERANCE { // accept a latency of max 30s
             return Err(BlockchainError::TimestampIsInFuture(get_current_timestamp(), block.get_timestamp()));
+        } else if current_height == 0 && !block.get_previous_hash().is_zero() { // only the genesis block has no parent
+            return Err(BlockchainError::InvalidGenesisBlock);
+        } else if current_height != 0 && !storage.has_blocks() { // first block after the snapshot, its parent is only known by its hash
+            if *storage.get_top_block_hash() != *block.get_previous_hash() {
+                return Err(BlockchainError::InvalidPreviousBlockHash(block.get_previous_hash().clone(), storage.get_top_block_hash().clone()));
+            }
         } else if current_height != 0 { // if it's not the genesis block
             let previous_block = storage.get_block_at_height(current_height)?;
             let previous_hash = previous_block.hash();
@@ -365,9 +572,21 @@ impl Blockchain {
             if previous_block.get_timestamp() > block.get_timestamp() { // block timestamp can't be less than previous block.
                 return Err(BlockchainError::TimestampIsLessThanParent(block.get_timestamp()));
             }
+            // prevent timestamp manipulation to game the difficulty adjustment
+            let median_time_past = storage.median_time_past(MEDIAN_TIME_PAST_WINDOW);
+            if block.get_timestamp() <= median_time_past {
+                return Err(BlockchainError::TimestampIsBeforeMedianTimePast(median_time_past, block.get_timestamp()));
+            }
             debug!("Block Time for this block is: {:.2}s", (block.get_timestamp() - previous_block.get_timestamp()) as f64 / 1000f64);
         }
 
+        // a block at a checkpoint height must be the expected one
+        if let Some(expected) = self.network.get_checkpoint(block.get_height()) {
+            if *expected != block_hash {
+                return Err(BlockchainError::InvalidCheckpoint(block.get_height(), expected.clone(), block_hash))
+            }
+        }
+
         let mut total_fees: u64 = 0;
         let mut total_tx_size: usize = 0;
         { // Transaction verification
@@ -378,30 +597,47 @@ impl Blockchain {
             }
             let mut cache_tx: HashMap<Hash, bool> = HashMap::new(); // avoid using a TX multiple times
             let mut registrations: HashMap<&PublicKey, bool> = HashMap::new(); // avoid multiple registration of the same public key 
-            for tx in block.get_transactions() {
-                let tx_hash = tx.hash();
-                // block can't contains the same tx and should have tx hash in block header
+            let mut spent_inputs: HashMap<(&Hash, u32), Hash> = HashMap::new(); // avoid spending the same input in two txs
+            // the block hash commits the tx hashes in order, so the txs must be in the same order
+            // checked before any signature so a reordered block is rejected cheaply
+            let txs_hashes: Vec<Hash> = block.get_transactions().iter().map(|tx| tx.hash()).collect();
+            for (i, (tx_hash, expected)) in txs_hashes.iter().zip(block.get_txs_hashes()).enumerate() {
+                if tx_hash != expected {
+                    return Err(BlockchainError::InvalidTxOrder(i, expected.clone(), tx_hash.clone()))
+                }
+            }
+            // signatures are the most expensive part, verify them all at once before the sequential checks
+            verify_signatures(block.get_transactions(), self.verification_threads, self.signature_cache.as_ref()).await?;
+            for (tx, tx_hash) in block.get_transactions().iter().zip(txs_hashes) {
+                // block can't contains the same tx
                 if cache_tx.contains_key(&tx_hash) {
                     return Err(BlockchainError::TxAlreadyInBlock(tx_hash));
                 }
 
-                if !block.get_txs_hashes().contains(&tx_hash) {
-                    return Err(BlockchainError::InvalidTxInBlock(tx_hash))
-                }
-                let fee = self.verify_transaction_with_hash(&storage, tx, &tx_hash, false)?;
+                let fee = self.verify_transaction_with_hash(&storage, tx, &tx_hash, false, true)?;
                 if let TransactionVariant::Registration = tx.get_variant() { // prevent any duplicate registration
                     if registrations.contains_key(tx.get_owner()) {
-                        return Err(BlockchainError::DuplicateRegistration(tx.get_owner().clone()))
+                        return Err(BlockchainError::DuplicateRegistration(Box::new(tx.get_owner().clone())))
                     }
                     registrations.insert(tx.get_owner(), true);
                 }
-                total_fees += fee;
+                for (input_hash, index) in tx.get_inputs() {
+                    if let Some(conflict) = spent_inputs.get(&(input_hash, *index)) {
+                        return Err(BlockchainError::InputAlreadySpent(input_hash.clone(), *index, conflict.clone()))
+                    }
+                }
+                total_fees = total_fees.checked_add(fee).ok_or(BlockchainError::BlockFeesOverflow)?;
+                for (input_hash, index) in tx.get_inputs() {
+                    spent_inputs.insert((input_hash, *index), tx_hash.clone());
+                }
                 cache_tx.insert(tx_hash, true);
                 total_tx_size += tx.size();
             }
 
-            if block.size() + total_tx_size > MAX_BLOCK_SIZE {
-                return Err(BlockchainError::InvalidBlockSize(MAX_BLOCK_SIZE, block.size() + total_tx_size));
+            // the complete block size already includes the txs, only its header is added to them
+            let block_size = block.get_header().size() + total_tx_size;
+            if block_size > MAX_BLOCK_SIZE {
+                return Err(BlockchainError::InvalidBlockSize(MAX_BLOCK_SIZE, block_size));
             }
 
             if cache_tx.len() != block.get_transactions().len() || cache_tx.len() != block.get_txs_hashes().len() {
@@ -420,6 +656,11 @@ impl Blockchain {
             return Err(BlockchainError::InvalidTransactionSignature)
         }
 
+        // checked before any tx is executed so the miner reward can't wrap
+        if block_reward.checked_add(total_fees).is_none() {
+            return Err(BlockchainError::BlockFeesOverflow)
+        }
+
         // Transaction execution
         let mut mempool = self.mempool.lock().await;
         for hash in block.get_txs_hashes() { // remove all txs present in mempool
@@ -431,48 +672,149 @@ impl Blockchain {
             };
         }
 
+        // remove all txs in mempool spending an input now spent by this block
+        for tx in block.get_transactions() {
+            while let Some(conflict) = mempool.get_conflicting_input(tx).map(|(_, _, conflict)| conflict.clone()) {
+                debug!("Removing tx hash '{}' from mempool, its input is spent in block", conflict);
+                mempool.remove_tx(&conflict)?;
+            }
+        }
+
         for tx in block.get_transactions() { // execute all txs
             self.execute_transaction(storage, tx)?;
         }
         self.execute_miner_tx(storage, block.get_miner_tx(), block_reward, total_fees)?; // execute coinbase tx
 
-        if current_height > 2 { // re calculate difficulty
+        if current_height > 2 && storage.has_blocks() { // re calculate difficulty
             let top_block = storage.get_top_block()?;
-            let difficulty = calculate_difficulty(top_block, &block, current_difficulty);
+            let difficulty = calculate_difficulty(top_block, &block, current_difficulty, self.network.get_minimum_difficulty());
             self.difficulty.store(difficulty, Ordering::Relaxed);
         }
 
         self.height.store(block.get_height(), Ordering::Relaxed);
+        for hash in mempool.remove_expired_txs(block.get_height()) {
+            debug!("Removing expired tx hash '{}' from mempool", hash);
+        }
         self.supply.fetch_add(block_reward, Ordering::Relaxed);
         debug!("Adding new block '{}' with {} txs at height {}", block_hash, block.get_txs_count(), block.get_height());
-        if broadcast {
-            if let Some(p2p) = self.p2p.lock().await.as_ref() {
+        if let Some(p2p) = self.p2p.lock().await.as_ref() {
+            if broadcast {
                 debug!("broadcast block to peers");
                 p2p.broadcast_block(&block, &block_hash).await;
             }
+            p2p.notify_new_block(&block);
         }
 
         storage.add_new_block(block, block_hash); // Add block to chain
         Ok(())
     }
 
-    pub async fn rewind_chain(&self, count: usize) -> Result<(), BlockchainError> {
+    // write all blocks as records of a u32 length followed by the block, to be imported by import_blocks_from
+    pub async fn export_blocks_to<W: AsyncWrite + Unpin>(&self, mut writer: W) -> Result<u64, BlockchainError> {
+        let storage = self.storage.lock().await;
+        for block in storage.get_blocks() {
+            let bytes = block.to_bytes();
+            writer.write_all(&(bytes.len() as u32).to_be_bytes()).await?;
+            writer.write_all(&bytes).await?;
+        }
+        writer.flush().await?;
+        Ok(storage.get_blocks().len() as u64)
+    }
+
+    // read blocks one by one from the exported records and add them to the chain, returns the number of blocks added
+    // blocks already in our chain are skipped, so the genesis block or a partial import can be imported again
+    pub async fn import_blocks_from<R: AsyncRead + Unpin + Send>(&self, mut reader: R) -> Result<u64, BlockchainError> {
+        let mut record: u64 = 0;
+        let mut imported: u64 = 0;
+        loop {
+            // end of file is only valid before the size of a record
+            let mut size = [0u8; 4];
+            let read = reader.read(&mut size).await?;
+            if read == 0 {
+                break;
+            }
+            if read < size.len() {
+                reader.read_exact(&mut size[read..]).await.map_err(|_| BlockchainError::ImportTruncated(record))?;
+            }
+
+            let size = u32::from_be_bytes(size);
+            if size == 0 || size as usize > MAX_BLOCK_SIZE {
+                return Err(BlockchainError::InvalidImportRecordSize(size, record))
+            }
+
+            // only one block is kept in memory at a time
+            let mut bytes = vec![0u8; size as usize];
+            reader.read_exact(&mut bytes).await.map_err(|_| BlockchainError::ImportTruncated(record))?;
+            let mut block_reader = Reader::new(&bytes);
+            let block = CompleteBlock::read(&mut block_reader)?;
+            if block_reader.size() != 0 {
+                return Err(BlockchainError::InvalidImportRecordSize(size, record))
+            }
+
+            let mut storage = self.storage.lock().await;
+            if block.get_height() <= self.get_height() {
+                let hash = block.hash();
+                if !storage.has_block(&hash) {
+                    return Err(BlockchainError::BlockNotFound(hash))
+                }
+            } else {
+                self.add_new_block_for_storage(&mut storage, block, false).await?;
+                imported += 1;
+                if imported.is_multiple_of(IMPORT_PROGRESS_INTERVAL) {
+                    info!("{} blocks imported, current height: {}", imported, self.get_height());
+                }
+            }
+            record += 1;
+        }
+        Ok(imported)
+    }
+
+    // import the accounts of a snapshot whose root is trusted (from a checkpoint for example)
+    // a node with only its genesis block continues its chain from the snapshot block
+    pub async fn import_snapshot(&self, snapshot: StateSnapshot, expected_root: &Hash) -> Result<(), BlockchainError> {
+        let mut storage = self.storage.lock().await;
+        let height = snapshot.get_height();
+        storage.import_snapshot(snapshot, expected_root)?;
+        if storage.get_base_height() == height {
+            self.height.store(height, Ordering::Relaxed);
+            self.supply.store(get_supply_at_height(height), Ordering::Relaxed);
+        }
+        Ok(())
+    }
+
+    pub async fn rewind_chain(&self, count: usize) -> Result<Vec<Hash>, BlockchainError> {
         let mut storage = self.storage.lock().await;
         self.rewind_chain_for_storage(&mut storage, count).await
     }
 
+    // returns the hashes of the blocks removed, from the highest one
     // TODO missing burned supply, txs etc
-    pub async fn rewind_chain_for_storage(&self, storage: &mut Storage, count: usize) -> Result<(), BlockchainError> {
+    pub async fn rewind_chain_for_storage(&self, storage: &mut Storage, count: usize) -> Result<Vec<Hash>, BlockchainError> {
+        // never rewind below the last checkpoint
+        let checkpoint = self.network.get_last_checkpoint_height();
+        let height = self.get_height();
+        if height.saturating_sub(count as u64) < checkpoint {
+            return Err(BlockchainError::ReorgBelowCheckpoint(checkpoint))
+        }
+
+        let mut removed = Vec::with_capacity(count);
+        for i in 0..count as u64 {
+            if let Ok(block) = storage.get_block_at_height(height - i) {
+                removed.push(block.hash());
+            }
+        }
+
         let top_height = storage.pop_blocks(count)?;
         self.height.store(top_height, Ordering::Relaxed);
         self.supply.store(get_supply_at_height(top_height), Ordering::Relaxed); // recaculate supply
-        Ok(())
+        Ok(removed)
     }
 
     // verify the transaction and returns fees available
-    fn verify_transaction_with_hash(&self, storage: &Storage, tx: &Transaction, hash: &Hash, disable_nonce_check: bool) -> Result<u64, BlockchainError> {
+    // signature check can be disabled if it was already verified
+    fn verify_transaction_with_hash(&self, storage: &Storage, tx: &Transaction, hash: &Hash, disable_nonce_check: bool, disable_signature_check: bool) -> Result<u64, BlockchainError> {
         // check signature validity
-        if !tx.verify_signature()? {
+        if !disable_signature_check && !verify_tx_signature(tx, self.get_signature_cache())? {
             return Err(BlockchainError::InvalidTransactionSignature)
         }
 
@@ -483,7 +825,7 @@ impl Blockchain {
             TransactionVariant::Registration => {
                 // verify this address isn't already registered
                 if storage.has_account(tx.get_owner()) && !disable_nonce_check {
-                    return Err(BlockchainError::AddressAlreadyRegistered(tx.get_owner().clone()))
+                    return Err(BlockchainError::AddressAlreadyRegistered(Box::new(tx.get_owner().clone())))
                 }
                 
                 // check validity of registration mini POW
@@ -498,6 +840,34 @@ impl Blockchain {
                     return Err(BlockchainError::InvalidTxFee(calculted_fee, *fee))
                 }
 
+                // a tx expired can't be added in mempool or in a block
+                if let Some(expire) = tx.get_expire_at_height() {
+                    if tx.is_expired(self.get_height()) {
+                        return Err(BlockchainError::TxExpired(hash.clone(), expire))
+                    }
+                }
+
+                // a tx can't reference too many inputs or spend twice the same input
+                let inputs = tx.get_inputs();
+                if inputs.len() > MAX_TRANSACTION_INPUTS {
+                    return Err(BlockchainError::TooManyInputs(MAX_TRANSACTION_INPUTS, inputs.len()))
+                }
+                for (i, (input_hash, index)) in inputs.iter().enumerate() {
+                    if inputs[..i].iter().any(|(h, idx)| h == input_hash && idx == index) {
+                        return Err(BlockchainError::DuplicateInput(input_hash.clone(), *index, hash.clone()))
+                    }
+
+                    // a coinbase output is spendable only once it's deep enough to not be reorged
+                    // the tx will be included at the next height at the earliest
+                    if let Some(coinbase_height) = storage.get_coinbase_height(input_hash) {
+                        let confirmations = (self.get_height() + 1).saturating_sub(coinbase_height);
+                        let maturity = self.network.get_coinbase_maturity();
+                        if confirmations < maturity {
+                            return Err(BlockchainError::CoinbaseNotMature(input_hash.clone(), confirmations, maturity))
+                        }
+                    }
+                }
+
                 let account = storage.get_account(tx.get_owner())?;
                 if !disable_nonce_check && account.nonce != *nonce { // check valid nonce
                     return Err(BlockchainError::InvalidTransactionNonce(account.nonce, *nonce))
@@ -508,25 +878,27 @@ impl Blockchain {
                         if txs.len() == 0 { // don't accept any empty tx
                             return Err(BlockchainError::TxEmpty(hash.clone()))
                         }
+                        // a wrapped sum could make the tx look payable, so any overflow is rejected
                         let mut total_coins = *fee;
                         for output in txs {
-                            total_coins += output.amount;
+                            total_coins = total_coins.checked_add(output.amount).ok_or_else(|| BlockchainError::TxAmountOverflow(hash.clone()))?;
                             if output.to == *tx.get_owner() { // we can't transfer coins to ourself, why would you do that ?
                                 return Err(BlockchainError::InvalidTransactionToSender(hash.clone()))
                             }
         
                             if !storage.has_account(&output.to) { // verify that all receivers are registered
-                                return Err(BlockchainError::AddressNotRegistered(output.to.clone()))
+                                return Err(BlockchainError::AddressNotRegistered(Box::new(output.to.clone())))
                             }
                         }
         
                         if account.balance < total_coins { // verify that the user have enough funds
-                            return Err(BlockchainError::NotEnoughFunds(tx.get_owner().clone(), total_coins))
+                            return Err(BlockchainError::NotEnoughFunds(Box::new(tx.get_owner().clone()), total_coins))
                         }
                     }
                     TransactionData::Burn(amount) => {
-                        if account.balance < amount + fee { // verify that the user have enough funds
-                            return Err(BlockchainError::NotEnoughFunds(tx.get_owner().clone(), amount + fee))
+                        let total_coins = amount.checked_add(*fee).ok_or_else(|| BlockchainError::TxAmountOverflow(hash.clone()))?;
+                        if account.balance < total_coins { // verify that the user have enough funds
+                            return Err(BlockchainError::NotEnoughFunds(Box::new(tx.get_owner().clone()), total_coins))
                         }
                     },
                     _ => {
@@ -594,14 +966,85 @@ impl Blockchain {
     }
 }
 
+// the genesis block is at height 1, so there is one reward per block
+// it restores the supply after a rewind, which sets the reward of the next block
 pub fn get_supply_at_height(height: u64) -> u64 {
     let mut supply = 0;
-    for _ in 0..=height {
+    for _ in 1..=height {
         supply += get_block_reward(supply);
     }
     supply
 }
 
+// verify the signature of the transaction, using the cache if enabled
+pub fn verify_tx_signature(tx: &Transaction, cache: Option<&SignatureCache>) -> Result<bool, BlockchainError> {
+    match cache {
+        Some(cache) => tx.verify_signature_with_cache(cache),
+        None => tx.verify_signature()
+    }
+}
+
+// verify the signatures of all transactions, split across the number of threads requested
+// the work runs on the tokio blocking pool so the runtime keeps serving other tasks meanwhile
+// all tasks stop as soon as an invalid signature is found
+pub async fn verify_signatures(txs: &[Transaction], threads: usize, cache: Option<&Arc<SignatureCache>>) -> Result<(), BlockchainError> {
+    if threads <= 1 || txs.len() <= 1 {
+        return txs.iter().try_for_each(|tx| verify_signature_of(tx, cache.map(|cache| cache.as_ref())))
+    }
+
+    let cache = cache.cloned();
+    run_blocking(txs.to_vec(), threads, move |tx| verify_signature_of(tx, cache.as_deref())).await
+}
+
+fn verify_signature_of(tx: &Transaction, cache: Option<&SignatureCache>) -> Result<(), BlockchainError> {
+    if !verify_tx_signature(tx, cache)? {
+        return Err(BlockchainError::InvalidTransactionSignature)
+    }
+    Ok(())
+}
+
+// run `f` on all items split in at most `threads` blocking tasks
+// all tasks stop as soon as one item fails, the first error reported is returned
+async fn run_blocking<T, F>(items: Vec<T>, threads: usize, f: F) -> Result<(), BlockchainError>
+    where T: Send + 'static, F: Fn(&T) -> Result<(), BlockchainError> + Send + Sync + 'static
+{
+    let f = Arc::new(f);
+    let failed = Arc::new(AtomicBool::new(false));
+    let chunk_size = items.len().div_ceil(threads.max(1)).max(1);
+    let mut handles = Vec::with_capacity(threads);
+    let mut items = items.into_iter();
+    loop {
+        let chunk: Vec<T> = items.by_ref().take(chunk_size).collect();
+        if chunk.is_empty() {
+            break;
+        }
+        let f = Arc::clone(&f);
+        let failed = Arc::clone(&failed);
+        handles.push(tokio::task::spawn_blocking(move || {
+            for item in &chunk {
+                if failed.load(Ordering::Relaxed) {
+                    break;
+                }
+                if let Err(e) = f(item) {
+                    failed.store(true, Ordering::Relaxed);
+                    return Err(e)
+                }
+            }
+            Ok(())
+        }));
+    }
+
+    // other tasks may have stopped early, keep the first error reported
+    let mut result = Ok(());
+    for handle in handles {
+        let res = handle.await.unwrap_or(Err(BlockchainError::InvalidTransactionSignature));
+        if result.is_ok() {
+            result = res;
+        }
+    }
+    result
+}
+
 pub fn get_block_reward(supply: u64) -> u64 {
     let base_reward = (MAX_SUPPLY - supply) >> EMISSION_SPEED_FACTOR;
     base_reward
@@ -623,4 +1066,364 @@ impl Display for Blockchain {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
         write!(f, "Blockchain[height: {}, accounts: {}, supply: {}]", self.get_height(), 0, self.get_supply())
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::config::BLOCK_TIME;
+    use crate::crypto::key::KeyPair;
+
+    // chain on a regtest network without the P2p and Rpc modules, its genesis block is mined by `miner`
+    // blocks are timestamped in the past, one block time apart, so the difficulty stays at its minimum
+    pub(crate) async fn create_test_chain(miner: &KeyPair, coinbase_maturity: u64) -> Blockchain {
+        create_test_chain_on(create_test_network(miner, coinbase_maturity)).await
+    }
+
+    // regtest network with a genesis block mined by miner
+    pub(crate) fn create_test_network(miner: &KeyPair, coinbase_maturity: u64) -> Network {
+        let timestamp = get_current_timestamp() - (BLOCK_TIME as u128) * 1000;
+        let coinbase = Transaction::new(miner.get_public_key().clone(), TransactionVariant::Coinbase);
+        let genesis = CompleteBlock::new(Block::new(1, timestamp, Hash::zero(), [0u8; 32], coinbase, Vec::new()), 1, Vec::new());
+        let mut network = Network::regtest(genesis, [1u8; 16]);
+        network.set_coinbase_maturity(coinbase_maturity);
+        network
+    }
+
+    pub(crate) async fn create_test_chain_on(network: Network) -> Blockchain {
+        let blockchain = Blockchain {
+            height: AtomicU64::new(0),
+            supply: AtomicU64::new(0),
+            burned: AtomicU64::new(0),
+            difficulty: AtomicU64::new(network.get_minimum_difficulty()),
+            mempool: Mutex::new(Mempool::new(DEFAULT_RBF_MIN_FEE_INCREMENT, DEFAULT_DUST_THRESHOLD, Box::new(SizeFeePolicy::new(DEFAULT_MIN_RELAY_FEE_PER_BYTE)))),
+            network,
+            storage: Mutex::new(Storage::new()),
+            p2p: Mutex::new(None),
+            rpc: Mutex::new(None),
+            dev_address: Address::from_string(&DEV_ADDRESS.to_owned()).unwrap().to_public_key(),
+            verification_threads: 1,
+            signature_cache: None,
+            max_reorg_depth: DEFAULT_MAX_REORG_DEPTH
+        };
+        blockchain.create_genesis_block().await.unwrap();
+        blockchain
+    }
+
+    // add a block with the mempool txs selected by the template
+    pub(crate) async fn add_test_block(blockchain: &Blockchain, miner: &KeyPair) -> Hash {
+        let (mut block, transactions) = blockchain.select_block_template(miner.get_public_key()).await.unwrap();
+        block.timestamp = blockchain.get_storage().lock().await.get_top_block().unwrap().get_timestamp() + BLOCK_TIME as u128;
+        let block = CompleteBlock::new(block, blockchain.get_difficulty(), transactions);
+        let hash = block.hash();
+        blockchain.add_new_block(block, false).await.unwrap();
+        hash
+    }
+
+    // transfer signed by `sender`, with the minimum fee of its size
+    fn create_transfer(sender: &KeyPair, nonce: u64, to: &PublicKey, amount: u64, inputs: &[(Hash, u32)]) -> Transaction {
+        let data = TransactionData::Normal(vec![Tx { amount, to: to.clone() }]);
+        let mut tx = Transaction::new(sender.get_public_key().clone(), TransactionVariant::Normal { nonce, fee: 0, data });
+        for (hash, index) in inputs {
+            tx.add_input(hash.clone(), *index).unwrap();
+        }
+        tx.set_fee(calculate_tx_fee(tx.size() + 64)).unwrap(); // signature included
+        tx.sign(sender);
+        tx
+    }
+
+    async fn verify_test_tx(blockchain: &Blockchain, tx: &Transaction) -> Result<u64, BlockchainError> {
+        let storage = blockchain.get_storage().lock().await;
+        blockchain.verify_transaction_with_hash(&storage, tx, &tx.hash(), false, false)
+    }
+
+    // block on top of the chain with these txs, without going through the mempool
+    async fn add_block_with_txs(blockchain: &Blockchain, miner: &KeyPair, transactions: Vec<Transaction>) -> Result<(), BlockchainError> {
+        let (mut block, _) = blockchain.select_block_template(miner.get_public_key()).await?;
+        block.txs_hashes = transactions.iter().map(|tx| tx.hash()).collect();
+        block.timestamp = blockchain.get_storage().lock().await.get_top_block()?.get_timestamp() + BLOCK_TIME as u128;
+        let block = CompleteBlock::new(block, blockchain.get_difficulty(), transactions);
+        blockchain.add_new_block(block, false).await
+    }
+
+    #[tokio::test]
+    async fn test_block_template_size_limit() {
+        let miner = KeyPair::new();
+        let blockchain = create_test_chain(&miner, 1).await;
+        for _ in 0..8 { // enough rewards to fund all the senders
+            add_test_block(&blockchain, &miner).await;
+        }
+
+        // one tx per sender, so many senders are needed to fill a block
+        let senders: Vec<KeyPair> = (0..130).map(|_| KeyPair::new()).collect();
+        for sender in &senders {
+            blockchain.add_tx_to_mempool(Transaction::new(sender.get_public_key().clone(), TransactionVariant::Registration), false).await.unwrap();
+        }
+        add_test_block(&blockchain, &miner).await;
+
+        let outputs = senders.iter().map(|sender| Tx { amount: 40_000, to: sender.get_public_key().clone() }).collect();
+        let mut tx = Transaction::new(miner.get_public_key().clone(), TransactionVariant::Normal { nonce: 0, fee: 0, data: TransactionData::Normal(outputs) });
+        tx.set_fee(calculate_tx_fee(tx.size())).unwrap();
+        tx.sign(&miner);
+        blockchain.add_tx_to_mempool(tx, false).await.unwrap();
+        add_test_block(&blockchain, &miner).await;
+
+        // all txs have the same size, and fit exactly in a block when their hashes are not counted
+        let (empty_block, _) = blockchain.select_block_template(miner.get_public_key()).await.unwrap();
+        let tx_size = (MAX_BLOCK_SIZE - empty_block.size()) / senders.len();
+        let create_tx = |sender: &KeyPair, outputs: usize| {
+            let outputs = (0..outputs).map(|_| Tx { amount: 100, to: miner.get_public_key().clone() }).collect();
+            let mut tx = Transaction::new(sender.get_public_key().clone(), TransactionVariant::Normal { nonce: 0, fee: 0, data: TransactionData::Normal(outputs) });
+            tx.set_fee(calculate_tx_fee(tx_size)).unwrap();
+            tx.set_data(Some(Vec::new())).unwrap();
+            tx
+        };
+        // outputs fill the tx, the data pads it to the exact size
+        let outputs = (tx_size - create_tx(&senders[0], 0).size() - 20) / 40;
+        for sender in &senders {
+            let mut tx = create_tx(sender, outputs);
+            let padding = tx_size - tx.size();
+            tx.set_data(Some(vec![0; padding])).unwrap();
+            assert_eq!(tx.size(), tx_size);
+            tx.sign(sender);
+            blockchain.add_tx_to_mempool(tx, false).await.unwrap();
+        }
+
+        let (block, transactions) = blockchain.select_block_template(miner.get_public_key()).await.unwrap();
+        assert!(transactions.len() < senders.len());
+        assert!(block.size() + transactions.iter().map(|tx| tx.size()).sum::<usize>() <= MAX_BLOCK_SIZE);
+        // the template must be accepted by our own block validation
+        add_test_block(&blockchain, &miner).await;
+    }
+
+    #[tokio::test]
+    async fn test_dust_output_is_not_consensus() {
+        let miner = KeyPair::new();
+        let blockchain = create_test_chain(&miner, 1).await;
+        let dev_address = blockchain.get_dev_address().clone();
+        let tx = create_transfer(&miner, 0, &dev_address, DEFAULT_DUST_THRESHOLD - 1, &[]);
+        // valid tx, but not accepted in our mempool
+        assert!(verify_test_tx(&blockchain, &tx).await.is_ok());
+        assert!(matches!(blockchain.add_tx_to_mempool(tx.clone(), false).await, Err(BlockchainError::DustOutput(_, _, _))));
+
+        // a block including it, from a node with another threshold, is valid
+        add_block_with_txs(&blockchain, &miner, vec![tx]).await.unwrap();
+        assert_eq!(blockchain.get_height(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_duplicate_input_in_tx() {
+        let miner = KeyPair::new();
+        let blockchain = create_test_chain(&miner, 1).await;
+        let dev_address = blockchain.get_dev_address().clone();
+        let input = (Hash::max(), 0);
+        let tx = create_transfer(&miner, 0, &dev_address, 1000, &[input.clone(), input]);
+        assert!(matches!(verify_test_tx(&blockchain, &tx).await, Err(BlockchainError::DuplicateInput(_, 0, _))));
+    }
+
+    #[tokio::test]
+    async fn test_conflicting_inputs() {
+        let miner = KeyPair::new();
+        let alice = KeyPair::new();
+        let blockchain = create_test_chain(&miner, 1).await;
+        let dev_address = blockchain.get_dev_address().clone();
+        blockchain.add_tx_to_mempool(Transaction::new(alice.get_public_key().clone(), TransactionVariant::Registration), false).await.unwrap();
+        add_test_block(&blockchain, &miner).await;
+        blockchain.add_tx_to_mempool(create_transfer(&miner, 0, alice.get_public_key(), 100_000, &[]), false).await.unwrap();
+        add_test_block(&blockchain, &miner).await;
+
+        // two senders spending the same input, each tx is valid alone
+        let input = (Hash::max(), 1);
+        let first = create_transfer(&miner, 1, &dev_address, 1000, &[input.clone()]);
+        let second = create_transfer(&alice, 0, &dev_address, 1000, &[input.clone(), (Hash::max(), 2)]);
+        assert!(verify_test_tx(&blockchain, &first).await.is_ok());
+        assert!(verify_test_tx(&blockchain, &second).await.is_ok());
+
+        // the second one doesn't pay enough to replace the first one in mempool
+        blockchain.add_tx_to_mempool(first.clone(), false).await.unwrap();
+        assert!(matches!(blockchain.add_tx_to_mempool(second.clone(), false).await, Err(BlockchainError::ReplacementFeeTooLow(_, _))));
+
+        // and both can't be in the same block
+        let err = add_block_with_txs(&blockchain, &miner, vec![first, second]).await.unwrap_err();
+        assert!(matches!(err, BlockchainError::InputAlreadySpent(hash, 1, _) if hash == input.0));
+        assert_eq!(blockchain.get_height(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_coinbase_maturity() {
+        let miner = KeyPair::new();
+        let blockchain = create_test_chain(&miner, 3).await;
+        let dev_address = blockchain.get_dev_address().clone();
+        // the same miner mines every block, so all its coinbase txs have the same hash
+        let coinbase_block = add_test_block(&blockchain, &miner).await;
+        add_test_block(&blockchain, &miner).await;
+
+        // next block is at height 4, the coinbase of height 2 only has 2 confirmations
+        let tx = create_transfer(&miner, 0, &dev_address, 1000, &[(coinbase_block.clone(), 0)]);
+        assert!(matches!(verify_test_tx(&blockchain, &tx).await, Err(BlockchainError::CoinbaseNotMature(_, 2, 3))));
+
+        // a newer block of the same miner doesn't change the maturity of the older one
+        add_test_block(&blockchain, &miner).await;
+        assert!(verify_test_tx(&blockchain, &tx).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_supply_after_rewind() {
+        let miner = KeyPair::new();
+        let blockchain = create_test_chain(&miner, 1).await;
+        // supply built by adding blocks, the genesis block included
+        let mut supplies = vec![blockchain.get_supply()];
+        for _ in 0..4 {
+            add_test_block(&blockchain, &miner).await;
+            supplies.push(blockchain.get_supply());
+        }
+        for (i, supply) in supplies.iter().enumerate() {
+            assert_eq!(get_supply_at_height(i as u64 + 1), *supply);
+        }
+
+        // the supply recalculated after a rewind sets the reward of the next block
+        for count in 1..=3 {
+            add_test_block(&blockchain, &miner).await;
+            let expected = blockchain.get_supply();
+            for _ in 0..count {
+                add_test_block(&blockchain, &miner).await;
+            }
+            blockchain.rewind_chain(count).await.unwrap();
+            assert_eq!(blockchain.get_supply(), expected);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_coinbase_maturity_after_rewind() {
+        let miner = KeyPair::new();
+        let blockchain = create_test_chain(&miner, 3).await;
+        let dev_address = blockchain.get_dev_address().clone();
+        let coinbase_block = add_test_block(&blockchain, &miner).await;
+        add_test_block(&blockchain, &miner).await;
+        // the rewinded block has a coinbase with the same tx hash as the one spent
+        blockchain.rewind_chain(1).await.unwrap();
+
+        let tx = create_transfer(&miner, 0, &dev_address, 1000, &[(coinbase_block, 0)]);
+        assert!(matches!(verify_test_tx(&blockchain, &tx).await, Err(BlockchainError::CoinbaseNotMature(_, 1, 3))));
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_bootstrap() {
+        let miner = KeyPair::new();
+        let source = create_test_chain(&miner, 1).await;
+        for _ in 0..4 {
+            add_test_block(&source, &miner).await;
+        }
+        let snapshot = source.get_storage().lock().await.export_snapshot().unwrap();
+        let root = snapshot.snapshot_root();
+        let bytes = snapshot.to_bytes();
+
+        // a fresh node only has its genesis block
+        let fresh = create_test_chain(&miner, 1).await;
+        let invalid = StateSnapshot::read(&mut Reader::new(&bytes)).unwrap();
+        assert!(matches!(fresh.import_snapshot(invalid, &Hash::zero()).await, Err(BlockchainError::InvalidSnapshotRoot(_, _))));
+        fresh.import_snapshot(StateSnapshot::read(&mut Reader::new(&bytes)).unwrap(), &root).await.unwrap();
+        assert_eq!(fresh.get_chain_state().await, source.get_chain_state().await);
+        assert_eq!(fresh.get_supply(), source.get_supply());
+        assert_eq!(fresh.get_storage().lock().await.export_snapshot().unwrap().to_bytes(), bytes);
+
+        // the chain continues from the snapshot block
+        add_test_block(&source, &miner).await;
+        let block = source.get_storage().lock().await.get_top_block().unwrap().clone();
+        fresh.add_new_block(block, false).await.unwrap();
+        assert_eq!(fresh.get_chain_state().await, source.get_chain_state().await);
+        assert_eq!(fresh.get_storage().lock().await.get_account(miner.get_public_key()).unwrap().get_balance(), source.get_storage().lock().await.get_account(miner.get_public_key()).unwrap().get_balance());
+
+        // a chain with its own blocks can't jump to another snapshot
+        let other = create_test_chain(&miner, 1).await;
+        add_test_block(&other, &miner).await;
+        assert!(matches!(other.import_snapshot(StateSnapshot::read(&mut Reader::new(&bytes)).unwrap(), &root).await, Err(BlockchainError::SnapshotNotImportable)));
+    }
+
+    #[tokio::test]
+    async fn test_has_object() {
+        let miner = KeyPair::new();
+        let blockchain = create_test_chain(&miner, 1).await;
+        let genesis = blockchain.get_top_block_hash().await;
+        assert!(blockchain.has_object(&ObjectRequest::Block(genesis)).await);
+        assert!(!blockchain.has_object(&ObjectRequest::Block(Hash::zero())).await);
+        assert!(!blockchain.has_object(&ObjectRequest::Transaction(Hash::zero())).await);
+
+        // known while in mempool, then once confirmed in a block
+        let tx = Transaction::new(KeyPair::new().get_public_key().clone(), TransactionVariant::Registration);
+        let request = ObjectRequest::Transaction(tx.hash());
+        blockchain.add_tx_to_mempool(tx, false).await.unwrap();
+        assert!(blockchain.has_object(&request).await);
+        let block = add_test_block(&blockchain, &miner).await;
+        assert!(!blockchain.get_mempool().lock().await.contains_tx(request.get_hash()));
+        assert!(blockchain.has_object(&request).await);
+        assert!(blockchain.has_object(&ObjectRequest::Block(block.clone())).await);
+
+        // a rewinded block doesn't confirm its txs anymore
+        blockchain.rewind_chain(1).await.unwrap();
+        assert!(!blockchain.has_object(&request).await);
+        assert!(!blockchain.has_object(&ObjectRequest::Block(block)).await);
+    }
+
+    // signed transfers from distinct senders, the one at `invalid` has its fee changed after signing
+    fn create_signed_txs(count: usize, invalid: Option<usize>) -> Vec<Transaction> {
+        let receiver = KeyPair::new();
+        (0..count).map(|i| {
+            let mut tx = create_transfer(&KeyPair::new(), 0, receiver.get_public_key(), 1000, &[]);
+            if invalid == Some(i) {
+                tx.set_fee(calculate_tx_fee(tx.size() + 64) + 1).unwrap();
+            }
+            tx
+        }).collect()
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_verify_signatures_in_parallel() {
+        let txs = create_signed_txs(64, None);
+        assert!(verify_signatures(&txs, 1, None).await.is_ok());
+        assert!(verify_signatures(&txs, 4, None).await.is_ok());
+
+        let txs = create_signed_txs(64, Some(50));
+        assert!(matches!(verify_signatures(&txs, 1, None).await, Err(BlockchainError::InvalidTransactionSignature)));
+        assert!(matches!(verify_signatures(&txs, 4, None).await, Err(BlockchainError::InvalidTransactionSignature)));
+        let cache = Arc::new(SignatureCache::new(128));
+        assert!(matches!(verify_signatures(&txs, 4, Some(&cache)).await, Err(BlockchainError::InvalidTransactionSignature)));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_verification_pool_respects_threads() {
+        let threads = 3;
+        let active = Arc::new(AtomicU64::new(0));
+        let max_active = Arc::new(AtomicU64::new(0));
+        let done = Arc::new(AtomicU64::new(0));
+        let (a, m, d) = (Arc::clone(&active), Arc::clone(&max_active), Arc::clone(&done));
+        run_blocking((0..64).collect::<Vec<u64>>(), threads, move |_| {
+            let current = a.fetch_add(1, Ordering::SeqCst) + 1;
+            m.fetch_max(current, Ordering::SeqCst);
+            std::thread::sleep(std::time::Duration::from_millis(2));
+            a.fetch_sub(1, Ordering::SeqCst);
+            d.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        }).await.unwrap();
+
+        assert_eq!(done.load(Ordering::SeqCst), 64);
+        let max_active = max_active.load(Ordering::SeqCst);
+        assert!(max_active >= 1 && max_active <= threads as u64);
+    }
+
+    // run with `cargo test --release -- --ignored --nocapture bench_verify_signatures`
+    #[tokio::test(flavor = "multi_thread")]
+    #[ignore]
+    async fn bench_verify_signatures() {
+        let txs = create_signed_txs(2000, None);
+        let threads = num_cpus::get();
+        let start = std::time::Instant::now();
+        verify_signatures(&txs, 1, None).await.unwrap();
+        let sequential = start.elapsed();
+
+        let start = std::time::Instant::now();
+        verify_signatures(&txs, threads, None).await.unwrap();
+        let parallel = start.elapsed();
+        println!("2000 txs: {:?} on 1 thread, {:?} on {} threads ({:.2}x)", sequential, parallel, threads, sequential.as_secs_f64() / parallel.as_secs_f64());
+    }
+}
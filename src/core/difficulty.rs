@@ -2,7 +2,7 @@ use crate::crypto::hash::Hash;
 use num_bigint::{BigUint, ToBigUint};
 use num_traits::{One};
 use super::error::BlockchainError;
-use crate::config::{MINIMUM_DIFFICULTY, BLOCK_TIME};
+use crate::config::BLOCK_TIME;
 use super::block::CompleteBlock;
 use log::debug;
 
@@ -33,8 +33,9 @@ pub fn hash_to_big(hash: &Hash) -> BigUint {
     BigUint::from_bytes_be(hash.as_bytes())
 }
 
-pub fn calculate_difficulty(parent_block: &CompleteBlock, new_block: &CompleteBlock, previous_difficulty: u64) -> u64 {
-    let mut solve_time: u128 = new_block.get_timestamp() - parent_block.get_timestamp();
+pub fn calculate_difficulty(parent_block: &CompleteBlock, new_block: &CompleteBlock, previous_difficulty: u64, minimum_difficulty: u64) -> u64 {
+    // blocks are validated to never be older than their parent, but it must never underflow
+    let mut solve_time: u128 = new_block.get_timestamp().saturating_sub(parent_block.get_timestamp());
     if solve_time > (BLOCK_TIME as u128 * 2) {
         solve_time = BLOCK_TIME as u128 * 2;
     }
@@ -43,8 +44,8 @@ pub fn calculate_difficulty(parent_block: &CompleteBlock, new_block: &CompleteBl
     let diff = ((previous_difficulty as i64 * easypart) / 10000) as u64;
     debug!("Difficulty calculated, easypart: {}, previous diff: {}, diff: {}", easypart, previous_difficulty, diff);
 
-    if diff < MINIMUM_DIFFICULTY {
-       return MINIMUM_DIFFICULTY
+    if diff < minimum_difficulty {
+       return minimum_difficulty
     }
 
     diff
@@ -1,6 +1,6 @@
 use crate::p2p::error::P2pError;
 use crate::crypto::hash::Hash;
-use crate::crypto::key::PublicKey;
+use crate::crypto::key::{PublicKey, CryptoError};
 use crate::crypto::bech32::Bech32Error;
 use super::reader::ReaderError;
 use super::prompt::prompt::PromptError;
@@ -13,6 +13,8 @@ pub enum BlockchainError {
     TimestampIsLessThanParent(u128),
     #[error("Timestamp {} is greater than current time {}", _0, _1)]
     TimestampIsInFuture(u128, u128), // left is expected, right is got
+    #[error("Timestamp {} must be greater than median time past {}", _1, _0)]
+    TimestampIsBeforeMedianTimePast(u128, u128), // left is median time past, right is got
     #[error("Block height mismatch, expected {}, got {}.", _0, _1)]
     InvalidBlockHeight(u64, u64),
     #[error("Invalid difficulty")]
@@ -27,6 +29,8 @@ pub enum BlockchainError {
     InvalidBlockTxs(usize, usize),
     #[error("Block contains an unknown tx: {}", _0)]
     InvalidTxInBlock(Hash),
+    #[error("Block tx at index {} doesn't match its header, expected {}, got {}", _0, _1, _2)]
+    InvalidTxOrder(usize, Hash, Hash),
     #[error("Tx {} not found in mempool", _0)]
     TxNotFound(Hash),
     #[error("Tx {} was present in mempool but not in sorted list!", _0)]
@@ -38,15 +42,21 @@ pub enum BlockchainError {
     #[error("Tx {} is already in block", _0)]
     TxAlreadyInBlock(Hash),
     #[error("Duplicate registration tx for address '{}' found in same block", _0)]
-    DuplicateRegistration(PublicKey), // address
+    DuplicateRegistration(Box<PublicKey>), // address
     #[error("Invalid Tx fee, expected at least {}, got {}", _0, _1)]
     InvalidTxFee(u64, u64),
     #[error("Address {} is not registered", _0)]
-    AddressNotRegistered(PublicKey),
+    AddressNotRegistered(Box<PublicKey>),
     #[error("Address {} is already registered", _0)]
-    AddressAlreadyRegistered(PublicKey),
+    AddressAlreadyRegistered(Box<PublicKey>),
     #[error("Address {} should have at least {}", _0, _1)]
-    NotEnoughFunds(PublicKey, u64),
+    NotEnoughFunds(Box<PublicKey>, u64),
+    #[error("Tx {} amounts overflow", _0)]
+    TxAmountOverflow(Hash),
+    #[error("Tx {} has an output of {} below the dust threshold {}", _0, _1, _2)]
+    DustOutput(Hash, u64, u64),
+    #[error("Total fees of the block overflow")]
+    BlockFeesOverflow,
     #[error("Coinbase Tx not allowed: {}", _0)]
     CoinbaseTxNotAllowed(Hash),
     #[error("Invalid block reward, expected {}, got {}", _0, _1)]
@@ -71,6 +81,10 @@ pub enum BlockchainError {
     ErrorStd(#[from] std::io::Error),
     #[error(transparent)]
     ErrorOnBech32(#[from] Bech32Error),
+    #[error("Unsupported address version {}", _0)]
+    UnsupportedAddressVersion(u8),
+    #[error(transparent)]
+    ErrorOnCrypto(#[from] CryptoError),
     #[error(transparent)]
     ErrorOnP2p(#[from] P2pError),
     #[error(transparent)]
@@ -97,16 +111,50 @@ pub enum BlockchainError {
     GenesisBlockMiner,
     #[error("Invalid genesis block")]
     InvalidGenesisBlock,
+    #[error("Block import file is truncated at record {}", _0)]
+    ImportTruncated(u64),
+    #[error("Invalid block record size {} at record {}", _0, _1)]
+    InvalidImportRecordSize(u32, u64),
     #[error("Not enough blocks")]
     NotEnoughBlocks,
+    #[error("Invalid magic bytes in block record")]
+    InvalidRecordMagic,
+    #[error("Unsupported block record version: {}", _0)]
+    UnsupportedRecordVersion(u8),
+    #[error("Block record is corrupted")]
+    CorruptedBlockRecord,
     #[error("Unknown data store error")]
     Unknown,
     #[error("No signature found for this TX")]
     NoTxSignature,
     #[error("Smart Contract not supported yet")]
     SmartContractTodo,
-    #[error("Unexpected transaction variant to set fees")]
-    UnexpectedTransactionVariant
+    #[error("Unexpected transaction variant for this operation")]
+    UnexpectedTransactionVariant,
+    #[error("Too many inputs in Tx, maximum {}, got {}", _0, _1)]
+    TooManyInputs(usize, usize),
+    #[error("Tx data is too large, maximum {} bytes, got {}", _0, _1)]
+    TransactionDataTooLarge(usize, usize),
+    #[error("Input {}:{} is spent twice in Tx {}", _0, _1, _2)]
+    DuplicateInput(Hash, u32, Hash),
+    #[error("Input {}:{} is already spent by Tx {}", _0, _1, _2)]
+    InputAlreadySpent(Hash, u32, Hash),
+    #[error("Invalid block at checkpoint height {}, expected {}, got {}", _0, _1, _2)]
+    InvalidCheckpoint(u64, Hash, Hash),
+    #[error("Can't rewind below checkpoint at height {}", _0)]
+    ReorgBelowCheckpoint(u64),
+    #[error("Tx {} expired at height {}", _0, _1)]
+    TxExpired(Hash, u64),
+    #[error("Coinbase output {} can't be spent yet, {} confirmations but {} required", _0, _1, _2)]
+    CoinbaseNotMature(Hash, u64, u64),
+    #[error("Replacement Tx fee is too low, expected at least {}, got {}", _0, _1)]
+    ReplacementFeeTooLow(u64, u64),
+    #[error("Tx fee is below the minimum relay fee, expected at least {}, got {}", _0, _1)]
+    FeeBelowRelayMinimum(u64, u64),
+    #[error("Invalid snapshot root, expected {}, got {}", _0, _1)]
+    InvalidSnapshotRoot(Hash, Hash),
+    #[error("A snapshot can only be imported at our top block or on a chain with only the genesis block")]
+    SnapshotNotImportable
 }
 
 impl<T> From<PoisonError<T>> for BlockchainError {
@@ -0,0 +1,56 @@
+use super::serializer::Serializer;
+use super::transaction::Transaction;
+
+// local rules on the fee a tx must pay to be accepted in mempool and relayed
+// it comes on top of the network fee, which is part of the consensus and always checked
+pub trait FeePolicy: Send + Sync {
+    // name shown in logs and through RPC
+    fn get_name(&self) -> &'static str;
+
+    // minimum fee this tx must pay
+    fn min_fee(&self, tx: &Transaction) -> u64;
+
+    fn is_acceptable(&self, tx: &Transaction, fee: u64) -> bool {
+        fee >= self.min_fee(tx)
+    }
+}
+
+// minimum fee proportional to the size of the tx, default policy
+pub struct SizeFeePolicy {
+    fee_per_byte: u64
+}
+
+impl SizeFeePolicy {
+    pub fn new(fee_per_byte: u64) -> Self {
+        Self {
+            fee_per_byte
+        }
+    }
+
+    pub fn get_fee_per_byte(&self) -> u64 {
+        self.fee_per_byte
+    }
+}
+
+impl FeePolicy for SizeFeePolicy {
+    fn get_name(&self) -> &'static str {
+        "size"
+    }
+
+    fn min_fee(&self, tx: &Transaction) -> u64 {
+        (tx.size() as u64).saturating_mul(self.fee_per_byte)
+    }
+}
+
+// any tx paying the network fee is accepted (private chain, faucet)
+pub struct ZeroFeePolicy;
+
+impl FeePolicy for ZeroFeePolicy {
+    fn get_name(&self) -> &'static str {
+        "zero"
+    }
+
+    fn min_fee(&self, _: &Transaction) -> u64 {
+        0
+    }
+}
@@ -1,7 +1,8 @@
 use crate::crypto::hash::Hash;
 use super::serializer::Serializer;
-use super::transaction::Transaction;
+use super::transaction::{Transaction, TransactionVariant, TransactionData};
 use super::error::BlockchainError;
+use super::fee_policy::FeePolicy;
 use std::collections::HashMap;
 
 #[derive(serde::Serialize)]
@@ -15,18 +16,70 @@ pub struct SortedTx {
 pub struct Mempool {
     txs: HashMap<Hash, Transaction>,
     txs_sorted: Vec<SortedTx>,
+    #[serde(skip)]
+    spent_inputs: HashMap<(Hash, u32), Hash>, // input spent -> tx hash spending it
+    rbf_min_fee_increment: u64, // minimum fee increase in percent to replace conflicting txs
+    dust_threshold: u64, // outputs below this amount are not accepted in mempool
+    #[serde(skip)]
+    fee_policy: Box<dyn FeePolicy> // minimum fee of a tx to be accepted and relayed by this node
 }
 
 impl Mempool {
-    pub fn new() -> Self {
+    pub fn new(rbf_min_fee_increment: u64, dust_threshold: u64, fee_policy: Box<dyn FeePolicy>) -> Self {
         Mempool {
             txs: HashMap::new(),
-            txs_sorted: Vec::new()
+            txs_sorted: Vec::new(),
+            spent_inputs: HashMap::new(),
+            rbf_min_fee_increment,
+            dust_threshold,
+            fee_policy
         }
     }
 
+    // local policy on top of the network fee, a tx paying less is valid but not kept or relayed by us
+    pub fn verify_relay_fee(&self, tx: &Transaction, fee: u64) -> Result<(), BlockchainError> {
+        if !self.fee_policy.is_acceptable(tx, fee) {
+            return Err(BlockchainError::FeeBelowRelayMinimum(self.fee_policy.min_fee(tx), fee))
+        }
+        Ok(())
+    }
+
+    // tiny outputs only bloat the accounts, a burn is not concerned as nobody can spend it
+    // local policy like the relay fee, a block containing a smaller output is still valid
+    pub fn verify_dust(&self, tx: &Transaction, hash: &Hash) -> Result<(), BlockchainError> {
+        if let TransactionVariant::Normal { data: TransactionData::Normal(txs), .. } = tx.get_variant() {
+            if let Some(output) = txs.iter().find(|output| output.amount < self.dust_threshold) {
+                return Err(BlockchainError::DustOutput(hash.clone(), output.amount, self.dust_threshold))
+            }
+        }
+        Ok(())
+    }
+
+    // a tx spending inputs already spent in mempool replaces the conflicting txs (replace-by-fee)
+    // only if it pays enough fees compared to all of them, otherwise it is rejected
+    // returns the hashes of the txs it would replace
+    pub fn verify_replacement(&self, tx: &Transaction, fee: u64) -> Result<Vec<Hash>, BlockchainError> {
+        let conflicts = self.get_conflicting_txs(tx);
+        if !conflicts.is_empty() {
+            let conflicts_fee: u64 = self.txs_sorted.iter().filter(|sorted| conflicts.contains(&sorted.hash)).map(|sorted| sorted.fee).sum();
+            // replacement must always pay strictly more, even with a zero increment
+            let min_fee = conflicts_fee.saturating_add((conflicts_fee.saturating_mul(self.rbf_min_fee_increment) / 100).max(1));
+            if fee < min_fee {
+                return Err(BlockchainError::ReplacementFeeTooLow(min_fee, fee))
+            }
+        }
+        Ok(conflicts)
+    }
+
     // All checks are made in Blockchain before calling this function
+    // relay fee, dust and replacement are checked again so a tx is never kept without them
     pub fn add_tx_with_fee(&mut self, hash: Hash, tx: Transaction, fee: u64) -> Result<(), BlockchainError> {
+        self.verify_relay_fee(&tx, fee)?;
+        self.verify_dust(&tx, &hash)?;
+        for conflict in self.verify_replacement(&tx, fee)? {
+            self.remove_tx(&conflict)?;
+        }
+
         let size = tx.size();
         let sorted_tx = SortedTx {
             hash: hash.clone(),
@@ -43,6 +96,9 @@ impl Mempool {
         }
 
         self.txs_sorted.insert(index, sorted_tx);
+        for (input_hash, input_index) in tx.get_inputs() {
+            self.spent_inputs.insert((input_hash.clone(), *input_index), hash.clone());
+        }
         self.txs.insert(hash, tx);
         Ok(())
     }
@@ -55,10 +111,64 @@ impl Mempool {
         let tx = self.txs.remove(hash).ok_or_else(|| BlockchainError::TxNotFound(hash.clone()))?;
         let index = self.txs_sorted.iter().position(|tx| tx.hash == *hash).ok_or_else(|| BlockchainError::TxNotFoundInSortedList(hash.clone()))?; // TODO Optimized
         self.txs_sorted.remove(index);
+        for (input_hash, input_index) in tx.get_inputs() {
+            self.spent_inputs.remove(&(input_hash.clone(), *input_index));
+        }
 
         Ok(tx)
     }
 
+    // remove all txs expired at this height, returns their hashes
+    pub fn remove_expired_txs(&mut self, height: u64) -> Vec<Hash> {
+        let expired: Vec<Hash> = self.txs.iter()
+            .filter(|(_, tx)| tx.is_expired(height))
+            .map(|(hash, _)| hash.clone())
+            .collect();
+
+        for hash in &expired {
+            // can't fail, hash was found just before
+            let _ = self.remove_tx(hash);
+        }
+        expired
+    }
+
+    // all txs in mempool spending at least one input of this tx
+    pub fn get_conflicting_txs(&self, tx: &Transaction) -> Vec<Hash> {
+        let mut conflicts: Vec<Hash> = Vec::new();
+        for (hash, index) in tx.get_inputs() {
+            if let Some(conflict) = self.spent_inputs.get(&(hash.clone(), *index)) {
+                if !conflicts.contains(conflict) {
+                    conflicts.push(conflict.clone());
+                }
+            }
+        }
+        conflicts
+    }
+
+    pub fn get_rbf_min_fee_increment(&self) -> u64 {
+        self.rbf_min_fee_increment
+    }
+
+    pub fn get_dust_threshold(&self) -> u64 {
+        self.dust_threshold
+    }
+
+    pub fn get_fee_policy(&self) -> &dyn FeePolicy {
+        self.fee_policy.as_ref()
+    }
+
+    // txs already in mempool are kept, the new policy only applies to the next ones
+    pub fn set_fee_policy(&mut self, fee_policy: Box<dyn FeePolicy>) {
+        self.fee_policy = fee_policy;
+    }
+
+    // returns the first input of this tx already spent by a tx in mempool, with the hash of this tx
+    pub fn get_conflicting_input<'a>(&'a self, tx: &'a Transaction) -> Option<(&'a Hash, u32, &'a Hash)> {
+        tx.get_inputs().iter().find_map(|(hash, index)| {
+            self.spent_inputs.get(&(hash.clone(), *index)).map(|conflict| (hash, *index, conflict))
+        })
+    }
+
     pub fn view_tx(&self, hash: &Hash) -> Result<&Transaction, BlockchainError> {
         self.txs.get(hash).ok_or_else(|| BlockchainError::TxNotFound(hash.clone()))
     }
@@ -80,4 +190,147 @@ impl SortedTx {
     pub fn get_size(&self) -> usize {
         self.size
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{DEFAULT_RBF_MIN_FEE_INCREMENT, DEFAULT_DUST_THRESHOLD};
+    use crate::core::fee_policy::{SizeFeePolicy, ZeroFeePolicy};
+    use crate::core::serializer::Serializer;
+    use crate::crypto::hash::Hashable;
+    use crate::crypto::key::KeyPair;
+    use crate::core::transaction::Tx;
+
+    // tx of a new sender spending the given inputs
+    fn create_tx(inputs: &[(Hash, u32)], fee: u64) -> (Hash, Transaction) {
+        let owner = KeyPair::new().get_public_key().clone();
+        let mut tx = Transaction::new(owner, TransactionVariant::Normal { nonce: 0, fee, data: TransactionData::Normal(Vec::new()) });
+        for (hash, index) in inputs {
+            tx.add_input(hash.clone(), *index).unwrap();
+        }
+        (tx.hash(), tx)
+    }
+
+    fn create_mempool_with_tx(input: &(Hash, u32), fee: u64) -> (Mempool, Hash) {
+        let mut mempool = Mempool::new(DEFAULT_RBF_MIN_FEE_INCREMENT, DEFAULT_DUST_THRESHOLD, Box::new(ZeroFeePolicy));
+        let (hash, tx) = create_tx(&[input.clone()], fee);
+        mempool.add_tx_with_fee(hash.clone(), tx, fee).unwrap();
+        (mempool, hash)
+    }
+
+    #[test]
+    fn test_replacement_with_fee_bump() {
+        let input = (Hash::max(), 0);
+        let (mut mempool, original) = create_mempool_with_tx(&input, 1000);
+        let fee = 1000 + 1000 * DEFAULT_RBF_MIN_FEE_INCREMENT / 100;
+        let (hash, tx) = create_tx(&[input], fee);
+
+        assert_eq!(mempool.verify_replacement(&tx, fee).unwrap(), vec![original.clone()]);
+        mempool.add_tx_with_fee(hash.clone(), tx, fee).unwrap();
+        assert!(!mempool.contains_tx(&original));
+        assert!(mempool.contains_tx(&hash));
+        assert_eq!(mempool.get_sorted_txs().len(), 1);
+    }
+
+    #[test]
+    fn test_replacement_with_insufficient_fee() {
+        let input = (Hash::max(), 0);
+        let (mut mempool, original) = create_mempool_with_tx(&input, 1000);
+        let fee = 1000 + 1000 * DEFAULT_RBF_MIN_FEE_INCREMENT / 100 - 1;
+        let (hash, tx) = create_tx(&[input], fee);
+
+        assert!(matches!(mempool.verify_replacement(&tx, fee), Err(BlockchainError::ReplacementFeeTooLow(_, _))));
+        assert!(mempool.add_tx_with_fee(hash.clone(), tx, fee).is_err());
+        // the original tx is kept untouched
+        assert!(mempool.contains_tx(&original));
+        assert!(!mempool.contains_tx(&hash));
+    }
+
+    fn create_transfer(amount: u64) -> (Hash, Transaction) {
+        let to = KeyPair::new().get_public_key().clone();
+        let tx = Transaction::new(KeyPair::new().get_public_key().clone(), TransactionVariant::Normal { nonce: 0, fee: 1000, data: TransactionData::Normal(vec![Tx { amount, to }]) });
+        (tx.hash(), tx)
+    }
+
+    #[test]
+    fn test_dust_threshold() {
+        let mut mempool = Mempool::new(DEFAULT_RBF_MIN_FEE_INCREMENT, DEFAULT_DUST_THRESHOLD, Box::new(ZeroFeePolicy));
+        let (hash, tx) = create_transfer(DEFAULT_DUST_THRESHOLD);
+        assert!(mempool.verify_dust(&tx, &hash).is_ok());
+        mempool.add_tx_with_fee(hash.clone(), tx, 1000).unwrap();
+        assert!(mempool.contains_tx(&hash));
+
+        let (hash, tx) = create_transfer(DEFAULT_DUST_THRESHOLD - 1);
+        assert!(matches!(mempool.verify_dust(&tx, &hash), Err(BlockchainError::DustOutput(_, amount, DEFAULT_DUST_THRESHOLD)) if amount == DEFAULT_DUST_THRESHOLD - 1));
+        assert!(mempool.add_tx_with_fee(hash.clone(), tx, 1000).is_err());
+        assert!(!mempool.contains_tx(&hash));
+    }
+
+    #[test]
+    fn test_min_relay_fee_boundary() {
+        let fee_per_byte = 2;
+        let mut mempool = Mempool::new(DEFAULT_RBF_MIN_FEE_INCREMENT, DEFAULT_DUST_THRESHOLD, Box::new(SizeFeePolicy::new(fee_per_byte)));
+        // the fee is given apart from the tx, so the same tx is checked with several fees
+        let (hash, tx) = create_tx(&[], 0);
+        let min_fee = tx.size() as u64 * fee_per_byte;
+
+        assert!(matches!(mempool.verify_relay_fee(&tx, min_fee - 1), Err(BlockchainError::FeeBelowRelayMinimum(expected, got)) if expected == min_fee && got == min_fee - 1));
+        assert!(mempool.add_tx_with_fee(hash.clone(), tx.clone(), min_fee - 1).is_err());
+        assert!(!mempool.contains_tx(&hash));
+
+        assert!(mempool.verify_relay_fee(&tx, min_fee).is_ok());
+        mempool.add_tx_with_fee(hash.clone(), tx.clone(), min_fee).unwrap();
+        assert!(mempool.contains_tx(&hash));
+
+        // without minimum, only the network fee is required
+        mempool.set_fee_policy(Box::new(ZeroFeePolicy));
+        assert!(mempool.verify_relay_fee(&tx, 0).is_ok());
+    }
+
+    // policy defined outside of the node, only its trait is needed by the mempool
+    struct FlatFeePolicy(u64);
+
+    impl FeePolicy for FlatFeePolicy {
+        fn get_name(&self) -> &'static str {
+            "flat"
+        }
+
+        fn min_fee(&self, _: &Transaction) -> u64 {
+            self.0
+        }
+    }
+
+    #[test]
+    fn test_fee_policies() {
+        let (hash, tx) = create_tx(&[], 10);
+        let policies: Vec<(Box<dyn FeePolicy>, &str, bool)> = vec![
+            (Box::new(ZeroFeePolicy), "zero", true),
+            (Box::new(SizeFeePolicy::new(1)), "size", false),
+            (Box::new(FlatFeePolicy(10)), "flat", true),
+            (Box::new(FlatFeePolicy(11)), "flat", false)
+        ];
+        // the same tx with the same fee, only the policy changes
+        for (policy, name, accepted) in policies {
+            let mut mempool = Mempool::new(DEFAULT_RBF_MIN_FEE_INCREMENT, DEFAULT_DUST_THRESHOLD, policy);
+            assert_eq!(mempool.get_fee_policy().get_name(), name);
+            assert_eq!(mempool.add_tx_with_fee(hash.clone(), tx.clone(), 10).is_ok(), accepted, "policy {}", name);
+            assert_eq!(mempool.contains_tx(&hash), accepted);
+        }
+    }
+
+    #[test]
+    fn test_no_conflict() {
+        let (mut mempool, original) = create_mempool_with_tx(&(Hash::max(), 0), 1000);
+        // same input hash but another index, and a tx without inputs
+        let (hash, tx) = create_tx(&[(Hash::max(), 1)], 1);
+        assert!(mempool.verify_replacement(&tx, 1).unwrap().is_empty());
+        mempool.add_tx_with_fee(hash.clone(), tx, 1).unwrap();
+        let (other, tx) = create_tx(&[], 1);
+        mempool.add_tx_with_fee(other.clone(), tx, 1).unwrap();
+
+        assert!(mempool.contains_tx(&original));
+        assert!(mempool.contains_tx(&hash));
+        assert!(mempool.contains_tx(&other));
+    }
+}
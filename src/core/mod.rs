@@ -1,14 +1,35 @@
+#[cfg(feature = "std")]
 pub mod block;
+#[cfg(feature = "std")]
 pub mod difficulty;
+#[cfg(feature = "std")]
 pub mod blockchain;
+#[cfg(feature = "std")]
 pub mod transaction;
+#[cfg(feature = "std")]
 pub mod mempool;
+#[cfg(feature = "std")]
+pub mod fee_policy;
+#[cfg(feature = "std")]
 pub mod error;
 pub mod serializer;
+#[cfg(feature = "std")]
+pub mod async_serializer;
 pub mod reader;
 pub mod writer;
+#[cfg(feature = "std")]
 pub mod storage;
+#[cfg(feature = "std")]
+pub mod block_store;
+#[cfg(feature = "std")]
+pub mod snapshot;
+#[cfg(feature = "std")]
+pub mod network;
+#[cfg(feature = "std")]
 pub mod prompt;
+#[cfg(feature = "std")]
 pub mod json_rpc;
+#[cfg(feature = "std")]
 pub mod message;
+#[cfg(feature = "std")]
 pub mod xpool;
\ No newline at end of file
@@ -0,0 +1,116 @@
+use crate::config::{NETWORK_ID, MAINNET_CHECKPOINTS, SEED_NODES, GENESIS_BLOCK, MINIMUM_DIFFICULTY, REGTEST_MINIMUM_DIFFICULTY, COINBASE_MATURITY, REGTEST_COINBASE_MATURITY};
+use crate::crypto::hash::{Hash, HashAlgorithm};
+use super::error::BlockchainError;
+use super::serializer::Serializer;
+use super::block::CompleteBlock;
+
+// All parameters specific to a network
+pub struct Network {
+    network_id: [u8; 16], // unique id sent in handshake
+    checkpoints: Vec<(u64, Hash)>, // hardcoded (height, block hash), sorted by height
+    seed_nodes: Vec<String>, // nodes to connect to at startup
+    genesis_block: Option<CompleteBlock>, // a new genesis block is generated if not set
+    minimum_difficulty: u64, // difficulty can't go below this value
+    hash_algorithm: HashAlgorithm, // algorithm used to hash blocks and transactions
+    coinbase_maturity: u64, // confirmations required before a coinbase output can be spent
+    mainnet: bool // addresses of this network use the mainnet prefix
+}
+
+impl Network {
+    pub fn mainnet() -> Result<Self, BlockchainError> {
+        let checkpoints = MAINNET_CHECKPOINTS.iter().map(|(height, hash)| {
+            let bytes = hex::decode(hash).expect("invalid checkpoint hash");
+            (*height, Hash::from_slice(&bytes).expect("invalid checkpoint hash size"))
+        }).collect();
+
+        let genesis_block = if GENESIS_BLOCK.is_empty() {
+            None
+        } else {
+            Some(CompleteBlock::from_hex(GENESIS_BLOCK.to_owned()).map_err(|_| BlockchainError::InvalidGenesisBlock)?)
+        };
+
+        let mut network = Self::new(NETWORK_ID, checkpoints);
+        network.seed_nodes = SEED_NODES.iter().map(|addr| addr.to_string()).collect();
+        network.genesis_block = genesis_block;
+        network.mainnet = true;
+        Ok(network)
+    }
+
+    // private network fully controlled by its operator (integration tests, CI)
+    // no seed nodes, no checkpoints and a trivial difficulty so blocks are mined instantly
+    pub fn regtest(genesis: CompleteBlock, network_id: [u8; 16]) -> Self {
+        let mut network = Self::new(network_id, Vec::new());
+        network.genesis_block = Some(genesis);
+        network.minimum_difficulty = REGTEST_MINIMUM_DIFFICULTY;
+        network.coinbase_maturity = REGTEST_COINBASE_MATURITY;
+        network
+    }
+
+    pub fn new(network_id: [u8; 16], mut checkpoints: Vec<(u64, Hash)>) -> Self {
+        checkpoints.sort_by_key(|(height, _)| *height);
+        Self {
+            network_id,
+            checkpoints,
+            seed_nodes: Vec::new(),
+            genesis_block: None,
+            minimum_difficulty: MINIMUM_DIFFICULTY,
+            hash_algorithm: HashAlgorithm::Sha256,
+            coinbase_maturity: COINBASE_MATURITY,
+            mainnet: false
+        }
+    }
+
+    pub fn get_network_id(&self) -> &[u8; 16] {
+        &self.network_id
+    }
+
+    pub fn get_seed_nodes(&self) -> &Vec<String> {
+        &self.seed_nodes
+    }
+
+    pub fn get_genesis_block(&self) -> Option<&CompleteBlock> {
+        self.genesis_block.as_ref()
+    }
+
+    pub fn get_minimum_difficulty(&self) -> u64 {
+        self.minimum_difficulty
+    }
+
+    pub fn get_hash_algorithm(&self) -> HashAlgorithm {
+        self.hash_algorithm
+    }
+
+    pub fn get_coinbase_maturity(&self) -> u64 {
+        self.coinbase_maturity
+    }
+
+    pub fn set_coinbase_maturity(&mut self, coinbase_maturity: u64) {
+        self.coinbase_maturity = coinbase_maturity;
+    }
+
+    pub fn set_seed_nodes(&mut self, seed_nodes: Vec<String>) {
+        self.seed_nodes = seed_nodes;
+    }
+
+    pub fn is_mainnet(&self) -> bool {
+        self.mainnet
+    }
+
+    pub fn get_checkpoints(&self) -> &Vec<(u64, Hash)> {
+        &self.checkpoints
+    }
+
+    // expected block hash at this height, if any
+    pub fn get_checkpoint(&self, height: u64) -> Option<&Hash> {
+        self.checkpoints.iter().find(|(h, _)| *h == height).map(|(_, hash)| hash)
+    }
+
+    pub fn is_checkpointed(&self, height: u64) -> bool {
+        self.get_checkpoint(height).is_some()
+    }
+
+    // no reorg is allowed below this height, 0 if there is no checkpoint
+    pub fn get_last_checkpoint_height(&self) -> u64 {
+        self.checkpoints.last().map_or(0, |(height, _)| *height)
+    }
+}
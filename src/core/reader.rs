@@ -1,9 +1,13 @@
 use crate::crypto::hash::Hash;
-use std::fmt::{Display, Error, Formatter};
-use std::convert::TryInto;
-use thiserror::Error;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::{Display, Error, Formatter};
+use core::convert::TryInto;
+use core::ops::Deref;
+#[cfg(feature = "std")]
+use bytes::Bytes;
 
-#[derive(Error, Debug)]
+#[derive(Debug)]
 pub enum ReaderError {
     InvalidSize,
     InvalidValue,
@@ -11,27 +15,80 @@ pub enum ReaderError {
     ErrorTryInto
 }
 
+// bytes read by a Reader, either borrowed or owned by it
+enum ReaderBytes<'a> {
+    Borrowed(&'a [u8]),
+    #[cfg(feature = "std")]
+    Owned(Bytes)
+}
+
+impl<'a> Deref for ReaderBytes<'a> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            ReaderBytes::Borrowed(bytes) => bytes,
+            #[cfg(feature = "std")]
+            ReaderBytes::Owned(bytes) => bytes
+        }
+    }
+}
+
 // Reader help us to read safely from bytes
 // Mostly used when de-serializing an object from Serializer trait 
 pub struct Reader<'a> {
-    bytes: &'a[u8], // bytes to read
-    total: usize // total read bytes
+    bytes: ReaderBytes<'a>, // bytes to read
+    total: usize, // total read bytes
+    #[cfg(debug_assertions)]
+    checkpoints: Vec<(&'static str, usize)> // labeled offsets, only kept in debug builds
+}
+
+// the reader owns the bytes, so it isn't tied to the lifetime of a buffer
+// cloning a Bytes is cheap, the data is shared and not copied
+#[cfg(feature = "std")]
+impl Reader<'static> {
+    pub fn from_bytes(bytes: Bytes) -> Self {
+        Reader {
+            bytes: ReaderBytes::Owned(bytes),
+            total: 0,
+            #[cfg(debug_assertions)]
+            checkpoints: Vec::new()
+        }
+    }
 }
 
 impl<'a> Reader<'a> {
     pub fn new(bytes: &'a [u8]) -> Self {
         Reader {
-            bytes,
-            total: 0
+            bytes: ReaderBytes::Borrowed(bytes),
+            total: 0,
+            #[cfg(debug_assertions)]
+            checkpoints: Vec::new()
         }
     }
 
+    // record the current offset with the label of the field about to be read
+    // does nothing in release builds
+    #[inline(always)]
+    pub fn checkpoint(&mut self, _label: &'static str) {
+        #[cfg(debug_assertions)]
+        self.checkpoints.push((_label, self.total));
+    }
+
+    // last label recorded and its offset, always None in release builds
+    pub fn last_checkpoint(&self) -> Option<(&'static str, usize)> {
+        #[cfg(debug_assertions)]
+        return self.checkpoints.last().copied();
+        #[cfg(not(debug_assertions))]
+        None
+    }
+
     pub fn read_bool(&mut self) -> Result<bool, ReaderError> {
         Ok(self.read_u8()? == 1)
     }
 
     pub fn read_bytes<T>(&mut self, n: usize) -> Result<T, ReaderError>
-    where T: for<'b> std::convert::TryFrom<&'b[u8]> {
+    where T: for<'b> core::convert::TryFrom<&'b[u8]> {
         if n > self.size() {
             return Err(ReaderError::InvalidSize)
         }
@@ -72,6 +129,10 @@ impl<'a> Reader<'a> {
         Ok(u16::from_be_bytes(self.read_bytes(2)?))
     }
 
+    pub fn read_u32(&mut self) -> Result<u32, ReaderError> {
+        Ok(u32::from_be_bytes(self.read_bytes(4)?))
+    }
+
     pub fn read_u64(&mut self) -> Result<u64, ReaderError> {
         Ok(u64::from_be_bytes(self.read_bytes(8)?))
     }
@@ -80,6 +141,45 @@ impl<'a> Reader<'a> {
         Ok(u128::from_be_bytes(self.read_bytes(16)?))
     }
 
+    pub fn read_i32(&mut self) -> Result<i32, ReaderError> {
+        Ok(i32::from_be_bytes(self.read_bytes(4)?))
+    }
+
+    pub fn read_i64(&mut self) -> Result<i64, ReaderError> {
+        Ok(i64::from_be_bytes(self.read_bytes(8)?))
+    }
+
+    // read a LEB128 varint, a u64 is encoded in 10 bytes max
+    // only the minimal encoding is accepted so a value has a single encoding (0x80 0x00 is not a 0)
+    pub fn read_varint(&mut self) -> Result<u64, ReaderError> {
+        let mut value: u64 = 0;
+        for i in 0..10 {
+            let byte = self.read_u8()?;
+            // last byte can only hold the highest bit of a u64
+            if i == 9 && byte > 1 {
+                return Err(ReaderError::InvalidValue)
+            }
+            // a last byte at 0 adds nothing to the value
+            if i > 0 && byte == 0 {
+                return Err(ReaderError::InvalidValue)
+            }
+            value |= ((byte & 0x7F) as u64) << (i * 7);
+            if byte & 0x80 == 0 {
+                return Ok(value)
+            }
+        }
+        Err(ReaderError::InvalidValue)
+    }
+
+    // read bytes prefixed by a varint length, length can't be greater than max
+    pub fn read_var_bytes(&mut self, max: usize) -> Result<Vec<u8>, ReaderError> {
+        let len = self.read_varint()?;
+        if len > max as u64 {
+            return Err(ReaderError::InvalidSize)
+        }
+        self.read_bytes(len as usize)
+    }
+
     pub fn read_string_with_size(&mut self, size: usize) -> Result<String, ReaderError> {
         let bytes: Vec<u8> = self.read_bytes(size)?;
         match String::from_utf8(bytes) {
@@ -100,6 +200,33 @@ impl<'a> Reader<'a> {
         }
     }
 
+    // same as read_string but the size is checked before reading the string
+    pub fn read_string_max(&mut self, max: usize) -> Result<String, ReaderError> {
+        let size = self.read_u8()? as usize;
+        if size > max {
+            return Err(ReaderError::InvalidSize)
+        }
+        self.read_string_with_size(size)
+    }
+
+    // same as read_optional_string but the size is checked before reading the string
+    pub fn read_optional_string_max(&mut self, max: usize) -> Result<Option<String>, ReaderError> {
+        match self.read_u8()? as usize {
+            0 => Ok(None),
+            n if n > max => Err(ReaderError::InvalidSize),
+            n => Ok(Some(self.read_string_with_size(n)?)),
+        }
+    }
+
+    // ignore the next n bytes, used for fields added by a newer version
+    pub fn skip(&mut self, n: usize) -> Result<(), ReaderError> {
+        if n > self.size() {
+            return Err(ReaderError::InvalidSize)
+        }
+        self.total += n;
+        Ok(())
+    }
+
     pub fn total_size(&self) -> usize {
         self.bytes.len()
     }
@@ -114,7 +241,7 @@ impl<'a> Reader<'a> {
 }
 
 impl Display for ReaderError {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::result::Result<(), Error> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::result::Result<(), Error> {
         match self {
             ReaderError::ErrorTryInto => write!(f, "Error on try into"),
             ReaderError::InvalidSize => write!(f, "Invalid size"),
@@ -122,4 +249,92 @@ impl Display for ReaderError {
             ReaderError::InvalidHex => write!(f, "Invalid hex"),
         }
     }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for ReaderError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::writer::Writer;
+
+    fn write_varint(value: u64) -> Vec<u8> {
+        let mut writer = Writer::new();
+        writer.write_varint(value);
+        writer.bytes()
+    }
+
+    #[test]
+    fn test_fixed_size_round_trip() {
+        let mut writer = Writer::new();
+        for value in [0, 1, 0x01020304, u32::MAX] {
+            writer.write_u32(&value);
+        }
+        for value in [0, -1, i32::MIN, i32::MAX] {
+            writer.write_i32(&value);
+        }
+        for value in [0, -1, i64::MIN, i64::MAX] {
+            writer.write_i64(&value);
+        }
+        let bytes = writer.bytes();
+        // big endian, like the other integers
+        assert_eq!(bytes[8..12], [1, 2, 3, 4]);
+
+        let mut reader = Reader::new(&bytes);
+        for value in [0, 1, 0x01020304, u32::MAX] {
+            assert_eq!(reader.read_u32().unwrap(), value);
+        }
+        for value in [0, -1, i32::MIN, i32::MAX] {
+            assert_eq!(reader.read_i32().unwrap(), value);
+        }
+        for value in [0, -1, i64::MIN, i64::MAX] {
+            assert_eq!(reader.read_i64().unwrap(), value);
+        }
+        assert_eq!(reader.size(), 0);
+        assert!(matches!(reader.read_u32(), Err(ReaderError::InvalidSize)));
+    }
+
+    #[test]
+    fn test_truncated_fixed_size() {
+        assert!(Reader::new(&[0; 3]).read_u32().is_err());
+        assert!(Reader::new(&[0; 3]).read_i32().is_err());
+        assert!(Reader::new(&[0; 7]).read_i64().is_err());
+    }
+
+    #[test]
+    fn test_varint_round_trip() {
+        for value in [0, 1, 0x7F, 0x80, 0x3FFF, 0x4000, u32::MAX as u64, u64::MAX - 1, u64::MAX] {
+            let bytes = write_varint(value);
+            let mut reader = Reader::new(&bytes);
+            assert_eq!(reader.read_varint().unwrap(), value);
+            assert_eq!(reader.size(), 0);
+        }
+    }
+
+    #[test]
+    fn test_varint_max_value() {
+        let bytes = write_varint(u64::MAX);
+        assert_eq!(bytes, vec![0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x01]);
+        // one bit more than a u64 can hold
+        let mut reader = Reader::new(&[0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x02]);
+        assert!(matches!(reader.read_varint(), Err(ReaderError::InvalidValue)));
+    }
+
+    #[test]
+    fn test_varint_over_length() {
+        let mut reader = Reader::new(&[0xFF; 11]);
+        assert!(matches!(reader.read_varint(), Err(ReaderError::InvalidValue)));
+        // no last byte
+        let mut reader = Reader::new(&[0x80, 0x80]);
+        assert!(reader.read_varint().is_err());
+    }
+
+    #[test]
+    fn test_varint_non_minimal() {
+        for bytes in [vec![0x80, 0x00], vec![0x81, 0x00], vec![0xFF, 0x80, 0x00], vec![0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x00]] {
+            let mut reader = Reader::new(&bytes);
+            assert!(matches!(reader.read_varint(), Err(ReaderError::InvalidValue)));
+        }
+    }
 }
\ No newline at end of file
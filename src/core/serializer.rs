@@ -1,7 +1,11 @@
 use super::reader::{Reader, ReaderError};
 use super::writer::Writer;
-use std::marker::Sized;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::marker::Sized;
 
+// Serializer implementations must be deterministic: the same object must always produce the same bytes
+// never write data by iterating over an unordered collection (HashMap, HashSet)
 pub trait Serializer {
     fn write(&self, writer: &mut Writer);
 
@@ -0,0 +1,107 @@
+use crate::crypto::hash::{Hash, hash};
+use crate::crypto::key::{PublicKey, KEY_LENGTH};
+use crate::crypto::merkle::merkle_root;
+use super::reader::{Reader, ReaderError};
+use super::serializer::Serializer;
+use super::blockchain::Account;
+use super::writer::Writer;
+use std::collections::HashMap;
+
+// size of one serialized entry: public key + balance + nonce
+const SNAPSHOT_ENTRY_SIZE: usize = KEY_LENGTH + 8 + 8;
+
+// Snapshot of all accounts at a specific block
+// entries are always sorted by public key so the snapshot and its root are deterministic
+pub struct StateSnapshot {
+    height: u64, // height of the block at which the snapshot was taken
+    top_hash: Hash, // hash of this block
+    accounts: Vec<(PublicKey, Account)> // sorted by public key bytes
+}
+
+impl StateSnapshot {
+    pub fn new(height: u64, top_hash: Hash, accounts: &HashMap<PublicKey, Account>) -> Self {
+        let mut accounts: Vec<(PublicKey, Account)> = accounts.iter()
+            .map(|(key, account)| (key.clone(), Account::new(account.get_balance(), account.get_nonce())))
+            .collect();
+        accounts.sort_by(|(a, _), (b, _)| a.as_bytes().cmp(b.as_bytes()));
+
+        Self {
+            height,
+            top_hash,
+            accounts
+        }
+    }
+
+    pub fn get_height(&self) -> u64 {
+        self.height
+    }
+
+    pub fn get_top_hash(&self) -> &Hash {
+        &self.top_hash
+    }
+
+    pub fn get_accounts(&self) -> &Vec<(PublicKey, Account)> {
+        &self.accounts
+    }
+
+    // merkle root over the hash of each sorted entry
+    pub fn snapshot_root(&self) -> Hash {
+        let leaves: Vec<Hash> = self.accounts.iter().map(|(key, account)| {
+            let mut writer = Writer::new();
+            write_entry(&mut writer, key, account);
+            hash(&writer.bytes())
+        }).collect();
+        merkle_root(&leaves)
+    }
+
+    pub fn into_accounts(self) -> HashMap<PublicKey, Account> {
+        self.accounts.into_iter().collect()
+    }
+}
+
+fn write_entry(writer: &mut Writer, key: &PublicKey, account: &Account) {
+    key.write(writer);
+    writer.write_u64(&account.get_balance());
+    writer.write_u64(&account.get_nonce());
+}
+
+impl Serializer for StateSnapshot {
+    fn write(&self, writer: &mut Writer) {
+        writer.write_u64(&self.height);
+        writer.write_hash(&self.top_hash);
+        writer.write_u64(&(self.accounts.len() as u64));
+        for (key, account) in &self.accounts {
+            write_entry(writer, key, account);
+        }
+    }
+
+    fn read(reader: &mut Reader) -> Result<StateSnapshot, ReaderError> {
+        let height = reader.read_u64()?;
+        let top_hash = reader.read_hash()?;
+        let count = reader.read_u64()? as usize;
+        // don't trust the count to allocate, it must fit in the remaining bytes
+        if count > reader.size() / SNAPSHOT_ENTRY_SIZE {
+            return Err(ReaderError::InvalidSize)
+        }
+
+        let mut accounts: Vec<(PublicKey, Account)> = Vec::with_capacity(count);
+        for _ in 0..count {
+            let key = PublicKey::read(reader)?;
+            let balance = reader.read_u64()?;
+            let nonce = reader.read_u64()?;
+            // entries must be strictly sorted, this also rejects duplicates
+            if let Some((previous, _)) = accounts.last() {
+                if previous.as_bytes() >= key.as_bytes() {
+                    return Err(ReaderError::InvalidValue)
+                }
+            }
+            accounts.push((key, Account::new(balance, nonce)));
+        }
+
+        Ok(StateSnapshot {
+            height,
+            top_hash,
+            accounts
+        })
+    }
+}
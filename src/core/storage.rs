@@ -3,12 +3,17 @@ use crate::crypto::key::PublicKey;
 use super::error::BlockchainError;
 use super::block::CompleteBlock;
 use super::blockchain::Account;
+use super::snapshot::StateSnapshot;
 use std::collections::HashMap;
 
 pub struct Storage {
     accounts: HashMap<PublicKey, Account>, // all accounts registered on chain
     top_block_hash: Hash, // current block top hash
     blocks: Vec<CompleteBlock>, // all blocks in blockchain
+    coinbase_heights: HashMap<Hash, u64>, // block hash -> height of the block, for the coinbase outputs it created
+    tx_heights: HashMap<Hash, u64>, // confirmed tx hash -> height of the block which included it
+    base_height: u64, // height of the snapshot block the chain was bootstrapped from, 0 if it starts at genesis
+    base_hash: Hash // hash of this snapshot block, which is not stored
 }
 
 impl Storage {
@@ -16,7 +21,11 @@ impl Storage {
         Self {
             accounts: HashMap::new(),
             top_block_hash: Hash::zero(),
-            blocks: Vec::new()
+            blocks: Vec::new(),
+            coinbase_heights: HashMap::new(),
+            tx_heights: HashMap::new(),
+            base_height: 0,
+            base_hash: Hash::zero()
         }
     }
 
@@ -27,7 +36,7 @@ impl Storage {
     pub fn get_account(&self, account: &PublicKey) -> Result<&Account, BlockchainError> {
         match self.accounts.get(account) {
             Some(v) => Ok(v),
-            None => Err(BlockchainError::AddressNotRegistered(account.clone()))
+            None => Err(BlockchainError::AddressNotRegistered(Box::new(account.clone())))
         }
     }
 
@@ -38,7 +47,7 @@ impl Storage {
     pub fn get_mut_account(&mut self, account: &PublicKey) -> Result<&mut Account, BlockchainError> {
         match self.accounts.get_mut(account) {
             Some(v) => Ok(v),
-            None => Err(BlockchainError::AddressNotRegistered(account.clone()))
+            None => Err(BlockchainError::AddressNotRegistered(Box::new(account.clone())))
         }
     }
 
@@ -47,24 +56,34 @@ impl Storage {
     }
 
     pub fn add_new_block(&mut self, block: CompleteBlock, hash: Hash) {
+        self.coinbase_heights.insert(hash.clone(), block.get_height());
+        for tx in block.get_transactions() {
+            self.tx_heights.insert(tx.hash(), block.get_height());
+        }
         self.blocks.push(block);
         self.top_block_hash = hash;
     }
 
     pub fn pop_blocks(&mut self, n: usize) -> Result<u64, BlockchainError> {
-        if self.blocks.len() <= n { // also prevent removing genesis block
+        // also prevent removing genesis block, a chain bootstrapped from a snapshot can go back to the snapshot block
+        if self.blocks.len() < n || (self.base_height == 0 && self.blocks.len() == n) {
             return Err(BlockchainError::NotEnoughBlocks);
         }
-        self.blocks.truncate(self.blocks.len() - n);
-        let top_height = if let Some(block) = self.blocks.get(self.blocks.len() - 1) {
+        for block in self.blocks.drain(self.blocks.len() - n..) {
+            self.coinbase_heights.remove(&block.hash());
+            for tx in block.get_transactions() {
+                self.tx_heights.remove(&tx.hash());
+            }
+        }
+        let top_height = if let Some(block) = self.blocks.last() {
             let hash = block.hash();
             let height = block.get_height();
             self.top_block_hash = hash;
             // TODO Reverse txs
             height
-        } else { // shouldn't happens
-            self.top_block_hash = Hash::zero();
-            0
+        } else { // only possible on a chain bootstrapped from a snapshot
+            self.top_block_hash = self.base_hash.clone();
+            self.base_height
         };
 
         Ok(top_height)
@@ -75,16 +94,36 @@ impl Storage {
     }
 
     pub fn has_block(&self, hash: &Hash) -> bool {
-        self.get_block_by_hash(hash).is_ok()
+        self.get_block_height_by_hash(hash).is_some()
+    }
+
+    // also knows the snapshot block of a bootstrapped chain, which is not stored
+    pub fn get_block_height_by_hash(&self, hash: &Hash) -> Option<u64> {
+        if self.base_height != 0 && self.base_hash == *hash {
+            return Some(self.base_height)
+        }
+        self.get_block_by_hash(hash).ok().map(|block| block.get_height())
     }
 
     pub fn get_block_at_height(&self, height: u64) -> Result<&CompleteBlock, BlockchainError> {
-        match self.blocks.get(height as usize - 1) {
+        if height <= self.base_height {
+            return Err(BlockchainError::BlockHeightNotFound(height))
+        }
+        match self.blocks.get((height - self.base_height) as usize - 1) {
             Some(block) => Ok(block),
             None => Err(BlockchainError::BlockHeightNotFound(height))
         }
     }
 
+    // height of the snapshot block the chain was bootstrapped from, 0 if it starts at genesis
+    pub fn get_base_height(&self) -> u64 {
+        self.base_height
+    }
+
+    pub fn get_base_hash(&self) -> &Hash {
+        &self.base_hash
+    }
+
     pub fn get_block_by_hash(&self, hash: &Hash) -> Result<&CompleteBlock, BlockchainError> {
         for block in &self.blocks {
             if block.hash() == *hash {
@@ -94,6 +133,21 @@ impl Storage {
         Err(BlockchainError::BlockNotFound(hash.clone()))
     }
 
+    // height of the block which created this coinbase output, None if it's not a known block
+    // a coinbase output is referenced by the block hash: all coinbase txs of a miner have the same hash
+    pub fn get_coinbase_height(&self, hash: &Hash) -> Option<u64> {
+        self.coinbase_heights.get(hash).copied()
+    }
+
+    // height of the block in which this tx was included, None if it's not confirmed
+    pub fn get_tx_height(&self, hash: &Hash) -> Option<u64> {
+        self.tx_heights.get(hash).copied()
+    }
+
+    pub fn has_transaction(&self, hash: &Hash) -> bool {
+        self.tx_heights.contains_key(hash)
+    }
+
     pub fn get_blocks(&self) -> &Vec<CompleteBlock> {
         &self.blocks
     }
@@ -105,4 +159,48 @@ impl Storage {
     pub fn get_top_block(&self) -> Result<&CompleteBlock, BlockchainError> {
         self.get_block_by_hash(self.get_top_block_hash())
     }
+
+    // median timestamp of the last `window` blocks, 0 if there is no block
+    pub fn median_time_past(&self, window: usize) -> u128 {
+        let start = self.blocks.len().saturating_sub(window);
+        let mut timestamps: Vec<u128> = self.blocks[start..].iter().map(|block| block.get_timestamp()).collect();
+        if timestamps.is_empty() {
+            return 0
+        }
+        timestamps.sort_unstable();
+        timestamps[timestamps.len() / 2]
+    }
+
+    // export all accounts at current top block
+    pub fn export_snapshot(&self) -> Result<StateSnapshot, BlockchainError> {
+        let height = match self.blocks.last() {
+            Some(block) => block.get_height(),
+            None => self.base_height
+        };
+        Ok(StateSnapshot::new(height, self.top_block_hash.clone(), &self.accounts))
+    }
+
+    // replace all accounts with the ones from the snapshot, its root must match the trusted one
+    // snapshot must be taken at the current top block, or the chain must only have its genesis block:
+    // in this case, the chain restarts from the snapshot block and the blocks before it are never stored
+    pub fn import_snapshot(&mut self, snapshot: StateSnapshot, expected_root: &Hash) -> Result<(), BlockchainError> {
+        let root = snapshot.snapshot_root();
+        if root != *expected_root {
+            return Err(BlockchainError::InvalidSnapshotRoot(expected_root.clone(), root))
+        }
+
+        if *snapshot.get_top_hash() != self.top_block_hash {
+            if self.base_height != 0 || self.blocks.len() > 1 || snapshot.get_height() <= 1 {
+                return Err(BlockchainError::SnapshotNotImportable)
+            }
+            self.blocks.clear();
+            self.coinbase_heights.clear();
+            self.tx_heights.clear();
+            self.base_height = snapshot.get_height();
+            self.base_hash = snapshot.get_top_hash().clone();
+            self.top_block_hash = self.base_hash.clone();
+        }
+        self.accounts = snapshot.into_accounts();
+        Ok(())
+    }
 }
\ No newline at end of file
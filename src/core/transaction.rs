@@ -1,10 +1,25 @@
-use crate::crypto::key::{PublicKey, Signature, SIGNATURE_LENGTH, KeyPair};
-use crate::crypto::hash::{Hashable, hash};
+use crate::crypto::key::{PublicKey, Signature, KeyPair};
+use crate::crypto::hash::{Hash, Hashable, hash_with_prefix};
+use crate::crypto::signature_cache::{SignatureCache, SignatureCacheKey};
 use super::reader::{Reader, ReaderError};
 use super::error::BlockchainError;
 use super::serializer::Serializer;
+use super::async_serializer::{AsyncSerializer, AsyncReader};
 use super::writer::Writer;
-use std::collections::HashMap;
+use crate::config::{MAX_TRANSACTION_INPUTS, MAX_TRANSACTION_DATA_SIZE, MAX_BLOCK_SIZE};
+use std::collections::BTreeMap;
+use tokio::io::AsyncRead;
+
+// domain separation prefix for transaction signatures
+// so a transaction signature can't be accepted for the same bytes signed in another context
+pub const TX_SIGN_PREFIX: &[u8] = b"XELIS_TX_SIGN";
+
+// variant ids, a Normal tx using inputs, expiration or data is written with its own id
+// so a node at protocol version 0 can still read all the other txs
+const NORMAL_ID: u8 = 0;
+const REGISTRATION_ID: u8 = 1;
+const COINBASE_ID: u8 = 2;
+const EXTENDED_NORMAL_ID: u8 = 3;
 
 #[derive(serde::Serialize, Clone)]
 pub struct Tx {
@@ -16,7 +31,7 @@ pub struct Tx {
 pub struct SmartContractTx {
     pub contract: String,
     pub amount: u64,
-    pub params: HashMap<String, String> // TODO
+    pub params: BTreeMap<String, String> // ordered map so params are always serialized in the same order
 }
 
 #[derive(serde::Serialize, Clone)]
@@ -56,7 +71,7 @@ impl Serializer for TransactionData {
             }
             TransactionData::UploadSmartContract(code) => {
                 writer.write_u8(3);
-                writer.write_string(code);
+                writer.write_var_bytes(code.as_bytes()); // code can be longer than 255 bytes
             }
         };
     }
@@ -80,13 +95,77 @@ impl Serializer for TransactionData {
                 }
                 TransactionData::Normal(txs)
             },
-            2 => { // TODO SC
+            2 => { // SC, all fields written must be consumed to not leave a partially read object
+                let contract = reader.read_string()?;
+                let amount = reader.read_u64()?;
+                let mut params = BTreeMap::new();
+                for _ in 0..reader.read_u8()? {
+                    let key = reader.read_string()?;
+                    let value = reader.read_string()?;
+                    // keys must be strictly sorted like they are written, so a tx has a single encoding
+                    if params.keys().next_back().is_some_and(|last: &String| *last >= key) {
+                        return Err(ReaderError::InvalidValue)
+                    }
+                    params.insert(key, value);
+                }
+                TransactionData::SmartContract(SmartContractTx {
+                    contract,
+                    amount,
+                    params
+                })
+            },
+            3 => {
+                let code = reader.read_var_bytes(MAX_BLOCK_SIZE)?;
+                TransactionData::UploadSmartContract(String::from_utf8(code).map_err(|_| ReaderError::InvalidValue)?)
+            },
+            _ => {
+                return Err(ReaderError::InvalidValue)
+            }
+        })
+    }
+}
+
+impl AsyncSerializer for TransactionData {
+    async fn read_async<R: AsyncRead + Unpin + Send>(reader: &mut AsyncReader<R>) -> Result<Self, ReaderError> {
+        Ok(match reader.read_u8().await? {
+            0 => {
+                let amount = reader.read_u64().await?;
+                TransactionData::Burn(amount)
+            },
+            1 => { // Normal
+                let mut txs = vec![];
+                for _ in 0..reader.read_u8().await? {
+                    let amount = reader.read_u64().await?;
+                    let to = PublicKey::read_async(reader).await?;
+                    txs.push(Tx {
+                        amount,
+                        to
+                    });
+                }
+                TransactionData::Normal(txs)
+            },
+            2 => { // SC
+                let contract = reader.read_string().await?;
+                let amount = reader.read_u64().await?;
+                let mut params = BTreeMap::new();
+                for _ in 0..reader.read_u8().await? {
+                    let key = reader.read_string().await?;
+                    let value = reader.read_string().await?;
+                    if params.keys().next_back().is_some_and(|last: &String| *last >= key) {
+                        return Err(ReaderError::InvalidValue)
+                    }
+                    params.insert(key, value);
+                }
                 TransactionData::SmartContract(SmartContractTx {
-                    contract: String::from(""),
-                    amount: 0,
-                    params: HashMap::new()
+                    contract,
+                    amount,
+                    params
                 })
             },
+            3 => {
+                let code = reader.read_var_bytes(MAX_BLOCK_SIZE).await?;
+                TransactionData::UploadSmartContract(String::from_utf8(code).map_err(|_| ReaderError::InvalidValue)?)
+            },
             _ => {
                 return Err(ReaderError::InvalidValue)
             }
@@ -105,37 +184,54 @@ pub enum TransactionVariant {
     Coinbase,
 }
 
-impl Serializer for TransactionVariant {
-    fn write(&self, writer: &mut Writer) {
+impl TransactionVariant {
+    fn get_id(&self) -> u8 {
         match self {
-            TransactionVariant::Normal { nonce, fee, data } => {
-                writer.write_u8(0);
-                writer.write_u64(nonce);
-                writer.write_u64(fee);
-                data.write(writer);
-            },
-            TransactionVariant::Registration => {
-                writer.write_u8(1);
-            },
-            TransactionVariant::Coinbase => {
-                writer.write_u8(2);
-            }
+            TransactionVariant::Normal { .. } => NORMAL_ID,
+            TransactionVariant::Registration => REGISTRATION_ID,
+            TransactionVariant::Coinbase => COINBASE_ID
         }
     }
 
-    fn read(reader: &mut Reader) -> Result<Self, ReaderError> {
-        let id = reader.read_u8()?;
+    // fields of the variant, without its id
+    fn write_body(&self, writer: &mut Writer) {
+        if let TransactionVariant::Normal { nonce, fee, data } = self {
+            writer.write_u64(nonce);
+            writer.write_u64(fee);
+            data.write(writer);
+        }
+    }
+
+    fn read_body(id: u8, reader: &mut Reader) -> Result<Self, ReaderError> {
         Ok(match id {
-            0 => {
+            NORMAL_ID => {
                 let nonce = reader.read_u64()?;
                 let fee = reader.read_u64()?;
                 let data = TransactionData::read(reader)?;
                 TransactionVariant::Normal { nonce, fee, data }
             },
-            1 => {
+            REGISTRATION_ID => {
+                TransactionVariant::Registration
+            }
+            COINBASE_ID => {
+                TransactionVariant::Coinbase
+            }
+            _ => return Err(ReaderError::InvalidValue)
+        })
+    }
+
+    async fn read_body_async<R: AsyncRead + Unpin + Send>(id: u8, reader: &mut AsyncReader<R>) -> Result<Self, ReaderError> {
+        Ok(match id {
+            NORMAL_ID => {
+                let nonce = reader.read_u64().await?;
+                let fee = reader.read_u64().await?;
+                let data = TransactionData::read_async(reader).await?;
+                TransactionVariant::Normal { nonce, fee, data }
+            },
+            REGISTRATION_ID => {
                 TransactionVariant::Registration
             }
-            2 => {
+            COINBASE_ID => {
                 TransactionVariant::Coinbase
             }
             _ => return Err(ReaderError::InvalidValue)
@@ -143,10 +239,32 @@ impl Serializer for TransactionVariant {
     }
 }
 
+impl Serializer for TransactionVariant {
+    fn write(&self, writer: &mut Writer) {
+        writer.write_u8(self.get_id());
+        self.write_body(writer);
+    }
+
+    fn read(reader: &mut Reader) -> Result<Self, ReaderError> {
+        let id = reader.read_u8()?;
+        TransactionVariant::read_body(id, reader)
+    }
+}
+
+impl AsyncSerializer for TransactionVariant {
+    async fn read_async<R: AsyncRead + Unpin + Send>(reader: &mut AsyncReader<R>) -> Result<Self, ReaderError> {
+        let id = reader.read_u8().await?;
+        TransactionVariant::read_body_async(id, reader).await
+    }
+}
+
 #[derive(serde::Serialize, Clone)]
 pub struct Transaction {
     owner: PublicKey,
     variant: TransactionVariant,
+    inputs: Vec<(Hash, u32)>, // previous outputs spent (tx hash, or block hash for a coinbase output + output index), only for Normal variant
+    expire_at_height: Option<u64>, // tx can't be included once the chain reached this height, only for Normal variant
+    data: Option<Vec<u8>>, // memo set by the sender (order id, message...), only for Normal variant
     signature: Option<Signature>
 }
 
@@ -155,10 +273,78 @@ impl Transaction {
         Transaction {
             owner,
             variant,
+            inputs: Vec::new(),
+            expire_at_height: None,
+            data: None,
             signature: None
         }
     }
 
+    pub fn get_inputs(&self) -> &Vec<(Hash, u32)> {
+        &self.inputs
+    }
+
+    // inputs are part of the signed data, so they must be set before signing
+    pub fn add_input(&mut self, hash: Hash, index: u32) -> Result<(), BlockchainError> {
+        if !self.has_extended_fields() {
+            return Err(BlockchainError::UnexpectedTransactionVariant)
+        }
+
+        if self.inputs.len() >= MAX_TRANSACTION_INPUTS {
+            return Err(BlockchainError::TooManyInputs(MAX_TRANSACTION_INPUTS, self.inputs.len() + 1))
+        }
+        self.inputs.push((hash, index));
+        Ok(())
+    }
+
+    pub fn get_expire_at_height(&self) -> Option<u64> {
+        self.expire_at_height
+    }
+
+    // expiration is part of the signed data, so it must be set before signing
+    pub fn set_expire_at_height(&mut self, height: Option<u64>) -> Result<(), BlockchainError> {
+        if !self.has_extended_fields() {
+            return Err(BlockchainError::UnexpectedTransactionVariant)
+        }
+        self.expire_at_height = height;
+        Ok(())
+    }
+
+    pub fn get_data(&self) -> Option<&Vec<u8>> {
+        self.data.as_ref()
+    }
+
+    // data is part of the signed data, so it must be set before signing
+    pub fn set_data(&mut self, data: Option<Vec<u8>>) -> Result<(), BlockchainError> {
+        if !self.has_extended_fields() {
+            return Err(BlockchainError::UnexpectedTransactionVariant)
+        }
+
+        if let Some(data) = &data {
+            if data.len() > MAX_TRANSACTION_DATA_SIZE {
+                return Err(BlockchainError::TransactionDataTooLarge(MAX_TRANSACTION_DATA_SIZE, data.len()))
+            }
+        }
+        self.data = data;
+        Ok(())
+    }
+
+    // check if the tx is expired when the chain is at this height
+    pub fn is_expired(&self, height: u64) -> bool {
+        matches!(self.expire_at_height, Some(expire) if expire <= height)
+    }
+
+    // only a Normal transaction has extended fields (inputs, expiration, data)
+    // this keeps the format of Registration & Coinbase txs (and so the genesis block) unchanged
+    pub fn has_extended_fields(&self) -> bool {
+        matches!(self.variant, TransactionVariant::Normal { .. })
+    }
+
+    // a tx using any extended field is written in the extended format, unknown at protocol version 0
+    pub fn is_extended(&self) -> bool {
+        !self.inputs.is_empty() || self.expire_at_height.is_some() || self.data.is_some()
+    }
+
     pub fn get_variant(&self) -> &TransactionVariant {
         &self.variant
     }
@@ -188,9 +374,7 @@ impl Transaction {
                 return Err(BlockchainError::UnexpectedTransactionSignature)
             }
 
-            let bytes = self.to_bytes();
-            let bytes = &bytes[0..bytes.len() - SIGNATURE_LENGTH]; // remove signature bytes for verification
-            Ok(self.get_owner().verify_signature(&hash(bytes), signature))
+            Ok(self.get_owner().verify_signature(&self.hash_for_signing(), signature))
         } else if self.require_signature() { // we shouldn't have a signature on unrequired variant
             Err(BlockchainError::NoTxSignature)
         } else {
@@ -198,8 +382,50 @@ impl Transaction {
         }
     }
 
+    // same as verify_signature, but the result of the verification is cached
+    pub fn verify_signature_with_cache(&self, cache: &SignatureCache) -> Result<bool, BlockchainError> {
+        match &self.signature {
+            Some(signature) if self.require_signature() => {
+                let hash = self.hash_for_signing();
+                let key = SignatureCacheKey::new(self.get_owner(), &hash, signature);
+                if let Some(valid) = cache.get(&key) {
+                    return Ok(valid)
+                }
+
+                let valid = self.get_owner().verify_signature(&hash, signature);
+                cache.insert(key, valid);
+                Ok(valid)
+            },
+            _ => self.verify_signature()
+        }
+    }
+
+    // bytes signed by the owner, all fields except the signature in this fixed order:
+    // owner (32 bytes) | variant (id + its fields)
+    // and for an extended Normal tx only (variant id 3 instead of 0): inputs count (u8) | inputs (tx hash + output index u32)
+    // | expiration (bool + u64 if set) | data (bool + varint length + bytes if set)
+    // integers are big endian, this layout must stay stable so external signers can rebuild it
+    pub fn signing_bytes(&self) -> Vec<u8> {
+        self.to_bytes()
+    }
+
+    // SHA-256 of TX_SIGN_PREFIX followed by the signing bytes
+    // a wallet can sign it offline and attach the signature with set_signature
+    pub fn hash_for_signing(&self) -> Hash {
+        hash_with_prefix(TX_SIGN_PREFIX, &self.signing_bytes())
+    }
+
     pub fn sign(&mut self, pair: &KeyPair) {
-        self.signature = Some(pair.sign(self.hash().as_bytes()));
+        self.signature = Some(pair.sign(self.hash_for_signing().as_bytes()));
+    }
+
+    pub fn get_signature(&self) -> Option<&Signature> {
+        self.signature.as_ref()
+    }
+
+    // signature computed outside of the node, checked with verify_signature
+    pub fn set_signature(&mut self, signature: Signature) {
+        self.signature = Some(signature);
     }
 
     pub fn set_fee(&mut self, value: u64) -> Result<(), BlockchainError> {
@@ -215,19 +441,348 @@ impl Transaction {
 impl Serializer for Transaction {
     fn write(&self, writer: &mut Writer) {
         self.owner.write(writer);
-        self.variant.write(writer);
+        if !self.is_extended() {
+            self.variant.write(writer);
+            return;
+        }
+
+        writer.write_u8(EXTENDED_NORMAL_ID);
+        self.variant.write_body(writer);
+        writer.write_u8(self.inputs.len() as u8); // bounded by MAX_TRANSACTION_INPUTS
+        for (hash, index) in &self.inputs {
+            writer.write_hash(hash);
+            writer.write_u32(index);
+        }
+
+        match &self.expire_at_height {
+            Some(height) => {
+                writer.write_bool(&true);
+                writer.write_u64(height);
+            },
+            None => writer.write_bool(&false)
+        };
+
+        match &self.data {
+            Some(data) => {
+                writer.write_bool(&true);
+                writer.write_var_bytes(data);
+            },
+            None => writer.write_bool(&false)
+        };
     }
 
     fn read(reader: &mut Reader) -> Result<Transaction, ReaderError> {
-        let tx = Transaction {
-            owner: PublicKey::read(reader)?,
-            variant: TransactionVariant::read(reader)?,
+        let owner = PublicKey::read(reader)?;
+        let id = reader.read_u8()?;
+        let extended = id == EXTENDED_NORMAL_ID;
+        let mut tx = Transaction {
+            owner,
+            variant: TransactionVariant::read_body(if extended { NORMAL_ID } else { id }, reader)?,
+            inputs: Vec::new(),
+            expire_at_height: None,
+            data: None,
+            signature: None
+        };
+
+        if extended {
+            let count = reader.read_u8()? as usize;
+            if count > MAX_TRANSACTION_INPUTS {
+                return Err(ReaderError::InvalidValue)
+            }
+
+            for _ in 0..count {
+                let hash = reader.read_hash()?;
+                let index = reader.read_u32()?;
+                tx.inputs.push((hash, index));
+            }
+
+            if reader.read_bool()? {
+                tx.expire_at_height = Some(reader.read_u64()?);
+            }
+
+            if reader.read_bool()? {
+                tx.data = Some(reader.read_var_bytes(MAX_TRANSACTION_DATA_SIZE)?);
+            }
+
+            // the same tx must always have the same bytes (and hash), so an unused extension is invalid
+            if !tx.is_extended() {
+                return Err(ReaderError::InvalidValue)
+            }
+        }
+
+        Ok(tx)
+    }
+}
+
+impl AsyncSerializer for Transaction {
+    async fn read_async<R: AsyncRead + Unpin + Send>(reader: &mut AsyncReader<R>) -> Result<Self, ReaderError> {
+        let owner = PublicKey::read_async(reader).await?;
+        let id = reader.read_u8().await?;
+        let extended = id == EXTENDED_NORMAL_ID;
+        let mut tx = Transaction {
+            owner,
+            variant: TransactionVariant::read_body_async(if extended { NORMAL_ID } else { id }, reader).await?,
+            inputs: Vec::new(),
+            expire_at_height: None,
+            data: None,
             signature: None
         };
 
+        if extended {
+            let count = reader.read_u8().await? as usize;
+            if count > MAX_TRANSACTION_INPUTS {
+                return Err(ReaderError::InvalidValue)
+            }
+
+            for _ in 0..count {
+                let hash = reader.read_hash().await?;
+                let index = reader.read_u32().await?;
+                tx.inputs.push((hash, index));
+            }
+
+            if reader.read_bool().await? {
+                tx.expire_at_height = Some(reader.read_u64().await?);
+            }
+
+            if reader.read_bool().await? {
+                tx.data = Some(reader.read_var_bytes(MAX_TRANSACTION_DATA_SIZE).await?);
+            }
+
+            if !tx.is_extended() {
+                return Err(ReaderError::InvalidValue)
+            }
+        }
 
         Ok(tx)
     }
 }
 
-impl Hashable for Transaction {}
\ No newline at end of file
+impl Hashable for Transaction {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::DEV_ADDRESS;
+    use crate::crypto::address::Address;
+
+    fn create_tx() -> Transaction {
+        let to = KeyPair::new().get_public_key().clone();
+        let data = TransactionData::Normal(vec![Tx { amount: 1000, to }]);
+        Transaction::new(KeyPair::new().get_public_key().clone(), TransactionVariant::Normal { nonce: 5, fee: 1000, data })
+    }
+
+    fn round_trip(tx: &Transaction) -> Transaction {
+        let bytes = tx.to_bytes();
+        let mut reader = Reader::new(&bytes);
+        let read = Transaction::read(&mut reader).unwrap();
+        assert_eq!(reader.size(), 0);
+        assert_eq!(read.to_bytes(), bytes);
+        read
+    }
+
+    #[test]
+    fn test_original_format() {
+        // a tx without extended fields is written like at protocol version 0
+        let tx = create_tx();
+        let mut writer = Writer::new();
+        tx.get_owner().write(&mut writer);
+        writer.write_u8(0); // variant id
+        writer.write_u64(&5); // nonce
+        writer.write_u64(&1000); // fee
+        if let TransactionVariant::Normal { data, .. } = tx.get_variant() {
+            data.write(&mut writer);
+        }
+        assert!(!tx.is_extended());
+        assert_eq!(tx.to_bytes(), writer.bytes());
+        round_trip(&tx);
+    }
+
+    #[test]
+    fn test_extended_format() {
+        let mut tx = create_tx();
+        tx.add_input(Hash::max(), 3).unwrap();
+        tx.set_expire_at_height(Some(100)).unwrap();
+        tx.set_data(Some(vec![1, 2, 3])).unwrap();
+        assert!(tx.is_extended());
+        assert_eq!(tx.to_bytes()[32], EXTENDED_NORMAL_ID);
+
+        let read = round_trip(&tx);
+        assert_eq!(*read.get_inputs(), vec![(Hash::max(), 3)]);
+        assert_eq!(read.get_expire_at_height(), Some(100));
+        assert_eq!(read.get_data(), Some(&vec![1, 2, 3]));
+        assert_eq!(read.hash(), tx.hash());
+    }
+
+    // fixed tx from the dev address to itself, so the signing hash doesn't depend on random keys
+    fn create_known_tx() -> Transaction {
+        let owner = Address::from_string(&DEV_ADDRESS.to_owned()).unwrap().to_public_key();
+        let data = TransactionData::Normal(vec![Tx { amount: 1000, to: owner.clone() }]);
+        Transaction::new(owner, TransactionVariant::Normal { nonce: 5, fee: 1000, data })
+    }
+
+    #[test]
+    fn test_signing_hash_vector() {
+        // external signers rebuild these bytes, any change here breaks them
+        let mut tx = create_known_tx();
+        assert_eq!(tx.hash_for_signing().to_hex(), "6967be2d8b8ce8d91769d6006db2fea5e95228c1228a8268bf43a84bcf2a3caf");
+
+        tx.add_input(Hash::zero(), 1).unwrap();
+        tx.set_expire_at_height(Some(100)).unwrap();
+        tx.set_data(Some(vec![1, 2, 3])).unwrap();
+        assert_eq!(tx.signing_bytes()[32], EXTENDED_NORMAL_ID);
+        assert_eq!(tx.hash_for_signing().to_hex(), "3f1ddd4e7946705ff18a2dbbdbf786f7f1d41011432e521e5fe19ea588404492");
+    }
+
+    fn create_smart_contract_data(params: &[(&str, &str)]) -> TransactionData {
+        TransactionData::SmartContract(SmartContractTx {
+            contract: "contract".to_owned(),
+            amount: 10,
+            params: params.iter().map(|(key, value)| (key.to_string(), value.to_string())).collect()
+        })
+    }
+
+    #[test]
+    fn test_smart_contract_params_serialization() {
+        // insertion order doesn't change the bytes
+        let data = create_smart_contract_data(&[("b", "2"), ("a", "1"), ("c", "3")]);
+        let bytes = data.to_bytes();
+        assert_eq!(bytes, create_smart_contract_data(&[("c", "3"), ("a", "1"), ("b", "2")]).to_bytes());
+
+        let mut writer = Writer::new();
+        writer.write_u8(2);
+        writer.write_string(&"contract".to_owned());
+        writer.write_u64(&10);
+        writer.write_u8(3);
+        for (key, value) in [("a", "1"), ("b", "2"), ("c", "3")] {
+            writer.write_string(&key.to_owned());
+            writer.write_string(&value.to_owned());
+        }
+        assert_eq!(bytes, writer.bytes());
+
+        let mut reader = Reader::new(&bytes);
+        assert_eq!(TransactionData::read(&mut reader).unwrap().to_bytes(), bytes);
+    }
+
+    #[test]
+    fn test_unsorted_smart_contract_params() {
+        let mut writer = Writer::new();
+        writer.write_u8(2);
+        writer.write_string(&"contract".to_owned());
+        writer.write_u64(&10);
+        writer.write_u8(2);
+        for (key, value) in [("b", "2"), ("a", "1")] {
+            writer.write_string(&key.to_owned());
+            writer.write_string(&value.to_owned());
+        }
+        let bytes = writer.bytes();
+        let mut reader = Reader::new(&bytes);
+        assert!(matches!(TransactionData::read(&mut reader), Err(ReaderError::InvalidValue)));
+    }
+
+    #[test]
+    fn test_signature_is_domain_separated() {
+        let pair = KeyPair::new();
+        let data = TransactionData::Normal(vec![Tx { amount: 1000, to: KeyPair::new().get_public_key().clone() }]);
+        let mut tx = Transaction::new(pair.get_public_key().clone(), TransactionVariant::Normal { nonce: 0, fee: 1000, data });
+        tx.sign(&pair);
+        assert!(tx.verify_signature().unwrap());
+        assert_eq!(tx.hash_for_signing(), hash_with_prefix(TX_SIGN_PREFIX, &tx.to_bytes()));
+        assert_ne!(tx.hash_for_signing(), tx.hash());
+
+        // a signature of the tx hash without the prefix is rejected
+        tx.set_signature(pair.sign(tx.hash().as_bytes()));
+        assert!(!tx.verify_signature().unwrap());
+    }
+
+    #[test]
+    fn test_signature_cache_hit_skips_verification() {
+        let pair = KeyPair::new();
+        let data = TransactionData::Normal(vec![Tx { amount: 1000, to: KeyPair::new().get_public_key().clone() }]);
+        let mut tx = Transaction::new(pair.get_public_key().clone(), TransactionVariant::Normal { nonce: 0, fee: 1000, data });
+        tx.sign(&pair);
+        let cache = SignatureCache::new(16);
+        assert!(tx.verify_signature_with_cache(&cache).unwrap());
+        assert_eq!(cache.len(), 1);
+
+        // a cached result is returned as is, the signature isn't verified again
+        let key = SignatureCacheKey::new(tx.get_owner(), &tx.hash_for_signing(), tx.get_signature().unwrap());
+        cache.insert(key, false);
+        assert!(!tx.verify_signature_with_cache(&cache).unwrap());
+        assert!(tx.verify_signature().unwrap());
+
+        // another signature for the same tx doesn't use this entry
+        tx.set_signature(pair.sign(tx.hash().as_bytes()));
+        assert!(!tx.verify_signature_with_cache(&cache).unwrap());
+        assert_eq!(cache.len(), 2);
+    }
+
+    #[test]
+    fn test_empty_extension_is_rejected() {
+        // same tx as the original format but with the extended id: a second encoding of the same tx
+        let tx = create_tx();
+        let mut bytes = tx.to_bytes();
+        bytes[32] = EXTENDED_NORMAL_ID;
+        bytes.extend([0, 0, 0]); // no input, no expiration, no data
+        let mut reader = Reader::new(&bytes);
+        assert!(matches!(Transaction::read(&mut reader), Err(ReaderError::InvalidValue)));
+    }
+
+    #[test]
+    fn test_extended_fields_only_for_normal_variant() {
+        let mut tx = Transaction::new(KeyPair::new().get_public_key().clone(), TransactionVariant::Registration);
+        assert!(tx.add_input(Hash::zero(), 0).is_err());
+        assert!(tx.set_expire_at_height(Some(1)).is_err());
+        assert!(tx.set_data(Some(Vec::new())).is_err());
+        round_trip(&tx);
+    }
+
+    #[test]
+    fn test_max_inputs() {
+        let mut tx = create_tx();
+        for i in 0..MAX_TRANSACTION_INPUTS {
+            tx.add_input(Hash::zero(), i as u32).unwrap();
+        }
+        assert!(matches!(tx.add_input(Hash::zero(), 0), Err(BlockchainError::TooManyInputs(_, _))));
+        round_trip(&tx);
+
+        // one input too many in the count
+        let mut bytes = tx.to_bytes();
+        let count_offset = bytes.len() - MAX_TRANSACTION_INPUTS * 36 - 3;
+        assert_eq!(bytes[count_offset] as usize, MAX_TRANSACTION_INPUTS);
+        bytes[count_offset] += 1;
+        let mut reader = Reader::new(&bytes);
+        assert!(Transaction::read(&mut reader).is_err());
+    }
+
+    #[test]
+    fn test_data_size() {
+        let mut tx = create_tx();
+        tx.set_data(Some(Vec::new())).unwrap();
+        assert!(tx.is_extended());
+        assert_eq!(round_trip(&tx).get_data(), Some(&Vec::new()));
+
+        tx.set_data(Some(vec![0xAB; MAX_TRANSACTION_DATA_SIZE])).unwrap();
+        assert_eq!(round_trip(&tx).get_data().map(|data| data.len()), Some(MAX_TRANSACTION_DATA_SIZE));
+
+        assert!(matches!(tx.set_data(Some(vec![0; MAX_TRANSACTION_DATA_SIZE + 1])), Err(BlockchainError::TransactionDataTooLarge(_, _))));
+        // over-length data written by hand is rejected when reading
+        let mut bytes = tx.to_bytes();
+        let len = bytes.len();
+        bytes.truncate(len - MAX_TRANSACTION_DATA_SIZE - 2);
+        let mut writer = Writer::new();
+        writer.write_var_bytes(&vec![0; MAX_TRANSACTION_DATA_SIZE + 1]);
+        bytes.extend(writer.bytes());
+        let mut reader = Reader::new(&bytes);
+        assert!(Transaction::read(&mut reader).is_err());
+    }
+
+    #[test]
+    fn test_expiration() {
+        let mut tx = create_tx();
+        assert!(!tx.is_expired(u64::MAX));
+        tx.set_expire_at_height(Some(10)).unwrap();
+        assert!(!tx.is_expired(9));
+        assert!(tx.is_expired(10));
+        assert!(tx.is_expired(11));
+    }
+}
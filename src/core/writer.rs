@@ -1,4 +1,6 @@
 use crate::crypto::hash::Hash;
+use alloc::string::String;
+use alloc::vec::Vec;
 
 pub struct Writer {
     bytes: Vec<u8>
@@ -42,6 +44,29 @@ impl Writer {
         self.bytes.extend(value.to_be_bytes());
     }
 
+    pub fn write_i32(&mut self, value: &i32) {
+        self.bytes.extend(value.to_be_bytes());
+    }
+
+    pub fn write_i64(&mut self, value: &i64) {
+        self.bytes.extend(value.to_be_bytes());
+    }
+
+    // LEB128 encoding: 7 bits per byte, high bit set if more bytes follow
+    pub fn write_varint(&mut self, mut value: u64) {
+        while value >= 0x80 {
+            self.bytes.push((value as u8 & 0x7F) | 0x80);
+            value >>= 7;
+        }
+        self.bytes.push(value as u8);
+    }
+
+    // varint length followed by the bytes
+    pub fn write_var_bytes(&mut self, bytes: &[u8]) {
+        self.write_varint(bytes.len() as u64);
+        self.bytes.extend(bytes);
+    }
+
     pub fn write_string(&mut self, value: &String) {
         self.bytes.push(value.len() as u8);
         self.bytes.extend(value.as_bytes());
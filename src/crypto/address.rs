@@ -5,20 +5,50 @@ use std::fmt::{Display, Formatter};
 use crate::core::error::BlockchainError;
 use crate::core::reader::{Reader, ReaderError};
 use crate::core::serializer::Serializer;
-use crate::config::PREFIX_ADDRESS;
+use crate::config::{PREFIX_ADDRESS, TESTNET_PREFIX_ADDRESS};
 use crate::core::writer::Writer;
 use super::bech32::{Bech32Error, encode, convert_bits, decode};
 use super::key::PublicKey;
 use serde::de::Error as SerdeError;
 
-const PAYMENT_ID_SIZE: usize = 8; // 8 bytes for paymentID
+pub const PAYMENT_ID_SIZE: usize = 8; // 8 bytes for paymentID
+// first byte of the address binary layout, to change the encoding without ambiguity
+pub const ADDRESS_VERSION: u8 = 0;
 
+// payment id and requested amount are independent, an address can have both, either, or neither
 pub enum AddressType {
     Normal,
     PaymentId([u8; PAYMENT_ID_SIZE]),
+    Amount(u64), // merchant request to pay exactly this amount
+    PaymentIdWithAmount([u8; PAYMENT_ID_SIZE], u64),
     // TODO add custom variables
 }
 
+impl AddressType {
+    pub fn new(payment_id: Option<[u8; PAYMENT_ID_SIZE]>, amount: Option<u64>) -> Self {
+        match (payment_id, amount) {
+            (None, None) => AddressType::Normal,
+            (Some(id), None) => AddressType::PaymentId(id),
+            (None, Some(amount)) => AddressType::Amount(amount),
+            (Some(id), Some(amount)) => AddressType::PaymentIdWithAmount(id, amount)
+        }
+    }
+
+    pub fn get_payment_id(&self) -> Option<&[u8; PAYMENT_ID_SIZE]> {
+        match self {
+            AddressType::PaymentId(id) | AddressType::PaymentIdWithAmount(id, _) => Some(id),
+            _ => None
+        }
+    }
+
+    pub fn get_amount(&self) -> Option<u64> {
+        match self {
+            AddressType::Amount(amount) | AddressType::PaymentIdWithAmount(_, amount) => Some(*amount),
+            _ => None
+        }
+    }
+}
+
 pub struct Address<'a> {
     mainnet: bool,
     addr_type: AddressType,
@@ -46,6 +76,15 @@ impl<'a> Address<'a> {
         &self.addr_type
     }
 
+    pub fn get_payment_id(&self) -> Option<&[u8; PAYMENT_ID_SIZE]> {
+        self.addr_type.get_payment_id()
+    }
+
+    // amount requested by the owner of this address
+    pub fn get_amount(&self) -> Option<u64> {
+        self.addr_type.get_amount()
+    }
+
     pub fn is_normal(&self) -> bool {
         match self.addr_type {
             AddressType::Normal => true,
@@ -53,21 +92,44 @@ impl<'a> Address<'a> {
         }
     }
 
+    pub fn is_mainnet(&self) -> bool {
+        self.mainnet
+    }
+
+    fn get_prefix(mainnet: bool) -> &'static str {
+        if mainnet {
+            PREFIX_ADDRESS
+        } else {
+            TESTNET_PREFIX_ADDRESS
+        }
+    }
+
     pub fn as_string(&self) -> Result<String, Bech32Error> {
         let bits = convert_bits(&self.to_bytes(), 8, 5, true)?;
-        let result = encode(PREFIX_ADDRESS.to_owned(), &bits)?;
+        let result = encode(Self::get_prefix(self.mainnet).to_owned(), &bits)?;
         Ok(result)
     }
 
     pub fn from_string(address: &String) -> Result<Self, BlockchainError> {
         let (hrp, decoded) = decode(address)?;
-        if hrp != PREFIX_ADDRESS {
+        if hrp != PREFIX_ADDRESS && hrp != TESTNET_PREFIX_ADDRESS {
             return Err(BlockchainError::ErrorOnBech32(Bech32Error::InvalidPrefix(hrp)))
         }
 
         let bits = convert_bits(&decoded, 5, 8, false)?;
+        // an address from a newer format must not be parsed with the current layout
+        match bits.first() {
+            Some(&ADDRESS_VERSION) => {},
+            Some(version) => return Err(BlockchainError::UnsupportedAddressVersion(*version)),
+            None => return Err(BlockchainError::ErrorOnReader(ReaderError::InvalidSize))
+        };
+
         let mut reader = Reader::new(&bits);
         let addr = Address::read(&mut reader)?;
+        // prefix must match the network of the address
+        if hrp != Self::get_prefix(addr.mainnet) {
+            return Err(BlockchainError::ErrorOnBech32(Bech32Error::InvalidPrefix(hrp)))
+        }
         Ok(addr)
     }
 }
@@ -81,6 +143,15 @@ impl Serializer for AddressType {
             AddressType::PaymentId(id) => {
                 writer.write_u8(1);
                 writer.write_bytes(id);
+            },
+            AddressType::Amount(amount) => {
+                writer.write_u8(2);
+                writer.write_u64(amount);
+            },
+            AddressType::PaymentIdWithAmount(id, amount) => {
+                writer.write_u8(3);
+                writer.write_bytes(id);
+                writer.write_u64(amount);
             }
         };
     }
@@ -91,7 +162,12 @@ impl Serializer for AddressType {
             1 => {
                 let id: [u8; PAYMENT_ID_SIZE] = reader.read_bytes(PAYMENT_ID_SIZE)?;
                 AddressType::PaymentId(id)
-            }
+            },
+            2 => AddressType::Amount(reader.read_u64()?),
+            3 => {
+                let id: [u8; PAYMENT_ID_SIZE] = reader.read_bytes(PAYMENT_ID_SIZE)?;
+                AddressType::PaymentIdWithAmount(id, reader.read_u64()?)
+            },
             _ => return Err(ReaderError::InvalidValue)
         };
         Ok(_type)
@@ -100,12 +176,17 @@ impl Serializer for AddressType {
 
 impl<'a> Serializer for Address<'a> {
     fn write(&self, writer: &mut Writer) {
+        writer.write_u8(ADDRESS_VERSION);
         writer.write_bool(&self.mainnet);
         self.addr_type.write(writer);
         self.pub_key.write(writer);
     }
 
     fn read(reader: &mut Reader) -> Result<Address<'a>, ReaderError> {
+        if reader.read_u8()? != ADDRESS_VERSION {
+            return Err(ReaderError::InvalidValue)
+        }
+
         let mainnet = match reader.read_u8()? {
             0 => false,
             1 => true,
@@ -143,4 +224,44 @@ impl Display for Address<'_> {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         write!(f, "{}", self.as_string().unwrap())
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::key::KeyPair;
+
+    #[test]
+    fn test_payment_id_and_amount_round_trip() {
+        let pair = KeyPair::new();
+        let id = [7u8; PAYMENT_ID_SIZE];
+        for (payment_id, amount) in vec![(None, None), (Some(id), None), (None, Some(1500)), (Some(id), Some(u64::MAX))] {
+            let address = Address::new(true, AddressType::new(payment_id, amount), Cow::Borrowed(pair.get_public_key()));
+            let decoded = Address::from_string(&address.to_string()).unwrap();
+            assert_eq!(decoded.get_payment_id(), payment_id.as_ref());
+            assert_eq!(decoded.get_amount(), amount);
+            assert_eq!(decoded.is_normal(), payment_id.is_none() && amount.is_none());
+            assert!(decoded.is_mainnet());
+            assert_eq!(decoded.get_public_key(), pair.get_public_key());
+        }
+    }
+
+    // encode raw address bytes as a bech32 string, without any check on its content
+    fn encode_bytes(bytes: &[u8]) -> String {
+        encode(PREFIX_ADDRESS.to_owned(), &convert_bits(bytes, 8, 5, true).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_address_version() {
+        let pair = KeyPair::new();
+        let address = Address::new(true, AddressType::Normal, Cow::Borrowed(pair.get_public_key()));
+        let mut bytes = address.to_bytes();
+        assert_eq!(bytes[0], ADDRESS_VERSION);
+        assert!(Address::from_string(&encode_bytes(&bytes)).is_ok());
+
+        // a future version is rejected with its version, even if the rest is still readable
+        bytes[0] = ADDRESS_VERSION + 1;
+        assert!(matches!(Address::from_string(&encode_bytes(&bytes)), Err(BlockchainError::UnsupportedAddressVersion(v)) if v == ADDRESS_VERSION + 1));
+        assert!(Address::read(&mut Reader::new(&bytes)).is_err());
+    }
 }
\ No newline at end of file
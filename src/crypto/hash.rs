@@ -1,15 +1,46 @@
 use crate::core::reader::{ReaderError, Reader};
 use crate::core::serializer::Serializer;
 use crate::core::writer::Writer;
-use std::fmt::{Display, Error, Formatter};
+use alloc::string::String;
+use core::fmt::{Display, Error, Formatter};
 use serde::de::Error as SerdeError;
 use serde::{Deserialize, Serialize};
 use sha2::{Sha256, Digest};
-use std::convert::TryInto;
-use std::hash::Hasher;
+use core::convert::TryInto;
+use core::hash::Hasher;
 
 pub const HASH_SIZE: usize = 32; // 32 bytes / 256 bits
 
+// algorithm used to hash blocks and transactions
+// peers must use the same one to validate each other's blocks
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HashAlgorithm {
+    Sha256
+}
+
+impl HashAlgorithm {
+    pub fn from_id(id: u8) -> Option<Self> {
+        match id {
+            0 => Some(HashAlgorithm::Sha256),
+            _ => None
+        }
+    }
+
+    pub fn get_id(&self) -> u8 {
+        match self {
+            HashAlgorithm::Sha256 => 0
+        }
+    }
+}
+
+impl Display for HashAlgorithm {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+        match self {
+            HashAlgorithm::Sha256 => write!(f, "SHA-256")
+        }
+    }
+}
+
 #[derive(Eq, Clone, Debug)]
 pub struct Hash([u8; HASH_SIZE]);
 
@@ -18,6 +49,16 @@ impl Hash {
         Hash(bytes)
     }
 
+    // build a Hash from a slice, its length must be exactly HASH_SIZE
+    pub fn from_slice(bytes: &[u8]) -> Result<Self, ReaderError> {
+        if bytes.len() != HASH_SIZE {
+            return Err(ReaderError::InvalidSize)
+        }
+
+        let bytes: [u8; HASH_SIZE] = bytes.try_into().map_err(|_| ReaderError::InvalidSize)?;
+        Ok(Hash::new(bytes))
+    }
+
     pub const fn zero() -> Self {
         Hash::new([0; HASH_SIZE])
     }
@@ -26,6 +67,14 @@ impl Hash {
         Hash::new([u8::MAX; HASH_SIZE])
     }
 
+    pub fn is_zero(&self) -> bool {
+        self.0.iter().all(|v| *v == 0)
+    }
+
+    pub fn is_max(&self) -> bool {
+        self.0.iter().all(|v| *v == u8::MAX)
+    }
+
     pub fn as_bytes(&self) -> &[u8; HASH_SIZE] {
         &self.0
     }
@@ -56,7 +105,7 @@ impl PartialEq for Hash {
     }
 }
 
-impl std::hash::Hash for Hash {
+impl core::hash::Hash for Hash {
     fn hash<H: Hasher>(&self, state: &mut H) {
         self.0.hash(state);
     }
@@ -97,6 +146,15 @@ pub trait Hashable: Serializer {
     }
 }
 
+// hash the value with a domain separation prefix
+pub fn hash_with_prefix(prefix: &[u8], value: &[u8]) -> Hash {
+    let mut hasher = Sha256::new();
+    hasher.update(prefix);
+    hasher.update(value);
+    let result: [u8; HASH_SIZE] = hasher.finalize()[..].try_into().unwrap();
+    Hash(result)
+}
+
 pub fn hash(value: &[u8]) -> Hash {
     let mut hasher = Sha256::new();
     hasher.update(value);
@@ -1,12 +1,16 @@
 use crate::core::reader::{Reader, ReaderError};
 use crate::core::serializer::Serializer;
+use crate::core::async_serializer::{AsyncSerializer, AsyncReader};
 use crate::core::writer::Writer;
 use super::address::{Address, AddressType};
 use super::hash::Hash;
 use std::borrow::Cow;
-use std::fmt::{Display, Error, Formatter};
-use rand::{rngs::OsRng, RngCore};
+use std::fmt::{Debug, Display, Error, Formatter};
+use rand::{rngs::OsRng, CryptoRng, RngCore};
 use std::hash::Hasher;
+use tokio::io::AsyncRead;
+use thiserror::Error;
+use serde::de::Error as SerdeError;
 
 pub const KEY_LENGTH: usize = 32;
 pub const SIGNATURE_LENGTH: usize = 64;
@@ -23,6 +27,14 @@ pub struct KeyPair {
     private_key: PrivateKey
 }
 
+#[derive(Error, Debug)]
+pub enum CryptoError {
+    #[error("Couldn't gather entropy from the OS: {}", _0)]
+    RngError(#[from] rand::Error),
+    #[error(transparent)]
+    InvalidKey(#[from] ed25519_dalek::SignatureError)
+}
+
 impl PublicKey {
     pub fn verify_signature(&self, hash: &Hash, signature: &Signature) -> bool {
         use ed25519_dalek::Verifier;
@@ -51,6 +63,15 @@ impl Serializer for PublicKey {
     }
 }
 
+impl AsyncSerializer for PublicKey {
+    async fn read_async<R: AsyncRead + Unpin + Send>(reader: &mut AsyncReader<R>) -> Result<Self, ReaderError> {
+        match ed25519_dalek::PublicKey::from_bytes(&reader.read_bytes_32().await?) {
+            Ok(v) => Ok(PublicKey(v)),
+            Err(_) => Err(ReaderError::ErrorTryInto)
+        }
+    }
+}
+
 impl PartialEq for PublicKey {
     fn eq(&self, other: &Self) -> bool {
         self.0 == other.0
@@ -72,6 +93,19 @@ impl serde::Serialize for PublicKey {
     }
 }
 
+// a public key is deserialized from its normal address, the same format used to serialize it
+impl<'a> serde::Deserialize<'a> for PublicKey {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where D: serde::Deserializer<'a> {
+        let value = String::deserialize(deserializer)?;
+        let address = Address::from_string(&value).map_err(SerdeError::custom)?;
+        if !address.is_normal() {
+            return Err(SerdeError::custom("Expected a normal address for public key"))
+        }
+        Ok(address.to_public_key())
+    }
+}
+
 impl Display for PublicKey {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
         write!(f, "{}", &self.to_address())
@@ -85,18 +119,38 @@ impl PrivateKey {
     }
 }
 
+// never print the secret bytes, even in debug mode
+impl Debug for PrivateKey {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+        write!(f, "PrivateKey(****)")
+    }
+}
+
 impl KeyPair {
+    // generate a new random key pair, panics if the OS can't provide entropy
+    // prefer try_new when the error can be reported
     pub fn new() -> Self {
-        let mut csprng = OsRng {};
+        Self::try_new().unwrap()
+    }
+
+    // generate a new random key pair
+    // the key is never built from partially filled bytes: any failure of the OS RNG is returned as an error
+    pub fn try_new() -> Result<Self, CryptoError> {
+        Self::new_with_rng(&mut OsRng {})
+    }
+
+    // generate a new key pair from the given RNG, a seeded one makes the generation reproducible
+    // CryptoRng is required so a weak RNG can't be used by mistake
+    pub fn new_with_rng<R: RngCore + CryptoRng>(rng: &mut R) -> Result<Self, CryptoError> {
         let mut bytes = [0u8; KEY_LENGTH];
-        csprng.fill_bytes(&mut bytes);
-        let secret_key: ed25519_dalek::SecretKey = ed25519_dalek::SecretKey::from_bytes(&bytes).unwrap();
+        rng.try_fill_bytes(&mut bytes)?;
+        let secret_key: ed25519_dalek::SecretKey = ed25519_dalek::SecretKey::from_bytes(&bytes)?;
         let public_key: ed25519_dalek::PublicKey = (&secret_key).into();
 
-        KeyPair {
+        Ok(KeyPair {
             public_key: PublicKey(public_key),
             private_key: PrivateKey(secret_key)
-        }
+        })
     }
 
     pub fn from_keys(public_key: PublicKey, private_key: PrivateKey) -> Self {
@@ -119,6 +173,10 @@ impl Signature {
     pub fn to_hex(&self) -> String {
         hex::encode(self.0)
     }
+
+    pub fn to_bytes(&self) -> [u8; SIGNATURE_LENGTH] {
+        self.0.to_bytes()
+    }
 }
 
 impl Serializer for Signature {
@@ -157,8 +215,108 @@ impl serde::Serialize for Signature {
     }
 }
 
+impl<'a> serde::Deserialize<'a> for Signature {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where D: serde::Deserializer<'a> {
+        let hex = String::deserialize(deserializer)?;
+        if hex.len() != SIGNATURE_LENGTH * 2 {
+            return Err(SerdeError::custom("Invalid hex length"))
+        }
+
+        let decoded_hex = hex::decode(hex).map_err(SerdeError::custom)?;
+        let signature = ed25519_dalek::Signature::from_bytes(&decoded_hex).map_err(SerdeError::custom)?;
+        Ok(Signature(signature))
+    }
+}
+
 impl Display for Signature {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
         write!(f, "{}", &self.to_hex())
     }
+}
+
+impl Debug for KeyPair {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+        f.debug_struct("KeyPair")
+            .field("address", &self.public_key.to_address().to_string())
+            .field("private_key", &self.private_key)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::{Serialize, Deserialize};
+    use std::collections::HashMap;
+
+    #[derive(Serialize, Deserialize)]
+    struct SignedBlockInfo {
+        hash: Hash,
+        miner: PublicKey,
+        signature: Signature
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct BlockResponse {
+        height: u64,
+        #[serde(flatten)]
+        info: SignedBlockInfo,
+        rewards: HashMap<String, PublicKey>
+    }
+
+    #[test]
+    fn test_debug_never_prints_secret() {
+        let secret = [0xabu8; KEY_LENGTH];
+        let secret_key = ed25519_dalek::SecretKey::from_bytes(&secret).unwrap();
+        let public_key = PublicKey((&secret_key).into());
+        let pair = KeyPair::from_keys(public_key, PrivateKey(secret_key));
+
+        let output = format!("{:?} {:#?} {:?}", pair, pair, pair.private_key);
+        assert!(output.contains("PrivateKey(****)"));
+        assert!(output.contains(&pair.get_public_key().to_address().to_string()));
+        // neither as hex nor as a byte array
+        assert!(!output.contains(&hex::encode(&secret[..4])));
+        assert!(!output.contains("171, 171"));
+    }
+
+    #[test]
+    fn test_serde_composed_round_trip() {
+        let pair = KeyPair::new();
+        let hash = Hash::new([3u8; 32]);
+        let mut rewards = HashMap::new();
+        rewards.insert("dev".to_owned(), KeyPair::new().get_public_key().clone());
+        let response = BlockResponse {
+            height: 10,
+            info: SignedBlockInfo {
+                hash: hash.clone(),
+                miner: pair.get_public_key().clone(),
+                signature: pair.sign(hash.as_bytes())
+            },
+            rewards
+        };
+
+        let json = serde_json::to_string(&response).unwrap();
+        // flattened fields are at the same level, all written as strings
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert!(value["hash"].is_string() && value["miner"].is_string() && value["signature"].is_string());
+
+        let decoded: BlockResponse = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded.height, 10);
+        assert_eq!(decoded.info.hash, hash);
+        assert_eq!(decoded.info.miner, *pair.get_public_key());
+        assert_eq!(decoded.info.signature.to_bytes(), response.info.signature.to_bytes());
+        assert_eq!(decoded.rewards, response.rewards);
+        assert_eq!(serde_json::to_string(&decoded).unwrap(), json);
+    }
+
+    #[test]
+    fn test_serde_rejects_invalid_values() {
+        assert!(serde_json::from_str::<Signature>("\"00\"").is_err());
+        assert!(serde_json::from_str::<Hash>("\"zz\"").is_err());
+        // an integrated address is not a public key
+        let pair = KeyPair::new();
+        let address = Address::new(true, AddressType::PaymentId([1u8; 8]), Cow::Borrowed(pair.get_public_key()));
+        assert!(serde_json::from_str::<PublicKey>(&format!("\"{}\"", address)).is_err());
+    }
 }
\ No newline at end of file
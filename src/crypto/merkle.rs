@@ -0,0 +1,67 @@
+use super::hash::{Hash, HASH_SIZE, hash};
+
+// compute the merkle root of a list of hashes
+// an odd leaf at a level is promoted as is to the next level, an empty list gives a zero hash
+// it is never paired with itself: [a, b, c] and [a, b, c, c] would have the same root
+pub fn merkle_root(hashes: &[Hash]) -> Hash {
+    if hashes.is_empty() {
+        return Hash::zero()
+    }
+
+    let mut level: Vec<Hash> = hashes.to_vec();
+    while level.len() > 1 {
+        let mut next = Vec::with_capacity(level.len().div_ceil(2));
+        for pair in level.chunks(2) {
+            match pair {
+                [left, right] => {
+                    let mut bytes = [0u8; HASH_SIZE * 2];
+                    bytes[0..HASH_SIZE].copy_from_slice(left.as_bytes());
+                    bytes[HASH_SIZE..].copy_from_slice(right.as_bytes());
+                    next.push(hash(&bytes));
+                },
+                _ => next.push(pair[0].clone())
+            }
+        }
+        level = next;
+    }
+
+    level.remove(0)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hash_pair(left: &Hash, right: &Hash) -> Hash {
+        let mut bytes = [0u8; HASH_SIZE * 2];
+        bytes[0..HASH_SIZE].copy_from_slice(left.as_bytes());
+        bytes[HASH_SIZE..].copy_from_slice(right.as_bytes());
+        hash(&bytes)
+    }
+
+    fn leaves(n: u8) -> Vec<Hash> {
+        (0..n).map(|i| hash(&[i])).collect()
+    }
+
+    #[test]
+    fn test_empty_and_single_leaf() {
+        assert_eq!(merkle_root(&[]), Hash::zero());
+        let leaves = leaves(1);
+        assert_eq!(merkle_root(&leaves), leaves[0]);
+    }
+
+    #[test]
+    fn test_odd_leaf_is_promoted() {
+        let l = leaves(3);
+        assert_eq!(merkle_root(&l), hash_pair(&hash_pair(&l[0], &l[1]), &l[2]));
+    }
+
+    #[test]
+    fn test_duplicated_last_leaf_changes_root() {
+        let mut l = leaves(3);
+        let root = merkle_root(&l);
+        l.push(l[2].clone());
+        assert_ne!(merkle_root(&l), root);
+    }
+}
@@ -1,4 +1,11 @@
 pub mod hash;
+#[cfg(feature = "std")]
 pub mod key;
+#[cfg(feature = "std")]
 pub mod bech32;
-pub mod address;
\ No newline at end of file
+#[cfg(feature = "std")]
+pub mod address;
+#[cfg(feature = "std")]
+pub mod merkle;
+#[cfg(feature = "std")]
+pub mod signature_cache;
\ No newline at end of file
@@ -0,0 +1,149 @@
+use super::key::{PublicKey, Signature, KEY_LENGTH, SIGNATURE_LENGTH};
+use super::hash::Hash;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+
+// the key contains everything used by the verification (key, message hash and full signature)
+// so a cached result can't be reused for another signature or message
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct SignatureCacheKey {
+    public_key: [u8; KEY_LENGTH],
+    message: Hash,
+    signature: [u8; SIGNATURE_LENGTH]
+}
+
+impl SignatureCacheKey {
+    pub fn new(public_key: &PublicKey, message: &Hash, signature: &Signature) -> Self {
+        Self {
+            public_key: *public_key.as_bytes(),
+            message: message.clone(),
+            signature: signature.to_bytes()
+        }
+    }
+}
+
+struct CacheEntries {
+    results: HashMap<SignatureCacheKey, (bool, u64)>, // verification result and its last use
+    order: VecDeque<(SignatureCacheKey, u64)>, // keys by last use, entries with an old counter are skipped
+    counter: u64
+}
+
+// LRU cache of signature verification results
+// prevent verifying again the same signature (tx in mempool then in a block, reorg...)
+pub struct SignatureCache {
+    capacity: usize,
+    entries: Mutex<CacheEntries>
+}
+
+impl SignatureCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: Mutex::new(CacheEntries {
+                results: HashMap::with_capacity(capacity),
+                order: VecDeque::with_capacity(capacity),
+                counter: 0
+            })
+        }
+    }
+
+    pub fn get(&self, key: &SignatureCacheKey) -> Option<bool> {
+        let mut entries = self.entries.lock().ok()?;
+        entries.counter += 1;
+        let counter = entries.counter;
+        let valid = match entries.results.get_mut(key) {
+            Some((valid, last_use)) => {
+                *last_use = counter;
+                *valid
+            },
+            None => return None
+        };
+        entries.order.push_back((key.clone(), counter));
+        Self::compact(&mut entries, self.capacity);
+        Some(valid)
+    }
+
+    pub fn insert(&self, key: SignatureCacheKey, valid: bool) {
+        if self.capacity == 0 {
+            return
+        }
+
+        let mut entries = match self.entries.lock() {
+            Ok(entries) => entries,
+            Err(_) => return
+        };
+        entries.counter += 1;
+        let counter = entries.counter;
+        entries.results.insert(key.clone(), (valid, counter));
+        entries.order.push_back((key, counter));
+
+        // delete the least recently used entries
+        while entries.results.len() > self.capacity {
+            let (key, last_use) = match entries.order.pop_front() {
+                Some(v) => v,
+                None => break
+            };
+            if entries.results.get(&key).map(|(_, v)| *v) == Some(last_use) {
+                entries.results.remove(&key);
+            }
+        }
+        Self::compact(&mut entries, self.capacity);
+    }
+
+    // drop the outdated keys in order list so it can't grow forever
+    fn compact(entries: &mut CacheEntries, capacity: usize) {
+        if entries.order.len() > capacity * 2 {
+            let CacheEntries { results, order, .. } = entries;
+            order.retain(|(key, last_use)| results.get(key).map(|(_, v)| *v) == Some(*last_use));
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.lock().map(|entries| entries.results.len()).unwrap_or(0)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn get_capacity(&self) -> usize {
+        self.capacity
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::key::KeyPair;
+
+    fn create_key(message: u8) -> SignatureCacheKey {
+        let pair = KeyPair::new();
+        let hash = Hash::new([message; 32]);
+        SignatureCacheKey::new(pair.get_public_key(), &hash, &pair.sign(hash.as_bytes()))
+    }
+
+    #[test]
+    fn test_least_recently_used_is_evicted() {
+        let cache = SignatureCache::new(2);
+        let (a, b, c) = (create_key(1), create_key(2), create_key(3));
+        cache.insert(a.clone(), true);
+        cache.insert(b.clone(), false);
+        // a is used again, b becomes the oldest entry
+        assert_eq!(cache.get(&a), Some(true));
+        cache.insert(c.clone(), true);
+
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.get(&a), Some(true));
+        assert_eq!(cache.get(&b), None);
+        assert_eq!(cache.get(&c), Some(true));
+    }
+
+    #[test]
+    fn test_disabled_cache() {
+        let cache = SignatureCache::new(0);
+        let key = create_key(1);
+        cache.insert(key.clone(), true);
+        assert!(cache.is_empty());
+        assert_eq!(cache.get(&key), None);
+    }
+}
@@ -1,6 +1,7 @@
 use crate::core::reader::{Reader, ReaderError};
 use crate::config::COIN_VALUE;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use std::sync::OnceLock;
 use std::net::{SocketAddr, IpAddr, Ipv4Addr, Ipv6Addr};
 
 // return timestamp in seconds
@@ -17,6 +18,30 @@ pub fn get_current_timestamp() -> u128 {
     time.as_millis()
 }
 
+// monotonic clock, only meaningful to compute durations as it's not affected by system clock changes
+// starts at 1s on first call, so 0 is never a valid time: it's used as the "never set" value
+// it must never be compared to a wall-clock timestamp
+pub fn get_monotonic_millis() -> u64 {
+    static START: OnceLock<Instant> = OnceLock::new();
+    START.get_or_init(Instant::now).elapsed().as_millis() as u64 + 1000
+}
+
+// monotonic clock in seconds, see get_monotonic_millis
+pub fn get_monotonic_time() -> u64 {
+    get_monotonic_millis() / 1000
+}
+
+// true if at least `delay` seconds passed since `since` on the monotonic clock, or if `since` was never set (0)
+// durations are never computed from wall-clock times, a clock correction could move them backward
+pub fn has_elapsed(since: u64, delay: u64) -> bool {
+    has_elapsed_at(since, delay, get_monotonic_time())
+}
+
+// a `since` after `now` is never considered elapsed
+fn has_elapsed_at(since: u64, delay: u64, now: u64) -> bool {
+    since == 0 || now.saturating_sub(since) >= delay
+}
+
 pub fn format_coin(value: u64) -> String {
     format!("{}", value as f64 / COIN_VALUE as f64)
 }
@@ -38,6 +63,37 @@ pub fn ip_to_bytes(ip: &SocketAddr) -> Vec<u8> {
     bytes
 }
 
+// canonical form of an address so the same host is always seen the same way on dual-stack setups
+// an IPv4-mapped IPv6 address ([::ffff:1.2.3.4]:8080) is converted to its IPv4 form (1.2.3.4:8080)
+pub fn canonical_addr(addr: &SocketAddr) -> SocketAddr {
+    match addr.ip() {
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ipv4) => SocketAddr::new(IpAddr::V4(ipv4), addr.port()),
+            None => *addr
+        },
+        IpAddr::V4(_) => *addr
+    }
+}
+
+// canonical address as a string (IPv6 is always written in lowercase)
+pub fn canonical_addr_string(addr: &SocketAddr) -> String {
+    canonical_addr(addr).to_string()
+}
+
+// returns the subnet of an IP: /16 for IPv4 and /32 for IPv6
+pub fn get_ip_subnet(ip: &IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V4(addr) => {
+            let octets = addr.octets();
+            IpAddr::V4(Ipv4Addr::new(octets[0], octets[1], 0, 0))
+        },
+        IpAddr::V6(addr) => {
+            let segments = addr.segments();
+            IpAddr::V6(Ipv6Addr::new(segments[0], segments[1], 0, 0, 0, 0, 0, 0))
+        }
+    }
+}
+
 // bytes to ip
 pub fn ip_from_bytes(reader: &mut Reader) -> Result<SocketAddr, ReaderError> {
     let is_v6 = reader.read_bool()?;
@@ -59,5 +115,60 @@ pub fn ip_from_bytes(reader: &mut Reader) -> Result<SocketAddr, ReaderError> {
         IpAddr::V6(Ipv6Addr::new(a, b, c, d, e, f, g, h))
     };
     let port = reader.read_u16()?;
-    Ok(SocketAddr::new(ip, port))
+    Ok(canonical_addr(&SocketAddr::new(ip, port)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_monotonic_clock_is_never_zero() {
+        // a time set during the first second must not be taken for "never set"
+        let now = get_monotonic_time();
+        assert!(now >= 1);
+        assert!(!has_elapsed(now, 10));
+    }
+
+    #[test]
+    fn test_has_elapsed() {
+        assert!(has_elapsed_at(0, 10, 1));
+        assert!(!has_elapsed_at(1, 10, 1));
+        assert!(!has_elapsed_at(1, 10, 10));
+        assert!(has_elapsed_at(1, 10, 11));
+    }
+
+    #[test]
+    fn test_has_elapsed_with_clock_backward() {
+        // a time recorded after now never counts as elapsed, whatever the delay
+        assert!(!has_elapsed_at(100, 10, 90));
+        assert!(!has_elapsed_at(u64::MAX, 1, 1));
+        assert!(has_elapsed_at(100, 0, 90));
+    }
+
+    #[test]
+    fn test_canonical_addr() {
+        let ipv4: SocketAddr = "1.2.3.4:8080".parse().unwrap();
+        let mapped: SocketAddr = "[::ffff:1.2.3.4]:8080".parse().unwrap();
+        assert_ne!(mapped, ipv4);
+        assert_eq!(canonical_addr(&mapped), ipv4);
+        assert_eq!(canonical_addr(&ipv4), ipv4);
+        assert_eq!(canonical_addr_string(&mapped), "1.2.3.4:8080");
+
+        // a real IPv6 address is kept, and written in lowercase
+        let ipv6: SocketAddr = "[2001:DB8::FFFF:1]:8080".parse().unwrap();
+        assert_eq!(canonical_addr(&ipv6), ipv6);
+        assert_eq!(canonical_addr_string(&ipv6), "[2001:db8::ffff:1]:8080");
+        // IPv4-compatible (deprecated) addresses are not IPv4-mapped ones
+        let compatible: SocketAddr = "[::1.2.3.4]:8080".parse().unwrap();
+        assert_eq!(canonical_addr(&compatible), compatible);
+    }
+
+    #[test]
+    fn test_mapped_addr_read_as_ipv4() {
+        let mapped: SocketAddr = "[::ffff:1.2.3.4]:8080".parse().unwrap();
+        let bytes = ip_to_bytes(&mapped);
+        let addr = ip_from_bytes(&mut Reader::new(&bytes)).unwrap();
+        assert_eq!(addr, "1.2.3.4:8080".parse::<SocketAddr>().unwrap());
+    }
 }
\ No newline at end of file
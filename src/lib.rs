@@ -1,7 +1,16 @@
+#![cfg_attr(not(feature = "std"), no_std)]
+// Vec and String come from alloc so the serialization core also builds without std
+extern crate alloc;
+
+#[cfg(feature = "std")]
 pub mod globals;
+#[cfg(feature = "std")]
 pub mod config;
 pub mod crypto;
+#[cfg(feature = "std")]
 pub mod wallet;
 pub mod core;
+#[cfg(feature = "std")]
 pub mod p2p;
+#[cfg(feature = "std")]
 pub mod rpc;
\ No newline at end of file
@@ -0,0 +1,140 @@
+use crate::config::P2P_DEFAULT_HANDSHAKE_TIMEOUT;
+use crate::core::serializer::Serializer;
+use crate::crypto::hash::HashAlgorithm;
+use super::packet::handshake::{Handshake, CAPABILITY_PACKET_SEQUENCE};
+use super::connection::{Connection, ConnectionMessage, State};
+use super::peer_list::PeerList;
+use super::packet::Packet;
+use super::error::P2pError;
+use super::peer::Peer;
+use tokio::net::TcpStream;
+use tokio::time::timeout;
+use std::borrow::Cow;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use log::{debug, trace};
+
+// Connect to a single node and exchange the handshakes without running a P2pServer
+// used by tools (crawler, connectivity tester) to open a one-off connection
+// the peer returned is not listened, but packets queued with Peer::send_packet are written by a spawned task
+pub async fn connect(addr: SocketAddr, our_handshake: Handshake) -> Result<Arc<Peer>, P2pError> {
+    let stream = timeout(Duration::from_millis(800), TcpStream::connect(&addr)).await??; // allow maximum 800ms of latency
+    let mut connection = Connection::new(stream, addr);
+    connection.send_bytes(&Packet::Handshake(Cow::Borrowed(&our_handshake)).to_bytes()).await?;
+
+    let mut buf = [0u8; 1024];
+    let max_size = 1 + Handshake::max_serialized_size() as u32; // packet id + handshake
+    let packet = timeout(Duration::from_millis(P2P_DEFAULT_HANDSHAKE_TIMEOUT), connection.read_packet(&mut buf, max_size, false)).await??;
+    let handshake = match packet {
+        Packet::Handshake(h) => h.into_owned(),
+        _ => return Err(P2pError::ExpectedHandshake)
+    };
+    connection.set_state(State::Handshake);
+
+    // without a chain, the node is only checked against our own handshake
+    if handshake.get_network_id() != our_handshake.get_network_id() {
+        return Err(P2pError::InvalidNetworkID)
+    }
+
+    // genesis hash and hash algorithm are only known if both sides sent the handshake extension
+    if handshake.has_extension() && our_handshake.has_extension() {
+        if handshake.get_genesis_hash() != our_handshake.get_genesis_hash() {
+            return Err(P2pError::WrongChain { expected: our_handshake.get_genesis_hash().clone(), got: handshake.get_genesis_hash().clone() })
+        }
+
+        if handshake.get_hash_algorithm() != our_handshake.get_hash_algorithm() {
+            let ours = HashAlgorithm::from_id(our_handshake.get_hash_algorithm()).ok_or(P2pError::InvalidHandshake)?;
+            let theirs = match HashAlgorithm::from_id(handshake.get_hash_algorithm()) {
+                Some(algorithm) => algorithm.to_string(),
+                None => format!("unknown ({})", handshake.get_hash_algorithm())
+            };
+            return Err(P2pError::InvalidHashAlgorithm(theirs, ours))
+        }
+    }
+
+    if handshake.get_peer_id() == our_handshake.get_peer_id() {
+        return Err(P2pError::PeerIdAlreadyUsed(handshake.get_peer_id()))
+    }
+
+    if our_handshake.has_capability(CAPABILITY_PACKET_SEQUENCE) && handshake.has_capability(CAPABILITY_PACKET_SEQUENCE) {
+        debug!("Packet sequence enabled with {}", addr);
+        connection.enable_packet_sequence();
+    }
+
+    connection.set_state(State::Success);
+    let protocol_version = handshake.get_protocol_version().min(our_handshake.get_protocol_version());
+    let (peer, _) = handshake.create_peer(connection, protocol_version, true, false, PeerList::new());
+    let peer = Arc::new(peer);
+    tokio::spawn(write_loop(Arc::clone(&peer)));
+    Ok(peer)
+}
+
+// write the queued packets on the connection so the sequence number is inserted when enabled
+async fn write_loop(peer: Arc<Peer>) {
+    let mut rx = peer.get_connection().get_rx().lock().await;
+    while let Some(message) = rx.recv().await {
+        match message {
+            ConnectionMessage::Packet(bytes) => {
+                if let Err(e) = peer.get_connection().send_packet_bytes(&bytes).await {
+                    debug!("Error while sending packet to {}: {}", peer.get_connection().get_address(), e);
+                    break;
+                }
+            }
+            ConnectionMessage::Exit => {
+                trace!("Exit message received for peer {}", peer);
+                break;
+            }
+        }
+    }
+    rx.close();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::p2p::packet::handshake::HandshakeBuilder;
+    use crate::p2p::packet::PacketKind;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use std::convert::TryInto;
+
+    async fn read_frame(stream: &mut TcpStream) -> Vec<u8> {
+        let size = stream.read_u32().await.unwrap();
+        let mut bytes = vec![0u8; size as usize];
+        stream.read_exact(&mut bytes).await.unwrap();
+        bytes
+    }
+
+    #[tokio::test]
+    async fn test_queued_packets_are_sequenced() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let count = 5;
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            // the handshake is always sent without sequence number
+            let bytes = read_frame(&mut stream).await;
+            assert_eq!(Packet::peek_id(&bytes), Some(PacketKind::Handshake.get_id()));
+            let handshake = HandshakeBuilder::new(2, 2125).capabilities(CAPABILITY_PACKET_SEQUENCE).build().unwrap();
+            stream.write_all(&Packet::Handshake(Cow::Owned(handshake)).to_bytes()).await.unwrap();
+
+            let mut sequences = Vec::new();
+            for _ in 0..count {
+                let bytes = read_frame(&mut stream).await;
+                sequences.push(u64::from_be_bytes(bytes[0..8].try_into().unwrap()));
+            }
+            sequences
+        });
+
+        let handshake = HandshakeBuilder::new(1, 2126).capabilities(CAPABILITY_PACKET_SEQUENCE).build().unwrap();
+        let peer = connect(addr, handshake.clone()).await.unwrap();
+        assert!(peer.get_connection().is_packet_sequence_enabled());
+        for _ in 0..count {
+            peer.send_packet(Packet::Handshake(Cow::Borrowed(&handshake))).await.unwrap();
+        }
+
+        let sequences = server.await.unwrap();
+        assert_eq!(sequences, (0..count).collect::<Vec<u64>>());
+    }
+}
@@ -1,14 +1,15 @@
 use crate::core::serializer::Serializer;
-use crate::globals::get_current_time;
+use crate::globals::{get_current_time, canonical_addr, canonical_addr_string};
+use crate::config::{P2P_LOG_PACKET_BYTES_LIMIT, P2P_PACKET_SIZE_BUCKETS};
 use crate::core::reader::Reader;
 use super::error::P2pError;
-use super::packet::Packet;
-use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use super::packet::{Packet, PacketKind};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::Mutex as StdMutex;
 use std::net::SocketAddr;
-use tokio::net::TcpStream;
 use std::fmt::{Display, Error, Formatter};
 use tokio::sync::{mpsc, Mutex};
-use tokio::io::{AsyncWriteExt, AsyncReadExt};
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt, AsyncReadExt};
 use std::convert::TryInto;
 use bytes::Bytes;
 use log::{debug, warn};
@@ -23,6 +24,50 @@ pub type Rx = mpsc::UnboundedReceiver<ConnectionMessage>;
 
 type P2pResult<T> = std::result::Result<T, P2pError>;
 
+// any bidirectional byte stream, a TcpStream for a peer or an in-memory stream (tokio::io::duplex) in tests
+pub trait Stream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Stream for T {}
+
+type BoxedStream = Box<dyn Stream>;
+
+// Number of packets seen per size range, bucket i counts the packets up to 2^(i + 6) bytes
+// the last bucket also counts all bigger packets
+#[derive(Default)]
+pub struct PacketSizeHistogram {
+    buckets: [AtomicU64; P2P_PACKET_SIZE_BUCKETS]
+}
+
+impl PacketSizeHistogram {
+    pub fn new() -> Self {
+        Self {
+            buckets: Default::default()
+        }
+    }
+
+    pub fn get_bucket(size: usize) -> usize {
+        let bits = (usize::BITS - size.saturating_sub(1).leading_zeros()) as usize; // log2 rounded up
+        bits.saturating_sub(6).min(P2P_PACKET_SIZE_BUCKETS - 1)
+    }
+
+    // upper bound in bytes of a bucket, the last one has no limit
+    pub fn get_bucket_limit(bucket: usize) -> Option<usize> {
+        if bucket + 1 < P2P_PACKET_SIZE_BUCKETS {
+            Some(1 << (bucket + 6))
+        } else {
+            None
+        }
+    }
+
+    pub fn record(&self, size: usize) {
+        self.buckets[Self::get_bucket(size)].fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn get_counts(&self) -> Vec<u64> {
+        self.buckets.iter().map(|bucket| bucket.load(Ordering::Relaxed)).collect()
+    }
+}
+
 pub enum State {
     Pending, // connection is new, no handshake received
     Handshake, // handshake received, not checked
@@ -31,29 +76,47 @@ pub enum State {
 
 pub struct Connection {
     state: State,
-    stream: Mutex<TcpStream>, // Stream for read & write
+    stream: Mutex<BoxedStream>, // Stream for read & write
     addr: SocketAddr, // TCP Address
     tx: Mutex<Tx>, // Tx to send bytes
     rx: Mutex<Rx>, // Rx to read bytes to send
     bytes_in: AtomicUsize, // total bytes read
     bytes_out: AtomicUsize, // total bytes sent
+    interval_bytes_in: AtomicU64, // bytes read since the last call to take_interval_stats
+    interval_bytes_out: AtomicU64, // bytes sent since the last call to take_interval_stats
+    packet_sizes_in: PacketSizeHistogram, // size (with its prefix) of each packet read
+    packet_sizes_out: PacketSizeHistogram, // size (with its prefix) of each packet sent
     connected_on: u64,
     closed: AtomicBool, // if Connection#close() is called, close is set to true
+    close_reason: StdMutex<Option<String>>, // why the connection was closed, if known
+    invalid_packet: StdMutex<Option<Vec<u8>>>, // first bytes of the last invalid packet, only kept if they are logged
+    packet_sequence: AtomicBool, // both sides agreed to prefix each packet with a sequence number
+    sequence_out: AtomicU64, // sequence number of the next packet sent
+    sequence_in: AtomicU64, // sequence number expected for the next packet received
 }
 
 impl Connection {
-    pub fn new(stream: TcpStream, addr: SocketAddr) -> Self {
+    pub fn new<S: Stream + 'static>(stream: S, addr: SocketAddr) -> Self {
         let (tx, rx) = mpsc::unbounded_channel();
         Self {
             state: State::Pending,
-            stream: Mutex::new(stream),
-            addr,
+            stream: Mutex::new(Box::new(stream)),
+            addr: canonical_addr(&addr),
             tx: Mutex::new(tx),
             rx: Mutex::new(rx),
             connected_on: get_current_time(),
             bytes_in: AtomicUsize::new(0),
             bytes_out: AtomicUsize::new(0),
-            closed: AtomicBool::new(false)
+            interval_bytes_in: AtomicU64::new(0),
+            interval_bytes_out: AtomicU64::new(0),
+            packet_sizes_in: PacketSizeHistogram::new(),
+            packet_sizes_out: PacketSizeHistogram::new(),
+            closed: AtomicBool::new(false),
+            close_reason: StdMutex::new(None),
+            invalid_packet: StdMutex::new(None),
+            packet_sequence: AtomicBool::new(false),
+            sequence_out: AtomicU64::new(0),
+            sequence_in: AtomicU64::new(0)
         }
     }
 
@@ -65,45 +128,113 @@ impl Connection {
         &self.rx
     }
 
+    // must be enabled right after the handshake, before any other packet is sent or read
+    pub fn enable_packet_sequence(&self) {
+        self.packet_sequence.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_packet_sequence_enabled(&self) -> bool {
+        self.packet_sequence.load(Ordering::Relaxed)
+    }
+
+    // send a packet built by Packet::write, the sequence number is inserted after its size if enabled
+    pub async fn send_packet_bytes(&self, buf: &[u8]) -> P2pResult<()> {
+        if !self.is_packet_sequence_enabled() {
+            return self.send_bytes(buf).await
+        }
+
+        let size = u32::from_be_bytes(buf[0..4].try_into()?) + 8;
+        let sequence = self.sequence_out.fetch_add(1, Ordering::Relaxed);
+        let mut bytes = Vec::with_capacity(buf.len() + 8);
+        bytes.extend(size.to_be_bytes());
+        bytes.extend(sequence.to_be_bytes());
+        bytes.extend(&buf[4..]);
+        self.send_bytes(&bytes).await
+    }
+
+    // only used for diagnostics: a gap or a packet out of order is logged but not rejected
+    fn check_packet_sequence(&self, sequence: u64) {
+        let expected = self.sequence_in.swap(sequence.wrapping_add(1), Ordering::Relaxed);
+        if sequence != expected {
+            warn!("Packet sequence gap from {}: expected {} but received {}", self.get_address(), expected, sequence);
+        }
+    }
+
     pub async fn send_bytes(&self, buf: &[u8]) -> P2pResult<()> {
         let mut stream = self.stream.lock().await;
         stream.write_all(buf).await?;
         self.bytes_out.fetch_add(buf.len(), Ordering::Relaxed);
+        self.interval_bytes_out.fetch_add(buf.len() as u64, Ordering::Relaxed);
+        self.packet_sizes_out.record(buf.len());
         stream.flush().await?;
         Ok(())
     }
 
-    pub async fn read_packet(&self, buf: &mut [u8], max_size: u32) -> P2pResult<Packet<'_>> {
+    // if log_bytes is set, the raw bytes of an invalid packet are written in debug logs
+    pub async fn read_packet(&self, buf: &mut [u8], max_size: u32, log_bytes: bool) -> P2pResult<Packet<'_>> {
         let mut stream = self.stream.lock().await;
         let size = self.read_packet_size(&mut stream, buf).await?;
-        if size == 0 || size > max_size {
+        let sequence = self.is_packet_sequence_enabled();
+        let (min_size, max_size) = if sequence { (9, max_size + 8) } else { (1, max_size) };
+        if size < min_size || size > max_size {
             warn!("Received invalid packet size: {} bytes (max: {} bytes) from peer {}", size, max_size, self.get_address());
             return Err(P2pError::InvalidPacketSize)
         }
         debug!("Size received: {}", size);
+        self.packet_sizes_in.record(size as usize + 4);
+
+        let mut bytes = self.read_all_bytes(&mut stream, buf, size).await?;
+        if sequence {
+            self.check_packet_sequence(u64::from_be_bytes(bytes[0..8].try_into()?));
+            bytes.drain(0..8);
+        }
+        // reported apart from other decoding errors so the caller can decide to tolerate it
+        if let Some(id) = Packet::peek_id(&bytes).filter(|id| PacketKind::from_id(*id).is_none()) {
+            if log_bytes {
+                self.log_packet_bytes(&bytes);
+            }
+            return Err(P2pError::UnknownPacketId(id))
+        }
 
-        let bytes = self.read_all_bytes(&mut stream, buf, size).await?;
         let mut reader = Reader::new(&bytes);
-        let packet = Packet::read(&mut reader)?;
+        let packet = match Packet::read(&mut reader) {
+            Ok(packet) => packet,
+            Err(e) => {
+                if log_bytes {
+                    self.log_packet_bytes(&bytes);
+                }
+                return Err(e.into())
+            }
+        };
         if reader.total_read() != bytes.len() {
             warn!("read only {}/{} on bytes available", reader.total_read(), bytes.len());
+            if let Some((label, offset)) = reader.last_checkpoint() {
+                debug!("Packet from {} consumed up to field '{}' (offset {}) then had {} bytes left", self.get_address(), label, offset, reader.size());
+            }
+            if log_bytes {
+                self.log_packet_bytes(&bytes);
+            }
             return Err(P2pError::InvalidPacketNotFullRead)
         }
         Ok(packet)
     }
 
-    async fn read_packet_size(&self, stream: &mut TcpStream, buf: &mut [u8]) -> P2pResult<u32> {
-        let read = self.read_bytes_from_stream(stream, &mut buf[0..4]).await?;
-        if read != 4 {
-            warn!("Received invalid packet size: expected to read 4 bytes but read only {} bytes from peer {}", read, self.get_address());
-            return Err(P2pError::InvalidPacketSize)
-        }
-        let array: [u8; 4] = buf[0..4].try_into()?;
-        let size = u32::from_be_bytes(array);
+    // write in hex the bytes of an invalid packet, truncated to avoid flooding the logs
+    // they are also kept to be reported with the violation
+    fn log_packet_bytes(&self, bytes: &[u8]) {
+        let limit = bytes.len().min(P2P_LOG_PACKET_BYTES_LIMIT);
+        *self.invalid_packet.lock().unwrap_or_else(|e| e.into_inner()) = Some(bytes[..limit].to_vec());
+        debug!("Invalid packet (id: {:?}, kind: {:?}, {} bytes) from {}: {}{}", Packet::peek_id(bytes), Packet::peek_kind(bytes), bytes.len(), self.get_address(), hex::encode(&bytes[..limit]), if limit < bytes.len() { "..." } else { "" });
+    }
+
+    // the size prefix can be split across several TCP segments, so wait until we have the 4 bytes
+    async fn read_packet_size(&self, stream: &mut BoxedStream, buf: &mut [u8]) -> P2pResult<u32> {
+        let bytes = self.read_all_bytes(stream, buf, 4).await?;
+        let size = Reader::new(&bytes).read_u32()?;
         Ok(size)
     }
 
-    async fn read_all_bytes(&self, stream: &mut TcpStream, buf: &mut [u8], mut left: u32) -> P2pResult<Vec<u8>> {
+    async fn read_all_bytes(&self, stream: &mut BoxedStream, buf: &mut [u8], mut left: u32) -> P2pResult<Vec<u8>> {
         let buf_size = buf.len() as u32;
         let mut bytes = Vec::new();
         while left > 0 {
@@ -119,10 +250,11 @@ impl Connection {
         Ok(bytes)
     }
 
-    // this function will wait until something is sent to the socket if it's in blocking mode
-    // this return the size of data read & set in the buffer.
+    // wait until something is received on the socket and return the size of data read & set in the buffer.
+    // a closed socket is reported as Disconnected and any I/O error is returned as is,
+    // both of them close the connection in handle_connection
     // used to only lock one time the stream and read on it
-    async fn read_bytes_from_stream(&self, stream: &mut TcpStream, buf: &mut [u8]) -> P2pResult<usize> {
+    async fn read_bytes_from_stream(&self, stream: &mut BoxedStream, buf: &mut [u8]) -> P2pResult<usize> {
         let result = stream.read(buf).await?;
         match result {
             0 => {
@@ -130,11 +262,33 @@ impl Connection {
             }
             n => {
                 self.bytes_in.fetch_add(n, Ordering::Relaxed);
+                self.interval_bytes_in.fetch_add(n as u64, Ordering::Relaxed);
                 Ok(n)
             }
         }
     }
 
+    // only the first reason is kept, it's the one which caused the disconnection
+    pub fn set_close_reason(&self, reason: &str) {
+        let mut close_reason = self.close_reason.lock().unwrap_or_else(|e| e.into_inner());
+        if close_reason.is_none() {
+            *close_reason = Some(reason.to_owned());
+        }
+    }
+
+    pub fn take_invalid_packet(&self) -> Option<Vec<u8>> {
+        self.invalid_packet.lock().unwrap_or_else(|e| e.into_inner()).take()
+    }
+
+    pub fn get_close_reason(&self) -> Option<String> {
+        self.close_reason.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    pub async fn close_with_reason(&self, reason: &str) -> P2pResult<()> {
+        self.set_close_reason(reason);
+        self.close().await
+    }
+
     pub async fn close(&self) -> P2pResult<()> {
         self.closed.store(true, Ordering::Relaxed);
         let tx = self.get_tx().lock().await;
@@ -160,6 +314,21 @@ impl Connection {
         self.bytes_in.load(Ordering::Relaxed)
     }
 
+    // bytes read and sent since the previous call, the interval counters are reset but not the totals
+    pub fn take_interval_stats(&self) -> (u64, u64) {
+        let bytes_in = self.interval_bytes_in.swap(0, Ordering::Relaxed);
+        let bytes_out = self.interval_bytes_out.swap(0, Ordering::Relaxed);
+        (bytes_in, bytes_out)
+    }
+
+    pub fn get_packet_sizes_in(&self) -> &PacketSizeHistogram {
+        &self.packet_sizes_in
+    }
+
+    pub fn get_packet_sizes_out(&self) -> &PacketSizeHistogram {
+        &self.packet_sizes_out
+    }
+
     pub fn connected_on(&self) -> u64 {
         self.connected_on
     }
@@ -171,6 +340,182 @@ impl Connection {
 
 impl Display for Connection {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::result::Result<(), Error> {
-        write!(f, "Connection[peer: {}, read: {} kB, sent: {} kB, connected on: {}, closed: {}]", self.get_address(), self.bytes_in() / 1024, self.bytes_out() / 1024, self.connected_on(), self.is_closed())
+        write!(f, "Connection[peer: {}, read: {} kB, sent: {} kB, connected on: {}, closed: {}", canonical_addr_string(self.get_address()), self.bytes_in() / 1024, self.bytes_out() / 1024, self.connected_on(), self.is_closed())?;
+        if let Some(reason) = self.get_close_reason() {
+            write!(f, ", reason: {}", reason)?;
+        }
+        write!(f, "]")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::p2p::packet::handshake::HandshakeBuilder;
+    use std::borrow::Cow;
+    use std::time::Duration;
+    use tokio::io::duplex;
+    use tokio::time::timeout;
+
+    #[tokio::test]
+    async fn test_connection_over_duplex() {
+        let addr: SocketAddr = "127.0.0.1:2125".parse().unwrap();
+        let handshake = HandshakeBuilder::new(42, 2125).node_tag(Some("test".to_owned())).build().unwrap();
+        let packet = Packet::Handshake(Cow::Owned(handshake.clone())).to_bytes();
+        let (local, mut remote) = duplex(1024);
+        let connection = Connection::new(local, addr);
+
+        // bytes piped on the remote side are decoded as a packet
+        remote.write_all(&packet).await.unwrap();
+        let mut buf = [0u8; 1024];
+        match connection.read_packet(&mut buf, 1024, false).await.unwrap() {
+            Packet::Handshake(read) => assert_eq!(read.to_bytes(), handshake.to_bytes()),
+            _ => panic!("expected a handshake")
+        };
+        assert_eq!(connection.bytes_in(), packet.len());
+
+        // and the bytes sent are received as is
+        connection.send_packet_bytes(&packet).await.unwrap();
+        let mut received = vec![0u8; packet.len()];
+        remote.read_exact(&mut received).await.unwrap();
+        assert_eq!(received, packet);
+    }
+
+    fn spawn_reader(connection: Connection) -> tokio::task::JoinHandle<bool> {
+        tokio::spawn(async move {
+            let mut buf = [0u8; 1024];
+            connection.read_packet(&mut buf, 1024, false).await.is_ok()
+        })
+    }
+
+    #[tokio::test]
+    async fn test_never_readable_peer_does_not_stall_others() {
+        let addr: SocketAddr = "127.0.0.1:2125".parse().unwrap();
+        let packet = Packet::Handshake(Cow::Owned(HandshakeBuilder::new(1, 2125).build().unwrap())).to_bytes();
+
+        // nothing is ever written on the remote side of this connection
+        let (silent, _silent_remote) = duplex(1024);
+        let mut silent = spawn_reader(Connection::new(silent, addr));
+
+        let mut readers = Vec::new();
+        let mut remotes = Vec::new();
+        for _ in 0..3 {
+            let (local, remote) = duplex(1024);
+            readers.push(spawn_reader(Connection::new(local, addr)));
+            remotes.push(remote);
+        }
+        for remote in &mut remotes {
+            remote.write_all(&packet).await.unwrap();
+        }
+
+        for reader in readers {
+            assert!(timeout(Duration::from_secs(1), reader).await.unwrap().unwrap());
+        }
+        // still waiting on its packet
+        assert!(timeout(Duration::from_millis(50), &mut silent).await.is_err());
+        silent.abort();
+    }
+
+    #[tokio::test]
+    async fn test_packet_sequence() {
+        let addr: SocketAddr = "127.0.0.1:2125".parse().unwrap();
+        let packet = Packet::Handshake(Cow::Owned(HandshakeBuilder::new(1, 2125).build().unwrap())).to_bytes();
+        let (local, remote) = duplex(64 * 1024);
+        let (sender, receiver) = (Connection::new(local, addr), Connection::new(remote, addr));
+
+        // disabled by default, nothing is added to the packet
+        sender.send_packet_bytes(&packet).await.unwrap();
+        assert_eq!(sender.bytes_out(), packet.len());
+        let mut buf = [0u8; 1024];
+        receiver.read_packet(&mut buf, 1024, false).await.unwrap();
+
+        sender.enable_packet_sequence();
+        receiver.enable_packet_sequence();
+        let count = 10;
+        for i in 0..count {
+            sender.send_packet_bytes(&packet).await.unwrap();
+            assert!(matches!(receiver.read_packet(&mut buf, 1024, false).await, Ok(Packet::Handshake(_))));
+            // the receiver expects the sequence following the one it just read
+            assert_eq!(receiver.sequence_in.load(Ordering::Relaxed), i + 1);
+        }
+        assert_eq!(sender.bytes_out(), packet.len() * (count as usize + 1) + 8 * count as usize);
+
+        // a gap is only logged, the packet is still accepted
+        sender.sequence_out.store(count + 5, Ordering::Relaxed);
+        sender.send_packet_bytes(&packet).await.unwrap();
+        assert!(receiver.read_packet(&mut buf, 1024, false).await.is_ok());
+        assert_eq!(receiver.sequence_in.load(Ordering::Relaxed), count + 6);
+    }
+
+    #[test]
+    fn test_packet_size_buckets() {
+        for (size, bucket) in [(0, 0), (1, 0), (64, 0), (65, 1), (128, 1), (129, 2), (1024, 4), (1025, 5)].iter() {
+            assert_eq!(PacketSizeHistogram::get_bucket(*size), *bucket, "size {}", size);
+        }
+        // every size above the last limit goes in the last bucket
+        assert_eq!(PacketSizeHistogram::get_bucket(usize::MAX), P2P_PACKET_SIZE_BUCKETS - 1);
+        assert_eq!(PacketSizeHistogram::get_bucket_limit(0), Some(64));
+        assert_eq!(PacketSizeHistogram::get_bucket_limit(P2P_PACKET_SIZE_BUCKETS - 1), None);
+        for bucket in 0..P2P_PACKET_SIZE_BUCKETS - 1 {
+            let limit = PacketSizeHistogram::get_bucket_limit(bucket).unwrap();
+            assert_eq!(PacketSizeHistogram::get_bucket(limit), bucket);
+            assert_eq!(PacketSizeHistogram::get_bucket(limit + 1), bucket + 1);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_packet_sizes_recorded() {
+        let addr: SocketAddr = "127.0.0.1:2125".parse().unwrap();
+        let (local, remote) = duplex(64 * 1024);
+        let (sender, receiver) = (Connection::new(local, addr), Connection::new(remote, addr));
+
+        // a handshake without peers is small, with a tag and peers it is bigger
+        let small = Packet::Handshake(Cow::Owned(HandshakeBuilder::new(1, 2125).build().unwrap())).to_bytes();
+        let peers: Vec<SocketAddr> = (0..16).map(|i| SocketAddr::new("10.0.0.1".parse().unwrap(), 2000 + i)).collect();
+        let big = Packet::Handshake(Cow::Owned(HandshakeBuilder::new(1, 2125).node_tag(Some("test".to_owned())).peers(peers).build().unwrap())).to_bytes();
+        let (small_bucket, big_bucket) = (PacketSizeHistogram::get_bucket(small.len()), PacketSizeHistogram::get_bucket(big.len()));
+        assert_ne!(small_bucket, big_bucket);
+
+        let mut buf = [0u8; 1024];
+        for packet in [&small, &big, &big].iter() {
+            sender.send_packet_bytes(packet).await.unwrap();
+            receiver.read_packet(&mut buf, 1024, false).await.unwrap();
+        }
+
+        for counts in vec![sender.get_packet_sizes_out().get_counts(), receiver.get_packet_sizes_in().get_counts()] {
+            assert_eq!(counts.len(), P2P_PACKET_SIZE_BUCKETS);
+            assert_eq!(counts[small_bucket], 1);
+            assert_eq!(counts[big_bucket], 2);
+            assert_eq!(counts.iter().sum::<u64>(), 3);
+        }
+        assert!(receiver.get_packet_sizes_out().get_counts().iter().all(|count| *count == 0));
+    }
+
+    #[tokio::test]
+    async fn test_interval_stats() {
+        let addr: SocketAddr = "127.0.0.1:2125".parse().unwrap();
+        let packet = Packet::Handshake(Cow::Owned(HandshakeBuilder::new(1, 2125).build().unwrap())).to_bytes();
+        let (local, remote) = duplex(64 * 1024);
+        let (sender, receiver) = (Connection::new(local, addr), Connection::new(remote, addr));
+
+        let mut buf = [0u8; 1024];
+        for _ in 0..3 {
+            sender.send_packet_bytes(&packet).await.unwrap();
+            receiver.read_packet(&mut buf, 1024, false).await.unwrap();
+        }
+        let size = packet.len() as u64;
+        assert_eq!(sender.take_interval_stats(), (0, 3 * size));
+        assert_eq!(receiver.take_interval_stats(), (3 * size, 0));
+        // the interval counters are reset by each call
+        assert_eq!(sender.take_interval_stats(), (0, 0));
+        assert_eq!(receiver.take_interval_stats(), (0, 0));
+
+        // next interval only counts the new bytes, the totals keep growing
+        sender.send_packet_bytes(&packet).await.unwrap();
+        receiver.read_packet(&mut buf, 1024, false).await.unwrap();
+        assert_eq!(sender.take_interval_stats(), (0, size));
+        assert_eq!(receiver.take_interval_stats(), (size, 0));
+        assert_eq!(sender.bytes_out() as u64, 4 * size);
+        assert_eq!(receiver.bytes_in() as u64, 4 * size);
     }
 }
\ No newline at end of file
@@ -0,0 +1,65 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+// memory shared by all the blocks downloaded during a sync and not yet added to the chain
+// each download reserves its size and the reservation is released once the block is dropped
+pub struct DownloadBudget {
+    max_bytes: usize, // maximum bytes reserved at the same time
+    bytes: AtomicUsize // bytes currently reserved
+}
+
+impl DownloadBudget {
+    pub fn new(max_bytes: usize) -> Arc<Self> {
+        Arc::new(Self {
+            max_bytes,
+            bytes: AtomicUsize::new(0)
+        })
+    }
+
+    // a reservation is always accepted when nothing is reserved, so a block bigger than the budget can still be downloaded
+    pub fn try_reserve(self: &Arc<Self>, bytes: usize) -> Option<DownloadReservation> {
+        let result = self.bytes.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |current| {
+            if current == 0 || current.saturating_add(bytes) <= self.max_bytes {
+                Some(current.saturating_add(bytes))
+            } else {
+                None
+            }
+        });
+
+        result.ok().map(|_| DownloadReservation {
+            budget: Arc::clone(self),
+            bytes
+        })
+    }
+
+    pub fn get_max_bytes(&self) -> usize {
+        self.max_bytes
+    }
+
+    pub fn get_bytes(&self) -> usize {
+        self.bytes.load(Ordering::SeqCst)
+    }
+}
+
+pub struct DownloadReservation {
+    budget: Arc<DownloadBudget>,
+    bytes: usize
+}
+
+impl DownloadReservation {
+    // replace the estimated size by the real one once the download is done
+    pub fn resize(&mut self, bytes: usize) {
+        if bytes > self.bytes {
+            self.budget.bytes.fetch_add(bytes - self.bytes, Ordering::SeqCst);
+        } else {
+            self.budget.bytes.fetch_sub(self.bytes - bytes, Ordering::SeqCst);
+        }
+        self.bytes = bytes;
+    }
+}
+
+impl Drop for DownloadReservation {
+    fn drop(&mut self) {
+        self.budget.bytes.fetch_sub(self.bytes, Ordering::SeqCst);
+    }
+}
\ No newline at end of file
@@ -1,16 +1,18 @@
 use crate::core::reader::ReaderError;
-use crate::crypto::hash::Hash;
+use crate::crypto::hash::{Hash, HashAlgorithm};
 use tokio::sync::mpsc::error::SendError as TSendError;
 use tokio::sync::oneshot::error::RecvError;
 use std::array::TryFromSliceError;
-use std::net::AddrParseError;
+use std::net::{AddrParseError, SocketAddr};
 use tokio::time::error::Elapsed;
+use tokio::task::JoinError;
 use std::sync::mpsc::SendError;
 use std::io::Error as IOError;
 use std::sync::PoisonError;
 use thiserror::Error;
 
 use super::packet::object::ObjectRequest;
+use super::packet::PacketKind;
 
 #[derive(Error, Debug)]
 pub enum P2pError {
@@ -18,16 +20,38 @@ pub enum P2pError {
     Disconnected,
     #[error("Invalid handshake")]
     InvalidHandshake,
+    #[error("Invalid handshake field: {}", _0)]
+    InvalidHandshakeField(&'static str),
     #[error("Expected Handshake packet")]
     ExpectedHandshake,
     #[error("Invalid peer address, {}", _0)]
     InvalidPeerAddress(String), // peer address from handshake
+    #[error("No bind address set")]
+    NoBindAddress,
+    #[error("Port {} is already in use", _0)]
+    PortAlreadyInUse(u16),
+    #[error("Cannot bind P2p server on {}: {}", _0, _1)]
+    BindError(SocketAddr, IOError),
     #[error("Invalid network ID")]
     InvalidNetworkID,
+    #[error("Reorg of {} blocks refused, maximum depth is {}", _0, _1)]
+    ReorgTooDeep(u64, u64),
+    #[error("Same network ID but another chain, expected genesis {} got {}", expected, got)]
+    WrongChain { expected: Hash, got: Hash },
+    #[error("Peer uses hash algorithm {}, expected {}", _0, _1)]
+    InvalidHashAlgorithm(String, HashAlgorithm),
     #[error("Peer id {} is already used!", _0)]
     PeerIdAlreadyUsed(u64),
+    #[error("Too many outgoing peers in subnet of {}", _0)]
+    SubnetLimitReached(String),
+    #[error("Peer {} was disconnected recently, waiting before dialing it again", _0)]
+    PeerInBackoff(String),
     #[error("Peer already connected: {}", _0)]
     PeerAlreadyConnected(String),
+    #[error("SOCKS5 proxy error: {}", _0)]
+    ProxyError(&'static str),
+    #[error("SOCKS5 proxy failed to connect, reply code {}", _0)]
+    ProxyConnectFailed(u8),
     #[error(transparent)]
     ErrorStd(#[from] IOError),
     #[error("Poison Error: {}", _0)]
@@ -42,6 +66,14 @@ pub enum P2pError {
     ParseAddressError(#[from] AddrParseError),
     #[error("Invalid packet ID")]
     InvalidPacket,
+    #[error("Unknown packet id {}", _0)]
+    UnknownPacketId(u8),
+    #[error("Packet {:?} is not allowed for the role of the peer", _0)]
+    PacketNotAllowed(PacketKind),
+    #[error("Too many packets with an unknown id received: {}", _0)]
+    TooManyUnknownPackets(u8),
+    #[error("Handshake received after the connection was established")]
+    HandshakeAlreadyDone,
     #[error("Packet size exceed limit")]
     InvalidPacketSize,
     #[error("Received valid packet with not used bytes")]
@@ -50,6 +82,8 @@ pub enum P2pError {
     RequestSyncChainTooFast,
     #[error(transparent)]
     AsyncTimeOut(#[from] Elapsed),
+    #[error(transparent)]
+    TaskError(#[from] JoinError),
     #[error("Object requested {:?} not found", _0)]
     ObjectNotFound(ObjectRequest),
     #[error("Object requested {:?} already requested", _0)]
@@ -3,4 +3,7 @@ pub mod peer;
 pub mod server;
 pub mod error;
 pub mod packet;
-pub mod peer_list;
\ No newline at end of file
+pub mod peer_list;
+pub mod client;
+pub mod socks;
+pub mod download;
\ No newline at end of file
@@ -1,19 +1,32 @@
-use crate::globals::{ip_from_bytes, ip_to_bytes};
+use crate::globals::{ip_from_bytes, ip_to_bytes, get_current_time};
+use crate::config::{VERSION, NETWORK_ID, PROTOCOL_VERSION};
+use crate::p2p::error::P2pError;
 use crate::core::reader::{Reader, ReaderError};
 use crate::p2p::peer_list::SharedPeerList;
 use crate::core::serializer::Serializer;
 use crate::p2p::connection::Connection;
 use crate::core::writer::Writer;
-use crate::crypto::hash::Hash;
-use crate::p2p::peer::Peer;
+use crate::crypto::hash::{Hash, HashAlgorithm};
+use crate::p2p::peer::{Peer, PeerDetails};
 use std::collections::HashSet;
 use std::fmt::{Display, Error, Formatter};
 use std::net::SocketAddr;
 
+// optional features supported by the node, a feature is used only if both peers support it
+pub const CAPABILITY_PACKET_SEQUENCE: u8 = 0b0000_0001; // each packet is prefixed by a sequence number
+pub const CAPABILITY_PEER_EXCHANGE: u8 = 0b0000_1000; // Peers packet is understood
+pub const CAPABILITY_INVENTORY: u8 = 0b0001_0000; // Inventory packet is understood, tx hashes are announced one by one otherwise
+pub const CAPABILITY_PENDING_OBJECT: u8 = 0b0010_0000; // Pending object response is understood, NotFound is sent otherwise
+// role of the node, a full node doesn't set any of them
+pub const CAPABILITY_PRUNED: u8 = 0b0000_0010; // only recent blocks are kept, the chain can't be served
+pub const CAPABILITY_LIGHT_CLIENT: u8 = 0b0000_0100; // blocks are not verified, so they are neither served nor relayed
+
 // this Handshake is the first data sent when connecting to the server
 // If handshake is valid, server reply with his own handshake
 // We just have to repeat this request to all peers until we reach max connection
 // Network ID, Block Height & block top hash is to verify that we are on the same network & chain.
+// Fields added after the protocol version 0 are in an extension appended to the original handshake,
+// it is only sent at protocol version 1 or above so a node at version 0 can still read our handshake
 #[derive(Clone)]
 pub struct Handshake {
     version: String, // daemon version
@@ -24,40 +37,54 @@ pub struct Handshake {
     utc_time: u64, // current time in seconds
     block_height: u64, // current block height
     block_top_hash: Hash, // current block top hash
-    peers: Vec<SocketAddr> // all peers that we are already connected to
+    peers: Vec<SocketAddr>, // all peers that we are already connected to
+    // extension, protocol version 1
+    protocol_version: u8, // highest protocol version supported, or the one selected in a reply
+    genesis_hash: Hash, // hash of the genesis block, two chains can share the same network id
+    hash_algorithm: u8, // id of the hash algorithm used by the node
+    capabilities: u8 // bit flags of the optional features supported
 } // Server reply with his own list of peers, but we remove all already known by requester for the response.
 
 impl Handshake {
     pub const MAX_LEN: usize = 16;
+    // bytes of the extension fields added by a newer protocol version, skipped by us
+    pub const MAX_UNKNOWN_EXTENSION_SIZE: usize = 128;
 
-    pub fn new(version: String, node_tag: Option<String>, network_id: [u8; 16], peer_id: u64, local_port: u16, utc_time: u64, block_height: u64, block_top_hash: Hash, peers: Vec<SocketAddr>) -> Self {
-        assert!(version.len() > 0 && version.len() <= Handshake::MAX_LEN); // version cannot be greater than 16 chars
-        if let Some(node_tag) = &node_tag {
-            assert!(node_tag.len() > 0 && node_tag.len() <= Handshake::MAX_LEN); // node tag cannot be greater than 16 chars
-        }
-
-        assert!(peers.len() <= Handshake::MAX_LEN); // maximum 16 peers allowed
+    // largest serialized handshake: strings and peer list at their maximum length, all peers in IPv6
+    pub const fn max_serialized_size() -> usize {
+        (1 + Handshake::MAX_LEN) * 2 // version, node tag
+        + 16 + 8 + 2 + 8 + 8 + 32 // network id, peer id, local port, utc time, block height, block top hash
+        + 1 + Handshake::MAX_LEN * 19 // peers
+        + 1 + 32 + 1 + 1 // protocol version, genesis hash, hash algorithm, capabilities
+        + Handshake::MAX_UNKNOWN_EXTENSION_SIZE
+    }
 
-        Self {
-            version,
-            node_tag,
-            network_id,
-            peer_id,
-            local_port,
-            utc_time,
-            block_height,
-            block_top_hash,
-            peers
+    // standalone deserialization (tools, tests), bigger inputs are rejected before reading anything
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, ReaderError> {
+        if bytes.len() > Handshake::max_serialized_size() {
+            return Err(ReaderError::InvalidSize)
         }
+        let mut reader = Reader::new(bytes);
+        Handshake::read(&mut reader)
     }
 
-    pub fn create_peer(self, connection: Connection, out: bool, priority: bool, peer_list: SharedPeerList) -> (Peer, Vec<SocketAddr>) {
-        let block_height = self.get_block_height();
+    // protocol_version is the one used on this connection, the lowest of both peers
+    pub fn create_peer(self, connection: Connection, protocol_version: u8, out: bool, priority: bool, peer_list: SharedPeerList) -> (Peer, Vec<SocketAddr>) {
         let mut peers = HashSet::new();
         for peer in &self.peers {
             peers.insert(peer.clone());
         }
-        (Peer::new(connection, self.get_peer_id(), self.node_tag, self.local_port, self.version, self.block_top_hash, block_height, out, priority, peer_list, peers), self.peers)
+        let details = PeerDetails {
+            id: self.peer_id,
+            node_tag: self.node_tag,
+            local_port: self.local_port,
+            version: self.version,
+            protocol_version,
+            capabilities: self.capabilities,
+            block_top_hash: self.block_top_hash,
+            block_height: self.block_height
+        };
+        (Peer::new(connection, details, out, priority, peer_list, peers), self.peers)
     }
 
     pub fn get_version(&self) -> &String {
@@ -68,6 +95,31 @@ impl Handshake {
         &self.network_id
     }
 
+    pub fn get_protocol_version(&self) -> u8 {
+        self.protocol_version
+    }
+
+    // genesis hash, hash algorithm and capabilities are only sent from the protocol version 1
+    pub fn has_extension(&self) -> bool {
+        self.protocol_version >= 1
+    }
+
+    pub fn get_genesis_hash(&self) -> &Hash {
+        &self.genesis_hash
+    }
+
+    pub fn get_hash_algorithm(&self) -> u8 {
+        self.hash_algorithm
+    }
+
+    pub fn get_capabilities(&self) -> u8 {
+        self.capabilities
+    }
+
+    pub fn has_capability(&self, capability: u8) -> bool {
+        self.capabilities & capability == capability
+    }
+
     pub fn get_node_tag(&self) -> &Option<String> {
         &self.node_tag
     }
@@ -94,7 +146,8 @@ impl Handshake {
 }
 
 impl Serializer for Handshake {
-    // 1 + MAX(16) + 1 + MAX(16) + 16 + 8 + 8 + 8 + 32 + 1 + 24 * 16
+    // 1 + MAX(16) + 1 + MAX(16) + 16 + 8 + 2 + 8 + 8 + 32 + 1 + MAX(16) * (7 for IPv4 or 19 for IPv6) + extension (1 + 32 + 1 + 1)
+    // peers list can be empty (0 peers) up to MAX_LEN peers, see Handshake::max_serialized_size
     fn write(&self, writer: &mut Writer) {
         // daemon version
         writer.write_string(&self.version);
@@ -113,6 +166,23 @@ impl Serializer for Handshake {
         for peer in &self.peers {
             writer.write_bytes(&ip_to_bytes(peer));
         }
+
+        // a node at protocol version 0 rejects any byte after the peers
+        if self.has_extension() {
+            writer.write_u8(self.protocol_version); // protocol version
+            writer.write_hash(&self.genesis_hash); // Genesis Hash (32 bytes)
+            writer.write_u8(self.hash_algorithm); // hash algorithm
+            writer.write_u8(self.capabilities); // capabilities
+        }
+    }
+
+    // hex is checked before being decoded, so an oversized input is never allocated
+    fn from_hex(hex: String) -> Result<Self, ReaderError> {
+        if hex.len() > Handshake::max_serialized_size() * 2 {
+            return Err(ReaderError::InvalidSize)
+        }
+        let bytes = hex::decode(&hex).map_err(|_| ReaderError::InvalidHex)?;
+        Handshake::from_bytes(&bytes)
     }
 
     fn read(reader: &mut Reader) -> Result<Self, ReaderError> {
@@ -121,25 +191,27 @@ impl Serializer for Handshake {
         // if we don't verify each time, it can create a panic error and crash the node
 
         // Daemon version
-        let version = reader.read_string()?;
-        if version.len() == 0 || version.len() > Handshake::MAX_LEN {
+        reader.checkpoint("version");
+        // sizes are checked before reading so we never allocate more than MAX_LEN
+        let version = reader.read_string_max(Handshake::MAX_LEN)?;
+        if version.len() == 0 {
             return Err(ReaderError::InvalidSize)
         }
 
         // Node Tag
-        let node_tag = reader.read_optional_string()?;
-        if let Some(tag) = &node_tag {
-            if tag.len() > Handshake::MAX_LEN {
-                return Err(ReaderError::InvalidSize)
-            }
-        }
+        reader.checkpoint("node tag");
+        let node_tag = reader.read_optional_string_max(Handshake::MAX_LEN)?;
 
+        reader.checkpoint("network id");
         let network_id: [u8; 16] = reader.read_bytes(16)?;
         let peer_id = reader.read_u64()?;
         let local_port = reader.read_u16()?;
         let utc_time = reader.read_u64()?;
+        reader.checkpoint("block height");
         let block_height = reader.read_u64()?;
         let block_top_hash = Hash::new(reader.read_bytes_32()?);
+        // an empty peer list is valid (new node or node without any peer)
+        reader.checkpoint("peers");
         let peers_len = reader.read_u8()? as usize;
         if peers_len > Handshake::MAX_LEN {
             return Err(ReaderError::InvalidSize)
@@ -150,7 +222,176 @@ impl Serializer for Handshake {
             let peer = ip_from_bytes(reader)?;
             peers.push(peer);
         }
-        Ok(Handshake::new(version, node_tag, network_id, peer_id, local_port, utc_time, block_height, block_top_hash, peers))
+
+        // no extension: peer at protocol version 0, its chain is only checked with the network id
+        let (protocol_version, genesis_hash, hash_algorithm, capabilities) = if reader.size() == 0 {
+            (0, Hash::zero(), HashAlgorithm::Sha256.get_id(), 0)
+        } else {
+            reader.checkpoint("extension");
+            let protocol_version = reader.read_u8()?;
+            // version 0 has no extension
+            if protocol_version == 0 {
+                return Err(ReaderError::InvalidValue)
+            }
+            let genesis_hash = Hash::new(reader.read_bytes_32()?);
+            // an unknown algorithm is rejected when verifying the handshake
+            let hash_algorithm = reader.read_u8()?;
+            // unknown capabilities are ignored, they may be supported by a newer version
+            let capabilities = reader.read_u8()?;
+            // fields of a newer version
+            if reader.size() > Handshake::MAX_UNKNOWN_EXTENSION_SIZE {
+                return Err(ReaderError::InvalidSize)
+            }
+            reader.skip(reader.size())?;
+            (protocol_version, genesis_hash, hash_algorithm, capabilities)
+        };
+        let builder = HandshakeBuilder {
+            version,
+            node_tag,
+            network_id,
+            peer_id,
+            local_port,
+            utc_time,
+            block_height,
+            block_top_hash,
+            peers,
+            protocol_version,
+            genesis_hash,
+            hash_algorithm,
+            capabilities
+        };
+        builder.build().map_err(|_| ReaderError::InvalidSize)
+    }
+}
+
+// Build a Handshake with named setters, the only way to create one as it checks the fields
+// defaults are our version & network, current time and a chain at genesis state (height 0, zero hashes)
+pub struct HandshakeBuilder {
+    version: String,
+    node_tag: Option<String>,
+    network_id: [u8; 16],
+    peer_id: u64,
+    local_port: u16,
+    utc_time: u64,
+    block_height: u64,
+    block_top_hash: Hash,
+    peers: Vec<SocketAddr>,
+    protocol_version: u8,
+    genesis_hash: Hash,
+    hash_algorithm: u8,
+    capabilities: u8
+}
+
+impl HandshakeBuilder {
+    pub fn new(peer_id: u64, local_port: u16) -> Self {
+        Self {
+            version: VERSION.to_owned(),
+            node_tag: None,
+            network_id: NETWORK_ID,
+            peer_id,
+            local_port,
+            utc_time: get_current_time(),
+            block_height: 0,
+            block_top_hash: Hash::zero(),
+            peers: Vec::new(),
+            protocol_version: PROTOCOL_VERSION,
+            genesis_hash: Hash::zero(),
+            hash_algorithm: HashAlgorithm::Sha256.get_id(),
+            capabilities: 0
+        }
+    }
+
+    pub fn version(mut self, version: String) -> Self {
+        self.version = version;
+        self
+    }
+
+    pub fn node_tag(mut self, node_tag: Option<String>) -> Self {
+        self.node_tag = node_tag;
+        self
+    }
+
+    pub fn network_id(mut self, network_id: [u8; 16]) -> Self {
+        self.network_id = network_id;
+        self
+    }
+
+    // at version 0, genesis hash, hash algorithm and capabilities are not sent
+    pub fn protocol_version(mut self, protocol_version: u8) -> Self {
+        self.protocol_version = protocol_version;
+        self
+    }
+
+    pub fn genesis_hash(mut self, genesis_hash: Hash) -> Self {
+        self.genesis_hash = genesis_hash;
+        self
+    }
+
+    pub fn hash_algorithm(mut self, hash_algorithm: HashAlgorithm) -> Self {
+        self.hash_algorithm = hash_algorithm.get_id();
+        self
+    }
+
+    pub fn capabilities(mut self, capabilities: u8) -> Self {
+        self.capabilities = capabilities;
+        self
+    }
+
+    pub fn utc_time(mut self, utc_time: u64) -> Self {
+        self.utc_time = utc_time;
+        self
+    }
+
+    pub fn block_height(mut self, block_height: u64) -> Self {
+        self.block_height = block_height;
+        self
+    }
+
+    pub fn block_top_hash(mut self, block_top_hash: Hash) -> Self {
+        self.block_top_hash = block_top_hash;
+        self
+    }
+
+    pub fn peers(mut self, peers: Vec<SocketAddr>) -> Self {
+        self.peers = peers;
+        self
+    }
+
+    pub fn build(self) -> Result<Handshake, P2pError> {
+        if self.version.is_empty() || self.version.len() > Handshake::MAX_LEN { // version cannot be greater than 16 chars
+            return Err(P2pError::InvalidHandshakeField("version"))
+        }
+
+        if let Some(node_tag) = &self.node_tag {
+            if node_tag.is_empty() || node_tag.len() > Handshake::MAX_LEN { // node tag cannot be greater than 16 chars
+                return Err(P2pError::InvalidHandshakeField("node tag"))
+            }
+        }
+
+        // the port is combined with the IP we see to gossip the peer address, 0 can't be dialed
+        if self.local_port == 0 {
+            return Err(P2pError::InvalidHandshakeField("local port"))
+        }
+
+        if self.peers.len() > Handshake::MAX_LEN { // maximum 16 peers allowed
+            return Err(P2pError::InvalidHandshakeField("peers"))
+        }
+
+        Ok(Handshake {
+            version: self.version,
+            node_tag: self.node_tag,
+            network_id: self.network_id,
+            peer_id: self.peer_id,
+            local_port: self.local_port,
+            utc_time: self.utc_time,
+            block_height: self.block_height,
+            block_top_hash: self.block_top_hash,
+            peers: self.peers,
+            protocol_version: self.protocol_version,
+            genesis_hash: self.genesis_hash,
+            hash_algorithm: self.hash_algorithm,
+            capabilities: self.capabilities
+        })
     }
 }
 
@@ -163,6 +404,153 @@ impl Display for Handshake {
         } else {
             &NO_NODE_TAG
         };
-        write!(f, "Handshake[version: {}, node tag: {}, network_id: {}, peer_id: {}, utc_time: {}, block_height: {}, block_top_hash: {}, peers: ({})]", self.get_version(), node_tag, hex::encode(self.get_network_id()), self.get_peer_id(), self.get_utc_time(), self.get_block_height(), self.get_block_top_hash(), self.get_peers().len())
+        write!(f, "Handshake[version: {}, node tag: {}, network_id: {}, protocol_version: {}, hash_algorithm: {}, capabilities: {:#04x}, peer_id: {}, utc_time: {}, block_height: {}, block_top_hash: {}, peers: ({})]", self.get_version(), node_tag, hex::encode(self.get_network_id()), self.get_protocol_version(), self.get_hash_algorithm(), self.get_capabilities(), self.get_peer_id(), self.get_utc_time(), self.get_block_height(), self.get_block_top_hash(), self.get_peers().len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn build_handshake(protocol_version: u8) -> Handshake {
+        HandshakeBuilder::new(42, 2125)
+            .node_tag(Some("test".to_owned()))
+            .protocol_version(protocol_version)
+            .genesis_hash(Hash::max())
+            .hash_algorithm(HashAlgorithm::Sha256)
+            .capabilities(CAPABILITY_PACKET_SEQUENCE | CAPABILITY_INVENTORY)
+            .block_height(10)
+            .peers(vec!["127.0.0.1:2125".parse().unwrap()])
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_extension_round_trip() {
+        let handshake = build_handshake(PROTOCOL_VERSION);
+        let read = Handshake::from_bytes(&handshake.to_bytes()).unwrap();
+        assert_eq!(read.get_protocol_version(), PROTOCOL_VERSION);
+        assert_eq!(*read.get_genesis_hash(), Hash::max());
+        assert_eq!(read.get_hash_algorithm(), HashAlgorithm::Sha256.get_id());
+        assert_eq!(read.get_capabilities(), CAPABILITY_PACKET_SEQUENCE | CAPABILITY_INVENTORY);
+        assert_eq!(read.get_peers(), handshake.get_peers());
+        assert_eq!(read.to_bytes(), handshake.to_bytes());
+    }
+
+    #[test]
+    fn test_protocol_version_zero_format() {
+        let handshake = build_handshake(0);
+        let bytes = handshake.to_bytes();
+        // original format: version, node tag, network id, peer id, local port, utc time, block height, top hash, peers
+        let expected = (1 + VERSION.len()) + (1 + 4) + 16 + 8 + 2 + 8 + 8 + 32 + (1 + 7);
+        assert_eq!(bytes.len(), expected);
+
+        // extension fields are not sent, a peer without them is read with the defaults
+        let read = Handshake::from_bytes(&bytes).unwrap();
+        assert_eq!(read.get_protocol_version(), 0);
+        assert!(!read.has_extension());
+        assert_eq!(*read.get_genesis_hash(), Hash::zero());
+        assert_eq!(read.get_hash_algorithm(), HashAlgorithm::Sha256.get_id());
+        assert_eq!(read.get_capabilities(), 0);
+        assert_eq!(read.get_block_height(), 10);
+    }
+
+    #[test]
+    fn test_unknown_extension_is_skipped() {
+        let mut bytes = build_handshake(PROTOCOL_VERSION + 1).to_bytes();
+        bytes.extend([0xFF; 16]);
+        let read = Handshake::from_bytes(&bytes).unwrap();
+        assert_eq!(read.get_protocol_version(), PROTOCOL_VERSION + 1);
+        assert_eq!(*read.get_genesis_hash(), Hash::max());
+
+        let mut bytes = build_handshake(PROTOCOL_VERSION).to_bytes();
+        bytes.extend(vec![0; Handshake::MAX_UNKNOWN_EXTENSION_SIZE + 1]);
+        let mut reader = Reader::new(&bytes);
+        assert!(Handshake::read(&mut reader).is_err());
+    }
+
+    #[test]
+    fn test_empty_extension_is_rejected() {
+        // an extension must start with a protocol version of at least 1
+        let mut bytes = build_handshake(0).to_bytes();
+        bytes.push(0);
+        bytes.extend([0; 34]);
+        assert!(Handshake::from_bytes(&bytes).is_err());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_zero_local_port_is_rejected() {
+        assert!(matches!(HandshakeBuilder::new(42, 0).build(), Err(P2pError::InvalidHandshakeField("local port"))));
+
+        // same check when it's read from a peer
+        let mut bytes = build_handshake(0).to_bytes();
+        let port_offset = (1 + VERSION.len()) + (1 + 4) + 16 + 8;
+        assert_eq!(bytes[port_offset..port_offset + 2], 2125u16.to_be_bytes());
+        bytes[port_offset..port_offset + 2].copy_from_slice(&[0, 0]);
+        assert!(Handshake::from_bytes(&bytes).is_err());
+    }
+
+    #[test]
+    fn test_max_serialized_size() {
+        // every field at its bound, followed by the largest unknown extension of a newer protocol version
+        let peers: Vec<SocketAddr> = (0..Handshake::MAX_LEN).map(|i| format!("[2001:db8::{}]:{}", i + 1, 2125 + i).parse().unwrap()).collect();
+        let handshake = HandshakeBuilder::new(42, 2125)
+            .version("v".repeat(Handshake::MAX_LEN))
+            .node_tag(Some("t".repeat(Handshake::MAX_LEN)))
+            .protocol_version(PROTOCOL_VERSION + 1)
+            .peers(peers)
+            .build()
+            .unwrap();
+        let mut bytes = handshake.to_bytes();
+        bytes.extend(vec![0; Handshake::MAX_UNKNOWN_EXTENSION_SIZE]);
+        assert_eq!(bytes.len(), Handshake::max_serialized_size());
+        assert!(Handshake::from_bytes(&bytes).is_ok());
+        assert!(Handshake::from_hex(hex::encode(&bytes)).is_ok());
+
+        bytes.push(0);
+        assert!(matches!(Handshake::from_bytes(&bytes), Err(ReaderError::InvalidSize)));
+        // the size is checked before decoding the hex
+        let hex = "z".repeat(Handshake::max_serialized_size() * 2 + 2);
+        assert!(matches!(Handshake::from_hex(hex), Err(ReaderError::InvalidSize)));
+        assert!(matches!(Handshake::from_hex("zz".to_owned()), Err(ReaderError::InvalidHex)));
+    }
+
+    fn build_with_peers(peers: Vec<SocketAddr>) -> Result<Handshake, P2pError> {
+        HandshakeBuilder::new(42, 2125).peers(peers).build()
+    }
+
+    #[test]
+    fn test_no_peers_round_trip() {
+        let handshake = build_with_peers(Vec::new()).unwrap();
+        let bytes = handshake.to_bytes();
+        let read = Handshake::from_bytes(&bytes).unwrap();
+        assert!(read.get_peers().is_empty());
+        assert_eq!(read.to_bytes(), bytes);
+    }
+
+    #[test]
+    fn test_max_peers_round_trip() {
+        // IPv6 peers are the largest ones
+        let peers: Vec<SocketAddr> = (0..Handshake::MAX_LEN).map(|i| format!("[2001:db8::{}]:{}", i + 1, 2125 + i).parse().unwrap()).collect();
+        let handshake = build_with_peers(peers.clone()).unwrap();
+        let bytes = handshake.to_bytes();
+        assert!(bytes.len() <= Handshake::max_serialized_size());
+        let read = Handshake::from_bytes(&bytes).unwrap();
+        assert_eq!(*read.get_peers(), peers);
+        assert_eq!(read.to_bytes(), bytes);
+    }
+
+    #[test]
+    fn test_too_many_peers() {
+        let peers: Vec<SocketAddr> = (0..=Handshake::MAX_LEN).map(|i| format!("127.0.0.1:{}", 2125 + i).parse().unwrap()).collect();
+        assert!(build_with_peers(peers.clone()).is_err());
+
+        // a peer count over the limit written by hand is rejected before reading the peers
+        let mut bytes = build_with_peers(peers[..Handshake::MAX_LEN].to_vec()).unwrap().to_bytes();
+        // IPv4 peers are 7 bytes, followed by the extension: protocol version, genesis hash, hash algorithm, capabilities
+        let count_offset = bytes.len() - Handshake::MAX_LEN * 7 - (1 + 32 + 1 + 1) - 1;
+        assert_eq!(bytes[count_offset] as usize, Handshake::MAX_LEN);
+        bytes[count_offset] += 1;
+        assert!(Handshake::from_bytes(&bytes).is_err());
+    }
+}
@@ -0,0 +1,52 @@
+use crate::config::P2P_INVENTORY_MAX_SIZE;
+use crate::core::reader::{Reader, ReaderError};
+use crate::core::serializer::Serializer;
+use crate::crypto::hash::Hash;
+use crate::core::writer::Writer;
+
+// Batch of transaction hashes announced at once
+// sent instead of one propagation packet per transaction when the announcement delay is enabled
+#[derive(Clone)]
+pub struct Inventory {
+    txs: Vec<Hash>
+}
+
+impl Inventory {
+    pub fn new(txs: Vec<Hash>) -> Self {
+        Self {
+            txs
+        }
+    }
+
+    pub fn get_txs(&self) -> &Vec<Hash> {
+        &self.txs
+    }
+
+    pub fn into_txs(self) -> Vec<Hash> {
+        self.txs
+    }
+}
+
+impl Serializer for Inventory {
+    fn write(&self, writer: &mut Writer) {
+        writer.write_u16(&(self.txs.len() as u16));
+        for hash in &self.txs {
+            writer.write_hash(hash);
+        }
+    }
+
+    fn read(reader: &mut Reader) -> Result<Self, ReaderError> {
+        reader.checkpoint("inventory");
+        let len = reader.read_u16()? as usize;
+        if len == 0 || len > P2P_INVENTORY_MAX_SIZE {
+            return Err(ReaderError::InvalidValue)
+        }
+
+        let mut txs = Vec::with_capacity(len);
+        for _ in 0..len {
+            txs.push(reader.read_hash()?);
+        }
+
+        Ok(Self::new(txs))
+    }
+}
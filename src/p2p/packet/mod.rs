@@ -2,6 +2,8 @@ pub mod handshake;
 pub mod chain;
 pub mod ping;
 pub mod object;
+pub mod peers;
+pub mod inventory;
 
 use crate::core::reader::{Reader, ReaderError};
 use crate::core::serializer::Serializer;
@@ -10,7 +12,9 @@ use crate::core::writer::Writer;
 use crate::crypto::hash::Hash;
 use self::object::{ObjectRequest, ObjectResponse};
 use self::chain::{ChainRequest, ChainResponse};
-use self::handshake::Handshake;
+use self::handshake::{Handshake, CAPABILITY_PRUNED, CAPABILITY_LIGHT_CLIENT};
+use self::peers::PeerExchange;
+use self::inventory::Inventory;
 use self::ping::Ping;
 use std::borrow::Cow;
 use log::{debug, error};
@@ -24,6 +28,66 @@ const CHAIN_RESPONSE_ID: u8 = 4;
 const PING_ID: u8 = 5;
 const OBJECT_REQUEST_ID: u8 = 6;
 const OBJECT_RESPONSE_ID: u8 = 7;
+const PEERS_ID: u8 = 8;
+const INVENTORY_ID: u8 = 9;
+
+// Kind of a packet, known without decoding its body
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PacketKind {
+    Handshake,
+    TransactionPropagation,
+    BlockPropagation,
+    ChainRequest,
+    ChainResponse,
+    Ping,
+    ObjectRequest,
+    ObjectResponse,
+    Peers,
+    Inventory
+}
+
+impl PacketKind {
+    pub fn from_id(id: u8) -> Option<Self> {
+        Some(match id {
+            HANDSHAKE_ID => PacketKind::Handshake,
+            TX_PROPAGATION_ID => PacketKind::TransactionPropagation,
+            BLOCK_PROPAGATION_ID => PacketKind::BlockPropagation,
+            CHAIN_REQUEST_ID => PacketKind::ChainRequest,
+            CHAIN_RESPONSE_ID => PacketKind::ChainResponse,
+            PING_ID => PacketKind::Ping,
+            OBJECT_REQUEST_ID => PacketKind::ObjectRequest,
+            OBJECT_RESPONSE_ID => PacketKind::ObjectResponse,
+            PEERS_ID => PacketKind::Peers,
+            INVENTORY_ID => PacketKind::Inventory,
+            _ => return None
+        })
+    }
+
+    pub fn get_id(&self) -> u8 {
+        match self {
+            PacketKind::Handshake => HANDSHAKE_ID,
+            PacketKind::TransactionPropagation => TX_PROPAGATION_ID,
+            PacketKind::BlockPropagation => BLOCK_PROPAGATION_ID,
+            PacketKind::ChainRequest => CHAIN_REQUEST_ID,
+            PacketKind::ChainResponse => CHAIN_RESPONSE_ID,
+            PacketKind::Ping => PING_ID,
+            PacketKind::ObjectRequest => OBJECT_REQUEST_ID,
+            PacketKind::ObjectResponse => OBJECT_RESPONSE_ID,
+            PacketKind::Peers => PEERS_ID,
+            PacketKind::Inventory => INVENTORY_ID
+        }
+    }
+
+    // check if a peer with these capabilities may send this packet according to its role
+    // a pruned node can't answer a chain request and a light client has no block to relay
+    pub fn is_allowed(&self, capabilities: u8) -> bool {
+        match self {
+            PacketKind::ChainResponse => capabilities & (CAPABILITY_PRUNED | CAPABILITY_LIGHT_CLIENT) == 0,
+            PacketKind::BlockPropagation => capabilities & CAPABILITY_LIGHT_CLIENT == 0,
+            _ => true
+        }
+    }
+}
 
 // PacketWrapper allows us to link any Packet to a Ping
 pub struct PacketWrapper<'a, T: Serializer + Clone> {
@@ -71,11 +135,42 @@ pub enum Packet<'a> {
     ChainResponse(ChainResponse<'a>),
     Ping(Cow<'a, Ping<'a>>),
     ObjectRequest(PacketWrapper<'a, ObjectRequest>),
-    ObjectResponse(ObjectResponse<'a>)
+    ObjectResponse(ObjectResponse<'a>),
+    Peers(Cow<'a, PeerExchange>), // peer exchange between connected peers
+    Inventory(PacketWrapper<'a, Inventory>) // several tx hashes announced at once
+}
+
+impl<'a> Packet<'a> {
+    // read only the packet id (first byte after the size prefix) without decoding the packet
+    // so an unwanted packet can be rejected before paying the full decode cost
+    pub fn peek_id(bytes: &[u8]) -> Option<u8> {
+        bytes.first().copied()
+    }
+
+    // kind of the packet, None if the id is unknown
+    pub fn peek_kind(bytes: &[u8]) -> Option<PacketKind> {
+        Self::peek_id(bytes).and_then(PacketKind::from_id)
+    }
+
+    pub fn get_kind(&self) -> PacketKind {
+        match self {
+            Packet::Handshake(_) => PacketKind::Handshake,
+            Packet::TransactionPropagation(_) => PacketKind::TransactionPropagation,
+            Packet::BlockPropagation(_) => PacketKind::BlockPropagation,
+            Packet::ChainRequest(_) => PacketKind::ChainRequest,
+            Packet::ChainResponse(_) => PacketKind::ChainResponse,
+            Packet::Ping(_) => PacketKind::Ping,
+            Packet::ObjectRequest(_) => PacketKind::ObjectRequest,
+            Packet::ObjectResponse(_) => PacketKind::ObjectResponse,
+            Packet::Peers(_) => PacketKind::Peers,
+            Packet::Inventory(_) => PacketKind::Inventory
+        }
+    }
 }
 
 impl<'a> Serializer for Packet<'a> {
     fn read(reader: &mut Reader) -> Result<Packet<'a>, ReaderError> {
+        reader.checkpoint("packet id");
         let id = reader.read_u8()?;
         debug!("Packet ID received: {}, size: {}", id, reader.total_size());
         Ok(match id {
@@ -87,6 +182,8 @@ impl<'a> Serializer for Packet<'a> {
             PING_ID => Packet::Ping(Cow::Owned(Ping::read(reader)?)),
             OBJECT_REQUEST_ID => Packet::ObjectRequest(PacketWrapper::read(reader)?),
             OBJECT_RESPONSE_ID => Packet::ObjectResponse(ObjectResponse::read(reader)?),
+            PEERS_ID => Packet::Peers(Cow::Owned(PeerExchange::read(reader)?)),
+            INVENTORY_ID => Packet::Inventory(PacketWrapper::read(reader)?),
             id => {
                 error!("Received a invalid packet id: {}", id);
                 return Err(ReaderError::InvalidValue)
@@ -103,7 +200,9 @@ impl<'a> Serializer for Packet<'a> {
             Packet::ChainResponse(response) => (CHAIN_RESPONSE_ID, response.to_bytes()),
             Packet::Ping(ping) => (PING_ID, ping.to_bytes()),
             Packet::ObjectRequest(request) => (OBJECT_REQUEST_ID, request.to_bytes()),
-            Packet::ObjectResponse(response) => (OBJECT_RESPONSE_ID, response.to_bytes())
+            Packet::ObjectResponse(response) => (OBJECT_RESPONSE_ID, response.to_bytes()),
+            Packet::Peers(peers) => (PEERS_ID, peers.to_bytes()),
+            Packet::Inventory(inventory) => (INVENTORY_ID, inventory.to_bytes())
         };
 
         let packet_len: u32 = packet.len() as u32 + 1;
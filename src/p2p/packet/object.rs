@@ -42,14 +42,16 @@ impl Serializer for ObjectRequest {
 
 pub enum OwnedObjectResponse {
     Block(CompleteBlock),
-    Transaction(Transaction)
+    Transaction(Transaction),
+    Pending(ObjectRequest) // peer is still downloading it, we should retry a bit later
 }
 
 impl OwnedObjectResponse {
     pub fn get_hash(&self) -> Hash {
         match self {
             OwnedObjectResponse::Block(block) => block.hash(),
-            OwnedObjectResponse::Transaction(transaction) => transaction.hash()
+            OwnedObjectResponse::Transaction(transaction) => transaction.hash(),
+            OwnedObjectResponse::Pending(request) => request.get_hash().clone()
         }
     }
 }
@@ -57,7 +59,8 @@ impl OwnedObjectResponse {
 pub enum ObjectResponse<'a> {
     Block(Cow<'a, CompleteBlock>),
     Transaction(Cow<'a, Transaction>),
-    NotFound(ObjectRequest)
+    NotFound(ObjectRequest),
+    Pending(ObjectRequest)
 }
 
 impl ObjectResponse<'_> {
@@ -65,7 +68,8 @@ impl ObjectResponse<'_> {
         match &self {
             ObjectResponse::Block(block) => Cow::Owned(ObjectRequest::Block(block.hash())),
             ObjectResponse::Transaction(tx) => Cow::Owned(ObjectRequest::Transaction(tx.hash())),
-            ObjectResponse::NotFound(request) => Cow::Borrowed(request)
+            ObjectResponse::NotFound(request) => Cow::Borrowed(request),
+            ObjectResponse::Pending(request) => Cow::Borrowed(request)
         }
     }
 
@@ -73,7 +77,8 @@ impl ObjectResponse<'_> {
         Ok(match self {
             ObjectResponse::Block(block) => OwnedObjectResponse::Block(block.into_owned()),
             ObjectResponse::Transaction(tx) => OwnedObjectResponse::Transaction(tx.into_owned()),
-            ObjectResponse::NotFound(request) => return Err(P2pError::ObjectNotFound(request))
+            ObjectResponse::NotFound(request) => return Err(P2pError::ObjectNotFound(request)),
+            ObjectResponse::Pending(request) => OwnedObjectResponse::Pending(request)
         })
     }
 }
@@ -92,6 +97,10 @@ impl<'a> Serializer for ObjectResponse<'a> {
             ObjectResponse::NotFound(obj) => {
                 writer.write_u8(2);
                 obj.write(writer);
+            },
+            ObjectResponse::Pending(obj) => {
+                writer.write_u8(3);
+                obj.write(writer);
             }
         }
     }
@@ -102,7 +111,35 @@ impl<'a> Serializer for ObjectResponse<'a> {
             0 => ObjectResponse::Block(Cow::Owned(CompleteBlock::read(reader)?)),
             1 => ObjectResponse::Transaction(Cow::Owned(Transaction::read(reader)?)),
             2 => ObjectResponse::NotFound(ObjectRequest::read(reader)?),
+            3 => ObjectResponse::Pending(ObjectRequest::read(reader)?),
             _ => return Err(ReaderError::InvalidValue)
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pending_round_trip() {
+        let request = ObjectRequest::Block(Hash::max());
+        let bytes = ObjectResponse::Pending(request.clone()).to_bytes();
+        assert_eq!(bytes[0], 3);
+
+        let response = ObjectResponse::read(&mut Reader::new(&bytes)).unwrap();
+        assert_eq!(*response.get_request(), request);
+        match response.to_owned() {
+            Ok(OwnedObjectResponse::Pending(r)) => assert_eq!(r, request),
+            _ => panic!("expected a pending response")
+        }
+    }
+
+    #[test]
+    fn test_not_found_is_not_pending() {
+        let request = ObjectRequest::Transaction(Hash::zero());
+        let bytes = ObjectResponse::NotFound(request.clone()).to_bytes();
+        let response = ObjectResponse::read(&mut Reader::new(&bytes)).unwrap();
+        assert!(matches!(response.to_owned(), Err(P2pError::ObjectNotFound(r)) if r == request));
+    }
+}
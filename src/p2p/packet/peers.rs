@@ -0,0 +1,53 @@
+use crate::config::P2P_PEER_EXCHANGE_LIMIT;
+use crate::core::reader::{Reader, ReaderError};
+use crate::core::serializer::Serializer;
+use crate::globals::{ip_to_bytes, ip_from_bytes};
+use crate::core::writer::Writer;
+use std::net::SocketAddr;
+
+// Peer exchange between already connected peers
+// it can carry more addresses than a handshake, its rate is limited like the ping peer list
+#[derive(Clone)]
+pub struct PeerExchange {
+    peers: Vec<SocketAddr>
+}
+
+impl PeerExchange {
+    pub fn new(peers: Vec<SocketAddr>) -> Self {
+        Self {
+            peers
+        }
+    }
+
+    pub fn get_peers(&self) -> &Vec<SocketAddr> {
+        &self.peers
+    }
+
+    pub fn into_peers(self) -> Vec<SocketAddr> {
+        self.peers
+    }
+}
+
+impl Serializer for PeerExchange {
+    fn write(&self, writer: &mut Writer) {
+        writer.write_u8(self.peers.len() as u8);
+        for peer in &self.peers {
+            writer.write_bytes(&ip_to_bytes(peer));
+        }
+    }
+
+    fn read(reader: &mut Reader) -> Result<Self, ReaderError> {
+        reader.checkpoint("peers");
+        let peers_len = reader.read_u8()? as usize;
+        if peers_len == 0 || peers_len > P2P_PEER_EXCHANGE_LIMIT {
+            return Err(ReaderError::InvalidValue)
+        }
+
+        let mut peers = Vec::with_capacity(peers_len);
+        for _ in 0..peers_len {
+            peers.push(ip_from_bytes(reader)?);
+        }
+
+        Ok(Self::new(peers))
+    }
+}
@@ -53,8 +53,10 @@ impl Serializer for Ping<'_> {
     }
 
     fn read(reader: &mut Reader) -> Result<Self, ReaderError> {
+        reader.checkpoint("ping top hash");
         let block_top_hash = Cow::Owned(reader.read_hash()?);
         let block_height = reader.read_u64()?;
+        reader.checkpoint("ping peers");
         let peers_len = reader.read_u8()? as usize;
         if peers_len > P2P_PING_PEER_LIST_LIMIT {
             return Err(ReaderError::InvalidValue)
@@ -1,68 +1,134 @@
-use crate::config::PEER_TIMEOUT_REQUEST_OBJECT;
+use crate::config::{PEER_TIMEOUT_REQUEST_OBJECT, PEER_OBJECT_PENDING_RETRY_DELAY, PEER_OBJECT_PENDING_MAX_RETRIES};
 use crate::core::serializer::Serializer;
+use crate::globals::get_monotonic_time;
 use crate::crypto::hash::Hash;
 use super::packet::object::{ObjectRequest, OwnedObjectResponse};
 use super::packet::ping::Ping;
 use super::peer_list::SharedPeerList;
 use super::connection::{Connection, ConnectionMessage};
-use super::packet::{Packet, PacketWrapper};
+use super::packet::{Packet, PacketKind, PacketWrapper};
 use super::error::P2pError;
 use std::net::SocketAddr;
-use std::sync::atomic::{AtomicU8, AtomicU64, AtomicBool, Ordering};
+use std::sync::atomic::{AtomicU8, AtomicU64, AtomicBool, AtomicUsize, Ordering};
 use std::fmt::{Display, Error, Formatter};
 use std::time::Duration;
 use tokio::sync::oneshot::Sender;
-use tokio::time::timeout;
+use tokio::time::{timeout, sleep};
 use std::collections::{HashMap, HashSet};
 use tokio::sync::Mutex;
 use std::borrow::Cow;
 use bytes::Bytes;
+use log::debug;
 
 pub type RequestedObjects = HashMap<ObjectRequest, Sender<OwnedObjectResponse>>;
 
+// All public data of a peer, used by RPC
+#[derive(serde::Serialize)]
+pub struct PeerInfo {
+    pub addr: SocketAddr,
+    pub peer_id: u64,
+    pub height: u64,
+    pub top_hash: Hash,
+    pub version: String,
+    pub node_tag: Option<String>,
+    pub protocol_version: u8,
+    pub capabilities: u8,
+    pub bytes_in: usize,
+    pub bytes_out: usize,
+    pub packet_sizes_in: Vec<u64>, // packets received per size bucket, see PacketSizeHistogram
+    pub packet_sizes_out: Vec<u64>, // packets sent per size bucket
+    pub connected_on: u64,
+    pub is_out: bool,
+    pub score: u8, // fail count of the peer, lower is better
+    pub requests_in_flight: usize
+}
+
+// data announced by the peer in its handshake, see Handshake::create_peer
+pub struct PeerDetails {
+    pub id: u64,
+    pub node_tag: Option<String>, // Node tag if provided
+    pub local_port: u16, // port the peer listens on
+    pub version: String, // daemon version
+    pub protocol_version: u8, // p2p protocol version used with this peer, the lowest of both
+    pub capabilities: u8, // role & optional features
+    pub block_top_hash: Hash,
+    pub block_height: u64
+}
+
 pub struct Peer {
     connection: Connection,
     id: u64,
     node_tag: Option<String>, // Node tag if provided
     local_port: u16,
     version: String, // daemon version
+    protocol_version: u8, // p2p protocol version used with this peer, the lowest of both
+    capabilities: u8, // role & optional features advertised in the handshake
     out: bool, // True mean we are the client
     priority: bool, // if this node can be trusted (seed node or added manually by user)
     block_top_hash: Mutex<Hash>, // current block top hash for this peer
     block_height: AtomicU64, // current block height for this peer
-    last_chain_sync: AtomicU64,
+    last_chain_sync: AtomicU64, // last time (monotonic) this peer requested our chain
     // TODO last_fail_count
     fail_count: AtomicU8, // fail count: if greater than 20, we should close this connection
+    unknown_packets: AtomicU8, // packets received with an unknown id
     peer_list: SharedPeerList,
     chain_requested: AtomicBool,
     objects_requested: Mutex<RequestedObjects>,
+    requests_in_flight: AtomicUsize, // object requests scheduled on this peer during a sync
     peers: Mutex<HashSet<SocketAddr>>, // all peers from this peer
-    last_peer_list_update: AtomicU64, // last time we send our peerlist to this peer
-    last_peer_list: AtomicU64, // last time we received a peerlist from this peer
-    last_ping: AtomicU64 // last time we got a ping packet from this peer
+    last_peer_list_update: AtomicU64, // last time (monotonic) we send our peerlist to this peer
+    last_peer_list: AtomicU64, // last time (monotonic) we received a peerlist from this peer
+    last_ping: AtomicU64, // last time (monotonic) we got a ping packet from this peer
+    last_activity: AtomicU64 // last time (monotonic) we received any packet from this peer
 }
 
 impl Peer {
-    pub fn new(connection: Connection, id: u64, node_tag: Option<String>, local_port: u16, version: String, block_top_hash: Hash, block_height: u64, out: bool, priority: bool, peer_list: SharedPeerList, peers: HashSet<SocketAddr>) -> Self {
+    pub fn new(connection: Connection, details: PeerDetails, out: bool, priority: bool, peer_list: SharedPeerList, peers: HashSet<SocketAddr>) -> Self {
         Self {
             connection,
-            id,
-            node_tag,
-            local_port,
-            version,
-            block_top_hash: Mutex::new(block_top_hash),
-            block_height: AtomicU64::new(block_height),
+            id: details.id,
+            node_tag: details.node_tag,
+            local_port: details.local_port,
+            version: details.version,
+            protocol_version: details.protocol_version,
+            capabilities: details.capabilities,
+            block_top_hash: Mutex::new(details.block_top_hash),
+            block_height: AtomicU64::new(details.block_height),
             out,
             priority,
             fail_count: AtomicU8::new(0),
+            unknown_packets: AtomicU8::new(0),
             last_chain_sync: AtomicU64::new(0),
             peer_list,
             chain_requested: AtomicBool::new(false),
             objects_requested: Mutex::new(HashMap::new()),
+            requests_in_flight: AtomicUsize::new(0),
             peers: Mutex::new(peers),
             last_peer_list_update: AtomicU64::new(0),
             last_peer_list: AtomicU64::new(0),
-            last_ping: AtomicU64::new(0)
+            last_ping: AtomicU64::new(0),
+            last_activity: AtomicU64::new(get_monotonic_time()) // handshake was just received
+        }
+    }
+
+    pub async fn info(&self) -> PeerInfo {
+        PeerInfo {
+            addr: *self.connection.get_address(),
+            peer_id: self.id,
+            height: self.get_block_height(),
+            top_hash: self.block_top_hash.lock().await.clone(),
+            version: self.version.clone(),
+            node_tag: self.node_tag.clone(),
+            protocol_version: self.protocol_version,
+            capabilities: self.capabilities,
+            bytes_in: self.connection.bytes_in(),
+            bytes_out: self.connection.bytes_out(),
+            packet_sizes_in: self.connection.get_packet_sizes_in().get_counts(),
+            packet_sizes_out: self.connection.get_packet_sizes_out().get_counts(),
+            connected_on: self.connection.connected_on(),
+            is_out: self.out,
+            score: self.get_fail_count(),
+            requests_in_flight: self.get_requests_in_flight()
         }
     }
 
@@ -82,6 +148,37 @@ impl Peer {
         self.local_port
     }
 
+    // address other nodes can dial, the local port is used if the peer connected to us
+    pub fn get_advertised_address(&self) -> SocketAddr {
+        let mut addr = *self.connection.get_address();
+        if !self.out {
+            addr.set_port(self.local_port);
+        }
+        addr
+    }
+
+    pub fn get_protocol_version(&self) -> u8 {
+        self.protocol_version
+    }
+
+    // txs in the extended format (inputs, expiration, data) are only known from the protocol version 1
+    pub fn supports_extended_transactions(&self) -> bool {
+        self.protocol_version >= 1
+    }
+
+    pub fn get_capabilities(&self) -> u8 {
+        self.capabilities
+    }
+
+    pub fn has_capability(&self, capability: u8) -> bool {
+        self.capabilities & capability == capability
+    }
+
+    // a pruned node or a light client can't send us its chain
+    pub fn can_serve_chain(&self) -> bool {
+        PacketKind::ChainResponse.is_allowed(self.capabilities)
+    }
+
     pub fn get_version(&self) -> &String {
         &self.version
     }
@@ -102,6 +199,19 @@ impl Peer {
         &self.block_top_hash
     }
 
+    // compared to the chain state reported by the peer in its handshake and pings
+    pub fn is_ahead_of(&self, our_height: u64) -> bool {
+        self.get_block_height() > our_height
+    }
+
+    pub fn is_behind(&self, our_height: u64) -> bool {
+        self.get_block_height() < our_height
+    }
+
+    pub async fn is_same_tip(&self, our_top_hash: &Hash) -> bool {
+        *self.block_top_hash.lock().await == *our_top_hash
+    }
+
     pub fn is_out(&self) -> bool {
         self.out
     }
@@ -119,6 +229,15 @@ impl Peer {
         self.fail_count.fetch_add(1, Ordering::Relaxed);
     }
 
+    pub fn get_unknown_packets(&self) -> u8 {
+        self.unknown_packets.load(Ordering::Relaxed)
+    }
+
+    // returns the new count of unknown packets
+    pub fn increment_unknown_packets(&self) -> u8 {
+        self.unknown_packets.fetch_add(1, Ordering::Relaxed).saturating_add(1)
+    }
+
     pub fn get_last_chain_sync(&self) -> u64 {
         self.last_chain_sync.load(Ordering::Relaxed)
     }
@@ -135,6 +254,18 @@ impl Peer {
         self.chain_requested.store(value, Ordering::Relaxed);
     }
 
+    pub fn get_requests_in_flight(&self) -> usize {
+        self.requests_in_flight.load(Ordering::Relaxed)
+    }
+
+    pub fn increment_requests_in_flight(&self) {
+        self.requests_in_flight.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn decrement_requests_in_flight(&self) {
+        self.requests_in_flight.fetch_sub(1, Ordering::Relaxed);
+    }
+
     pub fn get_objects_requested(&self) -> &Mutex<RequestedObjects> {
         &self.objects_requested
     }
@@ -144,32 +275,57 @@ impl Peer {
         objects.remove(&request).ok_or(P2pError::ObjectNotFound(request))
     }
 
-    // Request a object from this peer and wait on it until we receive it or until timeout 
+    // Drop all pending object requests of this peer
+    // each waiter will be notified with a RecvError and can retry with another peer
+    pub async fn clear_object_requests(&self) {
+        let mut objects = self.objects_requested.lock().await;
+        if !objects.is_empty() {
+            debug!("Cancelling {} pending object requests for peer {}", objects.len(), self.get_connection().get_address());
+        }
+        objects.clear();
+    }
+
+    // Request a object from this peer and wait on it until we receive it or until timeout
+    // if the peer is still downloading it, we wait a bit and ask it again
     pub async fn request_blocking_object(&self, request: ObjectRequest, ping: &Ping<'_>) -> Result<OwnedObjectResponse, P2pError> {
-        let receiver = {
-            let mut objects = self.objects_requested.lock().await;
-            if objects.contains_key(&request) {
-                return Err(P2pError::ObjectAlreadyRequested(request));
-            }
-            self.send_packet(Packet::ObjectRequest(PacketWrapper::new(Cow::Borrowed(&request), Cow::Borrowed(ping)))).await?;
-            let (sender, receiver) = tokio::sync::oneshot::channel();
-            objects.insert(request.clone(), sender); // clone is necessary in case timeout has occured
-            receiver
-        };
-        let object = match timeout(Duration::from_millis(PEER_TIMEOUT_REQUEST_OBJECT), receiver).await {
-            Ok(res) => res?,
-            Err(e) => {
+        let mut retries = 0;
+        loop {
+            let receiver = {
                 let mut objects = self.objects_requested.lock().await;
-                objects.remove(&request); // remove it from request list
-                return Err(P2pError::AsyncTimeOut(e));
+                if objects.contains_key(&request) {
+                    return Err(P2pError::ObjectAlreadyRequested(request));
+                }
+                self.send_packet(Packet::ObjectRequest(PacketWrapper::new(Cow::Borrowed(&request), Cow::Borrowed(ping)))).await?;
+                let (sender, receiver) = tokio::sync::oneshot::channel();
+                objects.insert(request.clone(), sender); // clone is necessary in case timeout has occured
+                receiver
+            };
+            let object = match timeout(Duration::from_millis(PEER_TIMEOUT_REQUEST_OBJECT), receiver).await {
+                Ok(res) => res?,
+                Err(e) => {
+                    let mut objects = self.objects_requested.lock().await;
+                    objects.remove(&request); // remove it from request list
+                    return Err(P2pError::AsyncTimeOut(e));
+                }
+            };
+
+            if let OwnedObjectResponse::Pending(_) = object {
+                if retries >= PEER_OBJECT_PENDING_MAX_RETRIES {
+                    return Err(P2pError::ObjectNotFound(request))
+                }
+                retries += 1;
+                debug!("Object {:?} is pending on peer {}, retrying ({}/{})", request, self.get_connection().get_address(), retries, PEER_OBJECT_PENDING_MAX_RETRIES);
+                sleep(Duration::from_millis(PEER_OBJECT_PENDING_RETRY_DELAY)).await;
+                continue;
             }
-        };
-        let object_hash = object.get_hash();
-        if object_hash != *request.get_hash() {
-            return Err(P2pError::InvalidObjectResponse(request, object_hash))
-        }
 
-        Ok(object)
+            let object_hash = object.get_hash();
+            if object_hash != *request.get_hash() {
+                return Err(P2pError::InvalidObjectResponse(request, object_hash))
+            }
+
+            return Ok(object)
+        }
     }
 
     pub fn get_peers(&self) -> &Mutex<HashSet<SocketAddr>> {
@@ -200,8 +356,23 @@ impl Peer {
         self.last_ping.store(value, Ordering::Relaxed)
     }
 
+    pub fn get_last_activity(&self) -> u64 {
+        self.last_activity.load(Ordering::Relaxed)
+    }
+
+    pub fn set_last_activity(&self, value: u64) {
+        self.last_activity.store(value, Ordering::Relaxed)
+    }
+
+    // reason is set before removing the peer so it appears in the disconnection log
+    pub async fn close_with_reason(&self, reason: &str) -> Result<(), P2pError> {
+        self.get_connection().set_close_reason(reason);
+        self.close().await
+    }
+
     pub async fn close(&self) -> Result<(), P2pError> {
         self.peer_list.lock().await.remove_peer(&self);
+        self.clear_object_requests().await;
         self.get_connection().close().await?;
         Ok(())
     }
@@ -229,4 +400,152 @@ impl Display for Peer {
             self.is_out()
         )
     }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use super::super::peer_list::PeerList;
+    use crate::core::transaction::{Transaction, TransactionVariant};
+    use crate::crypto::hash::Hashable;
+    use crate::crypto::key::KeyPair;
+    use std::sync::Arc;
+    use tokio::io::{duplex, DuplexStream};
+
+    // peer without capabilities at genesis state
+    pub(crate) fn test_details(id: u64, local_port: u16) -> PeerDetails {
+        PeerDetails {
+            id,
+            node_tag: None,
+            local_port,
+            version: "test".to_owned(),
+            protocol_version: 1,
+            capabilities: 0,
+            block_top_hash: Hash::zero(),
+            block_height: 0
+        }
+    }
+
+    // the remote side must be kept alive for the connection to stay open
+    fn create_test_peer() -> (Arc<Peer>, DuplexStream) {
+        let (local, remote) = duplex(4096);
+        let connection = Connection::new(local, "127.0.0.1:2125".parse().unwrap());
+        let peer = Peer::new(connection, test_details(1, 2125), true, false, PeerList::new(), HashSet::new());
+        (Arc::new(peer), remote)
+    }
+
+    #[test]
+    fn test_advertised_address() {
+        // the IP we see with the port the peer listens on, not the port it connected from
+        let (local, _remote) = duplex(4096);
+        let connection = Connection::new(local, "10.0.0.1:51234".parse().unwrap());
+        let incoming = Peer::new(connection, test_details(1, 2125), false, false, PeerList::new(), HashSet::new());
+        assert_eq!(incoming.get_advertised_address(), "10.0.0.1:2125".parse().unwrap());
+
+        // we dialed this one, its address is already the one it listens on
+        let (local, _remote) = duplex(4096);
+        let connection = Connection::new(local, "10.0.0.2:2126".parse().unwrap());
+        let outgoing = Peer::new(connection, test_details(2, 2125), true, false, PeerList::new(), HashSet::new());
+        assert_eq!(outgoing.get_advertised_address(), "10.0.0.2:2126".parse().unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_chain_state_comparison() {
+        let (peer, _remote) = create_test_peer();
+        peer.set_block_height(10);
+        peer.set_block_top_hash(Hash::max()).await;
+
+        // ahead
+        assert!(peer.is_ahead_of(9));
+        assert!(!peer.is_behind(9));
+        // equal height, the tips can still differ
+        assert!(!peer.is_ahead_of(10));
+        assert!(!peer.is_behind(10));
+        assert!(peer.is_same_tip(&Hash::max()).await);
+        assert!(!peer.is_same_tip(&Hash::zero()).await);
+        // behind
+        assert!(!peer.is_ahead_of(11));
+        assert!(peer.is_behind(11));
+
+        // the state compared is the latest one stored
+        peer.set_block_height(12);
+        peer.set_block_top_hash(Hash::zero()).await;
+        assert!(peer.is_ahead_of(11));
+        assert!(peer.is_same_tip(&Hash::zero()).await);
+    }
+
+    // wait until the request is registered on the peer
+    async fn wait_for_request(peer: &Peer, request: &ObjectRequest) {
+        while !peer.get_objects_requested().lock().await.contains_key(request) {
+            sleep(Duration::from_millis(5)).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_requests_cancelled_on_close() {
+        let (peer, _remote) = create_test_peer();
+        let request = ObjectRequest::Transaction(Hash::max());
+        let handle = {
+            let peer = Arc::clone(&peer);
+            let request = request.clone();
+            tokio::spawn(async move {
+                let ping = Ping::new(Cow::Owned(Hash::zero()), 0, Vec::new());
+                peer.request_blocking_object(request, &ping).await.is_err()
+            })
+        };
+        wait_for_request(&peer, &request).await;
+        peer.close().await.unwrap();
+
+        // the waiter is woken up well before the request timeout
+        let failed = timeout(Duration::from_millis(PEER_TIMEOUT_REQUEST_OBJECT / 2), handle).await.unwrap().unwrap();
+        assert!(failed);
+        assert!(peer.get_objects_requested().lock().await.is_empty());
+    }
+
+    // answer the request registered on the peer as if it was received from the network
+    async fn respond(peer: &Peer, request: &ObjectRequest, response: OwnedObjectResponse) {
+        wait_for_request(peer, request).await;
+        let sender = peer.remove_object_request(request.clone()).await.unwrap();
+        assert!(sender.send(response).is_ok());
+    }
+
+    fn spawn_request(peer: &Arc<Peer>, request: &ObjectRequest) -> tokio::task::JoinHandle<Result<OwnedObjectResponse, P2pError>> {
+        let peer = Arc::clone(peer);
+        let request = request.clone();
+        tokio::spawn(async move {
+            let ping = Ping::new(Cow::Owned(Hash::zero()), 0, Vec::new());
+            peer.request_blocking_object(request, &ping).await
+        })
+    }
+
+    #[tokio::test]
+    async fn test_pending_object_is_retried() {
+        let (peer, _remote) = create_test_peer();
+        let tx = Transaction::new(KeyPair::new().get_public_key().clone(), TransactionVariant::Coinbase);
+        let request = ObjectRequest::Transaction(tx.hash());
+        let handle = spawn_request(&peer, &request);
+
+        // first answer is pending, the same request is sent again after a short delay
+        respond(&peer, &request, OwnedObjectResponse::Pending(request.clone())).await;
+        respond(&peer, &request, OwnedObjectResponse::Transaction(tx.clone())).await;
+
+        match handle.await.unwrap() {
+            Ok(OwnedObjectResponse::Transaction(received)) => assert_eq!(received.hash(), tx.hash()),
+            _ => panic!("expected the transaction after a pending response")
+        }
+    }
+
+    #[tokio::test]
+    async fn test_pending_object_gives_up_after_max_retries() {
+        let (peer, _remote) = create_test_peer();
+        let request = ObjectRequest::Block(Hash::max());
+        let handle = spawn_request(&peer, &request);
+
+        for _ in 0..=PEER_OBJECT_PENDING_MAX_RETRIES {
+            respond(&peer, &request, OwnedObjectResponse::Pending(request.clone())).await;
+        }
+
+        assert!(matches!(handle.await.unwrap(), Err(P2pError::ObjectNotFound(r)) if r == request));
+        assert!(peer.get_objects_requested().lock().await.is_empty());
+    }
 }
\ No newline at end of file
@@ -1,5 +1,9 @@
+use crate::globals::{get_ip_subnet, get_monotonic_time};
+use crate::config::{P2P_KNOWN_ADDRESSES_LIMIT, P2P_DISCONNECT_HISTORY_SIZE, P2P_DISCONNECT_MAX_ADDRESSES, P2P_DISCONNECT_BACKOFF};
+use super::error::P2pError;
 use super::peer::Peer;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::net::{IpAddr, SocketAddr};
 use tokio::sync::Mutex;
 use std::sync::Arc;
 use bytes::Bytes;
@@ -7,18 +11,76 @@ use log::{info, debug, error};
 
 pub type SharedPeerList = Arc<Mutex<PeerList>>;
 
+// Why a peer was disconnected, used to delay the next connection to its address
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DisconnectReason {
+    Timeout, // handshake or request not answered in time
+    Idle, // nothing received for too long
+    InvalidPacket, // packet can't be decoded
+    InvalidHandshake, // handshake is invalid or not on the same chain
+    ProtocolViolation, // packets sent faster than protocol rules
+    TooManyFailures, // fail count limit reached
+    WrongNetwork // different network id
+}
+
+impl DisconnectReason {
+    // returns None for errors which are not the fault of the peer
+    pub fn from_error(error: &P2pError) -> Option<Self> {
+        Some(match error {
+            P2pError::AsyncTimeOut(_) => DisconnectReason::Timeout,
+            P2pError::InvalidPacket | P2pError::UnknownPacketId(_) | P2pError::InvalidPacketSize | P2pError::InvalidPacketNotFullRead | P2pError::ReaderError(_) => DisconnectReason::InvalidPacket,
+            P2pError::InvalidHandshake | P2pError::InvalidHandshakeField(_) | P2pError::ExpectedHandshake => DisconnectReason::InvalidHandshake,
+            P2pError::PeerInvalidPeerListCountdown | P2pError::PeerInvalidPingCoutdown | P2pError::RequestSyncChainTooFast | P2pError::TooManyUnknownPackets(_) | P2pError::PacketNotAllowed(_) | P2pError::HandshakeAlreadyDone => DisconnectReason::ProtocolViolation,
+            P2pError::InvalidNetworkID | P2pError::WrongChain { .. } | P2pError::InvalidHashAlgorithm(_, _) => DisconnectReason::WrongNetwork,
+            _ => return None
+        })
+    }
+
+    // the peer broke the protocol rules, unlike a timeout which can be caused by the network
+    pub fn is_violation(&self) -> bool {
+        !matches!(self, DisconnectReason::Timeout | DisconnectReason::Idle)
+    }
+
+    pub fn get_severity(&self) -> u64 {
+        match self {
+            DisconnectReason::Timeout | DisconnectReason::Idle => 1,
+            DisconnectReason::InvalidPacket | DisconnectReason::InvalidHandshake | DisconnectReason::ProtocolViolation => 3,
+            DisconnectReason::TooManyFailures | DisconnectReason::WrongNetwork => 6
+        }
+    }
+}
+
+// recent disconnects of an address, oldest first
+struct DisconnectHistory {
+    records: VecDeque<(DisconnectReason, u64)> // reason, monotonic time in seconds
+}
+
+impl DisconnectHistory {
+    fn get_last_time(&self) -> u64 {
+        self.records.back().map_or(0, |(_, time)| *time)
+    }
+
+    fn get_score(&self) -> u64 {
+        self.records.iter().map(|(reason, _)| reason.get_severity()).sum()
+    }
+}
+
 // this object will be shared in Server, and each Peer
 // so when we call Peer#close it will remove it from the list too
 pub struct PeerList {
-    peers: HashMap<u64, Arc<Peer>>
+    peers: BTreeMap<u64, Arc<Peer>>, // ordered by peer id so peers are always iterated in the same order
+    known_addresses: VecDeque<SocketAddr>, // addresses shared by peers that we didn't dial yet, oldest first
+    disconnects: HashMap<IpAddr, DisconnectHistory> // recent disconnect reasons per address
 }
 
 impl PeerList {
-    pub fn new(capacity: usize) -> SharedPeerList {
+    pub fn new() -> SharedPeerList {
         Arc::new(
             Mutex::new(
                 Self {
-                    peers: HashMap::with_capacity(capacity)
+                    peers: BTreeMap::new(),
+                    known_addresses: VecDeque::new(),
+                    disconnects: HashMap::new()
                 }
             )
         )
@@ -40,7 +102,7 @@ impl PeerList {
         self.peers.contains_key(peer_id)
     }
 
-    pub fn get_peers(&self) -> &HashMap<u64, Arc<Peer>> {
+    pub fn get_peers(&self) -> &BTreeMap<u64, Arc<Peer>> {
         &self.peers
     }
 
@@ -48,10 +110,37 @@ impl PeerList {
         self.peers.len()
     }
 
-    pub async fn close_all(&mut self) {
+    // owned copy of the advertised address of each peer
+    // so packets can be built and sent once the lock is released
+    pub fn snapshot(&self) -> Vec<SocketAddr> {
+        self.peers.values().map(|peer| peer.get_advertised_address()).collect()
+    }
+
+    // keep an address to dial it later, the oldest one is dropped when the limit is reached
+    pub fn remember_address(&mut self, addr: SocketAddr) {
+        if self.known_addresses.contains(&addr) {
+            return
+        }
+
+        if self.known_addresses.len() >= P2P_KNOWN_ADDRESSES_LIMIT {
+            self.known_addresses.pop_front();
+        }
+        self.known_addresses.push_back(addr);
+    }
+
+    // oldest known address, removed from the list
+    pub fn take_known_address(&mut self) -> Option<SocketAddr> {
+        self.known_addresses.pop_front()
+    }
+
+    pub fn get_known_addresses(&self) -> &VecDeque<SocketAddr> {
+        &self.known_addresses
+    }
+
+    pub async fn close_all(&mut self, reason: &str) {
         for (_, peer) in self.peers.iter() {
             debug!("Closing peer: {}", peer);
-            if let Err(e) = peer.get_connection().close().await {
+            if let Err(e) = peer.get_connection().close_with_reason(reason).await {
                 error!("Error while trying to close peer {}: {}", peer.get_connection().get_address(), e);
             }
         }
@@ -77,6 +166,58 @@ impl PeerList {
         }
     }
 
+    // number of outgoing peers for each subnet (/16 for IPv4, /32 for IPv6)
+    pub fn get_subnets_distribution(&self) -> HashMap<IpAddr, usize> {
+        let mut subnets = HashMap::new();
+        for peer in self.peers.values().filter(|p| p.is_out()) {
+            let subnet = get_ip_subnet(&peer.get_connection().get_address().ip());
+            *subnets.entry(subnet).or_insert(0) += 1;
+        }
+        subnets
+    }
+
+    // number of outgoing peers in the same subnet as this IP
+    pub fn count_outgoing_peers_in_subnet(&self, ip: &IpAddr) -> usize {
+        let subnet = get_ip_subnet(ip);
+        self.peers.values().filter(|p| p.is_out() && get_ip_subnet(&p.get_connection().get_address().ip()) == subnet).count()
+    }
+
+    // register why we disconnected from this address
+    // each reason increases the time to wait before dialing it again based on its severity
+    pub fn record_disconnect(&mut self, ip: IpAddr, reason: DisconnectReason) {
+        debug!("Recording disconnect of {}: {:?}", ip, reason);
+        if !self.disconnects.contains_key(&ip) && self.disconnects.len() >= P2P_DISCONNECT_MAX_ADDRESSES {
+            // forget the address with the oldest disconnect
+            let oldest = self.disconnects.iter().min_by_key(|(_, history)| history.get_last_time()).map(|(ip, _)| *ip);
+            if let Some(oldest) = oldest {
+                self.disconnects.remove(&oldest);
+            }
+        }
+
+        let history = self.disconnects.entry(ip).or_insert_with(|| DisconnectHistory { records: VecDeque::with_capacity(P2P_DISCONNECT_HISTORY_SIZE) });
+        if history.records.len() >= P2P_DISCONNECT_HISTORY_SIZE {
+            history.records.pop_front();
+        }
+        history.records.push_back((reason, get_monotonic_time()));
+    }
+
+    // recent disconnect reasons with their monotonic time, oldest first
+    pub fn get_disconnect_reasons(&self, ip: &IpAddr) -> Vec<(DisconnectReason, u64)> {
+        self.disconnects.get(ip).map_or_else(Vec::new, |history| history.records.iter().cloned().collect())
+    }
+
+    // time in seconds to wait after the last disconnect before dialing this address again
+    pub fn get_backoff(&self, ip: &IpAddr) -> u64 {
+        self.disconnects.get(ip).map_or(0, |history| history.get_score() * P2P_DISCONNECT_BACKOFF)
+    }
+
+    pub fn is_in_backoff(&self, ip: &IpAddr) -> bool {
+        match self.disconnects.get(ip) {
+            Some(history) => get_monotonic_time() < history.get_last_time() + self.get_backoff(ip),
+            None => false
+        }
+    }
+
     pub fn get_best_height(&self) -> u64 { // TODO: Calculate median of all peers
         let mut best_height = 0;
         for (_, peer) in self.peers.iter() {
@@ -87,4 +228,66 @@ impl PeerList {
         }
         best_height
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::p2p::connection::Connection;
+    use crate::p2p::peer::tests::test_details;
+    use std::collections::HashSet;
+    use tokio::io::{duplex, DuplexStream};
+
+    fn create_peer(peer_list: &SharedPeerList, id: u64, addr: &str, local_port: u16, out: bool) -> (Peer, DuplexStream) {
+        let (local, remote) = duplex(1024);
+        let connection = Connection::new(local, addr.parse().unwrap());
+        (Peer::new(connection, test_details(id, local_port), out, false, Arc::clone(peer_list), HashSet::new()), remote)
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_is_an_owned_copy() {
+        let peer_list = PeerList::new();
+        // an incoming peer is advertised on its local port, not the port it connected from
+        let (incoming, _incoming_remote) = create_peer(&peer_list, 1, "10.0.0.1:50000", 2125, false);
+        let (outgoing, _outgoing_remote) = create_peer(&peer_list, 2, "10.0.0.2:2126", 2000, true);
+        let removed = {
+            let mut list = peer_list.lock().await;
+            let removed = list.add_peer(1, incoming);
+            list.add_peer(2, outgoing);
+            removed
+        };
+
+        let snapshot = peer_list.lock().await.snapshot();
+        let expected: Vec<SocketAddr> = vec!["10.0.0.1:2125".parse().unwrap(), "10.0.0.2:2126".parse().unwrap()];
+        assert_eq!(snapshot, expected);
+
+        // the list can be updated while the snapshot is used, the snapshot is left unchanged
+        let mut list = peer_list.lock().await;
+        list.remove_peer(&removed);
+        assert_eq!(snapshot, expected);
+        assert_eq!(list.snapshot(), vec!["10.0.0.2:2126".parse::<SocketAddr>().unwrap()]);
+    }
+
+    #[tokio::test]
+    async fn test_peers_iterated_by_peer_id() {
+        let peer_list = PeerList::new();
+        let mut remotes = Vec::new();
+        let mut list = peer_list.lock().await;
+        for (i, id) in [42u64, 7, 1000, 3, 99].iter().enumerate() {
+            let (peer, remote) = create_peer(&peer_list, *id, &format!("10.0.0.{}:2125", i + 1), 2125, true);
+            list.add_peer(*id, peer);
+            remotes.push(remote);
+        }
+
+        // same order on each call, whatever the order the peers were added in
+        let ids: Vec<u64> = list.get_peers().keys().copied().collect();
+        assert_eq!(ids, vec![3, 7, 42, 99, 1000]);
+        let snapshot = list.snapshot();
+        let expected: Vec<SocketAddr> = ["10.0.0.4:2125", "10.0.0.2:2125", "10.0.0.1:2125", "10.0.0.5:2125", "10.0.0.3:2125"].iter().map(|addr| addr.parse().unwrap()).collect();
+        assert_eq!(snapshot, expected);
+        for _ in 0..10 {
+            assert_eq!(list.get_peers().keys().copied().collect::<Vec<u64>>(), ids);
+            assert_eq!(list.snapshot(), snapshot);
+        }
+    }
 }
\ No newline at end of file
@@ -1,112 +1,318 @@
-use crate::config::{VERSION, NETWORK_ID, SEED_NODES, MAX_BLOCK_SIZE, CHAIN_SYNC_DELAY, P2P_PING_DELAY, CHAIN_SYNC_REQUEST_MAX_BLOCKS, MAX_BLOCK_REWIND, P2P_PING_PEER_LIST_DELAY, P2P_PING_PEER_LIST_LIMIT};
+use crate::config::{MAX_BLOCK_SIZE, CHAIN_SYNC_DELAY, CHAIN_SYNC_STALL_TIMEOUT, CHAIN_TIP_DISAGREEMENT_PERCENT, P2P_PING_DELAY, CHAIN_SYNC_REQUEST_MAX_BLOCKS, P2P_PING_PEER_LIST_DELAY, P2P_PING_PEER_LIST_LIMIT, P2P_PEER_EXCHANGE_LIMIT, P2P_BLOCKS_CHANNEL_CAPACITY, P2P_PROXY_CONNECT_TIMEOUT, P2P_REORGS_CHANNEL_CAPACITY, P2P_VIOLATIONS_CHANNEL_CAPACITY, P2P_PRIORITY_NODES_RETRY_DELAY, P2P_KNOWN_ADDRESSES_DIAL_DELAY, P2P_HEIGHT_OUTLIER_THRESHOLD, P2P_UNKNOWN_PACKETS_LIMIT, P2P_INVENTORY_MAX_SIZE, PROTOCOL_VERSION};
 use crate::core::blockchain::Blockchain;
 use crate::core::error::BlockchainError;
 use crate::core::serializer::Serializer;
 use crate::core::block::CompleteBlock;
-use crate::globals::get_current_time;
-use crate::crypto::hash::{Hashable, Hash};
+use crate::globals::{get_monotonic_time, has_elapsed, canonical_addr};
+use crate::crypto::hash::{Hashable, Hash, HashAlgorithm};
 use crate::core::writer::Writer;
 use crate::p2p::connection::ConnectionMessage;
 use crate::p2p::packet::chain::CommonPoint;
 use super::packet::chain::{BlockId, ChainRequest, ChainResponse};
 use super::packet::object::{ObjectRequest, ObjectResponse, OwnedObjectResponse};
-use super::peer_list::{SharedPeerList, PeerList};
+use super::peer_list::{SharedPeerList, PeerList, DisconnectReason};
 use super::connection::{State, Connection};
-use super::packet::handshake::Handshake;
+use super::packet::handshake::{Handshake, HandshakeBuilder, CAPABILITY_PACKET_SEQUENCE, CAPABILITY_PEER_EXCHANGE, CAPABILITY_INVENTORY, CAPABILITY_PENDING_OBJECT};
+use super::packet::peers::PeerExchange;
+use super::packet::inventory::Inventory;
 use super::packet::ping::Ping;
 use super::error::P2pError;
+use super::socks;
+use super::download::{DownloadBudget, DownloadReservation};
 use super::packet::{Packet, PacketWrapper};
-use super::peer::Peer;
+use super::peer::{Peer, PeerInfo};
 use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{broadcast, Mutex, Semaphore, OwnedSemaphorePermit};
 use log::{info, warn, error, debug, trace};
 use tokio::io::AsyncWriteExt;
 use tokio::time::interval;
 use tokio::time::timeout;
 use std::borrow::Cow;
 use std::convert::TryInto;
-use std::net::SocketAddr;
+use std::net::{SocketAddr, IpAddr, TcpListener as StdTcpListener};
+use std::io::ErrorKind;
+use std::collections::{HashMap, VecDeque};
 use std::time::Duration;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::Arc;
 use bytes::Bytes;
 use rand::Rng;
 
+// Sent when the chain switched to another branch
+// so consumers can roll back what they built on the disconnected blocks
+#[derive(Clone, Debug, serde::Serialize)]
+pub struct ReorgEvent {
+    pub common_ancestor: Hash, // top block once the chain was rewinded
+    pub disconnected: Vec<Hash>, // blocks removed, from the highest one
+    pub connected: Vec<Hash> // blocks added on top of the common ancestor, from the lowest one
+}
+
+// Sent each time a peer is dropped for breaking the protocol rules
+#[derive(Debug)]
+pub struct PeerViolation {
+    pub peer_id: Option<u64>, // None if the handshake was not done
+    pub addr: SocketAddr,
+    pub reason: DisconnectReason,
+    pub error: P2pError,
+    pub bytes: Option<String> // hex of the first bytes of the invalid packet, only with --log-packet-bytes
+}
+
+// settings of the P2p server set by the operator
+pub struct P2pConfig {
+    pub tag: Option<String>, // node tag sent on handshake
+    pub max_peers: usize, // max peers accepted by this server
+    pub bind_addresses: Vec<String>, // all ip:port addresses to receive connections, the first one is advertised
+    pub max_outgoing_handshakes: usize, // max outgoing handshakes in progress at the same time
+    pub handshake_timeout: u64, // maximum time in millis allowed for a new connection to send its handshake
+    pub idle_timeout: u64, // time in seconds without any packet received before dropping a peer
+    pub max_peers_per_subnet: usize, // max outgoing peers in the same subnet
+    pub max_requests_per_peer: usize, // max object requests in flight per peer while syncing
+    pub max_download_bytes: usize, // memory allowed for the blocks downloaded and not yet added to the chain
+    pub log_packet_bytes: bool, // log in debug the raw bytes of invalid packets
+    pub packet_sequence: bool, // prefix packets with a sequence number when the peer supports it
+    pub announcement_delay: u64, // time in millis new tx hashes are batched before being announced
+    pub outbound_enabled: bool, // if false, only priority nodes are dialed
+    pub proxy: Option<SocketAddr>, // SOCKS5 proxy used for all outgoing connections
+    pub priority_nodes: Vec<SocketAddr> // trusted nodes dialed first and reconnected quickly
+}
+
 // P2pServer is a fully async TCP server
 // Each connection will block on a data to send or to receive
 // useful for low end hardware
 pub struct P2pServer {
-    peer_id: u64, // unique peer id
+    peer_id: AtomicU64, // unique peer id, can be rotated by the operator
     tag: Option<String>, // node tag sent on handshake
     max_peers: usize, // max peers accepted by this server
-    bind_address: SocketAddr, // ip:port address to receive connections
+    bind_addresses: Vec<SocketAddr>, // all ip:port addresses to receive connections, the first one is advertised
     peer_list: SharedPeerList, // all peers accepted
+    max_outgoing_handshakes: usize, // max outgoing handshakes in progress at the same time
+    outgoing_handshakes: Arc<Semaphore>, // limit the outgoing handshakes to prevent a connection storm
+    handshake_timeout: Duration, // maximum time allowed for a new connection to send its handshake
+    max_peers_per_subnet: usize, // max outgoing peers in the same subnet to prevent eclipse attacks
+    max_requests_per_peer: usize, // max object requests in flight per peer while syncing
+    download_budget: Arc<DownloadBudget>, // memory used by the blocks downloaded and not yet added to the chain
+    idle_timeout: u64, // time in seconds without any packet received before dropping a peer
+    log_packet_bytes: bool, // log in debug the raw bytes of invalid packets
+    packet_sequence: bool, // prefix packets with a sequence number when the peer supports it
+    announcement_delay: u64, // time in millis new tx hashes are batched before being announced, 0 to announce them immediately
+    pending_announcements: Mutex<Vec<(Hash, bool)>>, // tx hashes waiting for the next announcement batch, with their extended format flag
+    outbound_enabled: bool, // if false, only priority nodes are dialed and other peers must connect to us
+    proxy: Option<SocketAddr>, // SOCKS5 proxy used for all outgoing connections
+    priority_nodes: Vec<SocketAddr>, // trusted nodes set by the operator, dialed first and reconnected quickly
+    blocks_sender: broadcast::Sender<Arc<CompleteBlock>>, // notify all subscribers of each new block added to the chain
+    reorgs_sender: broadcast::Sender<Arc<ReorgEvent>>, // notify all subscribers each time the chain switched to another branch
+    violations_sender: broadcast::Sender<Arc<PeerViolation>>, // notify all subscribers each time a peer broke the protocol rules
+    sync_source: Mutex<Option<Arc<Peer>>>, // peer we are currently syncing from
+    last_sync_progress: AtomicU64, // last time (monotonic) a new block was added to the chain
+    chain_tip_disagreement: AtomicBool, // peers at our height disagree with our top hash, only warned once until resolved
     blockchain: Arc<Blockchain>, // reference to the chain to add blocks/txs
 }
 
 impl P2pServer {
-    pub fn new(tag: Option<String>, max_peers: usize, bind_address: String, blockchain: Arc<Blockchain>) -> Result<Arc<Self>, P2pError> {
+    pub fn new(config: P2pConfig, blockchain: Arc<Blockchain>) -> Result<Arc<Self>, P2pError> {
+        let P2pConfig { tag, max_peers, bind_addresses, max_outgoing_handshakes, handshake_timeout, idle_timeout, max_peers_per_subnet, max_requests_per_peer, max_download_bytes, log_packet_bytes, packet_sequence, announcement_delay, outbound_enabled, proxy, priority_nodes } = config;
+        // a bad node tag is reported as an error instead of crashing the node
         if let Some(tag) = &tag {
-            assert!(tag.len() > 0 && tag.len() <= 16);
+            if tag.is_empty() || tag.len() > Handshake::MAX_LEN {
+                return Err(P2pError::InvalidHandshakeField("node tag"))
+            }
         }
 
         // set channel to communicate with listener thread
         let mut rng = rand::thread_rng();
         let peer_id: u64 = rng.gen(); // generate a random peer id for network
-        let addr: SocketAddr = bind_address.parse()?; // parse the bind address
+        // parse all bind addresses, fail fast on a bad one
+        if bind_addresses.is_empty() {
+            return Err(P2pError::NoBindAddress)
+        }
+        let mut addresses: Vec<SocketAddr> = Vec::with_capacity(bind_addresses.len());
+        for address in bind_addresses {
+            let addr: SocketAddr = address.parse()?;
+            if !addresses.contains(&addr) {
+                addresses.push(addr);
+            }
+        }
+        // the port of the first address is advertised in our handshake, peers must be able to dial it
+        if addresses[0].port() == 0 {
+            return Err(P2pError::InvalidHandshakeField("local port"))
+        }
+        // bind all addresses before starting anything so the node can report it and exit cleanly
+        let mut listeners = Vec::with_capacity(addresses.len());
+        for addr in &addresses {
+            listeners.push(Self::bind(addr)?);
+        }
+
+        let (blocks_sender, _) = broadcast::channel(P2P_BLOCKS_CHANNEL_CAPACITY);
+        let (reorgs_sender, _) = broadcast::channel(P2P_REORGS_CHANNEL_CAPACITY);
+        let (violations_sender, _) = broadcast::channel(P2P_VIOLATIONS_CHANNEL_CAPACITY);
         let server = Self {
-            peer_id,
+            peer_id: AtomicU64::new(peer_id),
             tag,
             max_peers,
-            bind_address: addr,
-            peer_list: PeerList::new(max_peers),
+            bind_addresses: addresses,
+            peer_list: PeerList::new(),
+            max_outgoing_handshakes,
+            outgoing_handshakes: Arc::new(Semaphore::new(max_outgoing_handshakes)),
+            handshake_timeout: Duration::from_millis(handshake_timeout),
+            max_peers_per_subnet,
+            // at least one request per peer or the sync can't progress
+            max_requests_per_peer: max_requests_per_peer.max(1),
+            download_budget: DownloadBudget::new(max_download_bytes),
+            idle_timeout,
+            log_packet_bytes,
+            packet_sequence,
+            announcement_delay,
+            pending_announcements: Mutex::new(Vec::new()),
+            outbound_enabled,
+            proxy,
+            priority_nodes,
+            blocks_sender,
+            reorgs_sender,
+            violations_sender,
+            sync_source: Mutex::new(None),
+            last_sync_progress: AtomicU64::new(get_monotonic_time()),
+            chain_tip_disagreement: AtomicBool::new(false),
             blockchain
         };
 
         let arc = Arc::new(server);
         let zelf = Arc::clone(&arc);
         tokio::spawn(async move {
-            if let Err(e) = zelf.start().await {
+            if let Err(e) = zelf.start(listeners).await {
                 error!("Unexpected error on P2p module: {}", e);
             }
         });
         Ok(arc)
     }
 
+    // subscribe to all new blocks validated and added to the chain
+    // a subscriber too slow will receive a Lagged error instead of blocking the node
+    pub fn subscribe_blocks(&self) -> broadcast::Receiver<Arc<CompleteBlock>> {
+        self.blocks_sender.subscribe()
+    }
+
+    // called by the blockchain each time a block is added
+    pub fn notify_new_block(&self, block: &CompleteBlock) {
+        self.last_sync_progress.store(get_monotonic_time(), Ordering::Relaxed);
+        // don't clone the block if nobody is listening
+        if self.blocks_sender.receiver_count() > 0 {
+            if let Err(e) = self.blocks_sender.send(Arc::new(block.clone())) {
+                debug!("Error while notifying new block: {}", e);
+            }
+        }
+    }
+
+    // subscribe to all reorgs, sent once the blocks of the new branch are added
+    pub fn subscribe_reorgs(&self) -> broadcast::Receiver<Arc<ReorgEvent>> {
+        self.reorgs_sender.subscribe()
+    }
+
+    // subscribe to all protocol violations, an operator or a scoring system can act on them
+    pub fn subscribe_violations(&self) -> broadcast::Receiver<Arc<PeerViolation>> {
+        self.violations_sender.subscribe()
+    }
+
+    fn notify_violation(&self, violation: PeerViolation) {
+        if self.violations_sender.receiver_count() > 0 {
+            if let Err(e) = self.violations_sender.send(Arc::new(violation)) {
+                debug!("Error while notifying peer violation: {}", e);
+            }
+        }
+    }
+
+    fn notify_reorg(&self, event: ReorgEvent) {
+        info!("Chain reorganized from {}: {} blocks disconnected, {} connected", event.common_ancestor, event.disconnected.len(), event.connected.len());
+        if self.reorgs_sender.receiver_count() > 0 {
+            if let Err(e) = self.reorgs_sender.send(Arc::new(event)) {
+                debug!("Error while notifying reorg: {}", e);
+            }
+        }
+    }
+
     pub async fn stop(&self) {
         info!("Stopping P2p Server...");
         let mut peers = self.peer_list.lock().await;
-        peers.close_all().await;
+        peers.close_all("node shutting down").await;
         info!("P2p Server is now stopped!");
     }
 
-    // Connect to all seed nodes from constant
-    // buffer parameter is to prevent the re-allocation
-    async fn connect_to_seed_nodes(self: &Arc<Self>) -> Result<(), P2pError> {
-        for peer in SEED_NODES {
-            let addr: SocketAddr = match peer.parse() {
-                Ok(addr) => addr,
-                Err(e) => return Err(P2pError::InvalidPeerAddress(format!("seed node {}: {}", peer, e)))
-            };
-            self.try_to_connect_to_peer(addr, true);
+    // bind the address in non-blocking mode so it can be used by tokio
+    fn bind(addr: &SocketAddr) -> Result<StdTcpListener, P2pError> {
+        let listener = StdTcpListener::bind(addr).map_err(|e| match e.kind() {
+            ErrorKind::AddrInUse => P2pError::PortAlreadyInUse(addr.port()),
+            _ => P2pError::BindError(*addr, e)
+        })?;
+        listener.set_nonblocking(true)?;
+        Ok(listener)
+    }
+
+    // nodes dialed at startup, in order: priority nodes first, then the seed nodes if outbound connections are enabled
+    // a seed node also set as priority node is only dialed once
+    fn get_startup_nodes(&self) -> Vec<SocketAddr> {
+        let mut nodes = self.priority_nodes.clone();
+        if self.outbound_enabled {
+            for peer in self.blockchain.get_network().get_seed_nodes() {
+                match peer.parse() {
+                    Ok(addr) if !nodes.contains(&addr) => nodes.push(addr),
+                    Ok(_) => {},
+                    Err(e) => error!("Invalid seed node address {}: {}", peer, e)
+                };
+            }
         }
-        Ok(())
+        nodes
     }
 
-    // connect to seed nodes, start p2p server
+    // connect to priority nodes, then seed nodes, start p2p server
     // and wait on all new connections
-    async fn start(self: &Arc<Self>) -> Result<(), P2pError> {
-        info!("Connecting to seed nodes...");
-        // allocate this buffer only one time, because we are using the same thread
-        if let Err(e) = self.connect_to_seed_nodes().await {
-            error!("Error while connecting to seed nodes: {}", e);
+    async fn start(self: &Arc<Self>, listeners: Vec<StdTcpListener>) -> Result<(), P2pError> {
+        info!("Connecting to priority and seed nodes...");
+        for addr in self.get_startup_nodes() {
+            self.try_to_connect_to_peer(addr, true);
+        }
+
+        if !self.priority_nodes.is_empty() {
+            tokio::spawn(Arc::clone(self).priority_nodes_loop());
+        }
+
+        if self.outbound_enabled {
+            // dial the addresses shared by our peers when slots are available
+            tokio::spawn(Arc::clone(self).known_addresses_loop());
+        } else {
+            info!("Outbound connections are disabled, waiting for incoming peers");
         }
 
         // start a new task for chain sync
         tokio::spawn(Arc::clone(&self).chain_sync_loop());
+        if self.announcement_delay > 0 {
+            tokio::spawn(Arc::clone(self).announcements_loop());
+        }
+
+        // one task per listener, all connections use the same handling path
+        let mut handles = Vec::with_capacity(listeners.len());
+        for listener in listeners {
+            let listener = TcpListener::from_std(listener)?;
+            info!("P2p Server will listen on: {}", listener.local_addr()?);
+            handles.push(tokio::spawn(Arc::clone(self).listen(listener)));
+        }
 
-        let listener = TcpListener::bind(self.get_bind_address()).await?;
-        info!("P2p Server will listen on: {}", self.get_bind_address());
+        for handle in handles {
+            if let Err(e) = handle.await {
+                error!("Error on p2p listener task: {}", e);
+            }
+        }
+        Ok(())
+    }
+
+    // accept all new connections on this listener
+    async fn listen(self: Arc<Self>, listener: TcpListener) {
         loop {
-            let (mut stream, addr) = listener.accept().await?;
+            let (mut stream, addr) = match listener.accept().await {
+                Ok(value) => value,
+                Err(e) => {
+                    error!("Error while accepting new connection: {}", e);
+                    continue;
+                }
+            };
             if !self.accept_new_connections().await { // if we have already reached the limit, we ignore this new connection
                 debug!("Max peers reached, rejecting connection");
                 if let Err(e) = stream.shutdown().await {
@@ -116,10 +322,11 @@ impl P2pServer {
             }
 
             let connection = Connection::new(stream, addr.clone());
-            let zelf = Arc::clone(self);
+            let zelf = Arc::clone(&self);
             tokio::spawn(async move {
-                if let Err(e) = zelf.handle_new_connection(connection, false, false).await {
+                if let Err(e) = Arc::clone(&zelf).handle_new_connection(connection).await {
                     debug!("Error on {}: {}", addr, e);
+                    zelf.record_disconnect_error(&addr, None, None, e).await;
                 }
             });
         }
@@ -128,13 +335,42 @@ impl P2pServer {
     // Verify handshake send by a new connection
     // based on data size, network ID, peers address validity
     // block height and block top hash of this peer (to know if we are on the same chain)
-    async fn verify_handshake(&self, mut connection: Connection, handshake: Handshake, out: bool, priority: bool) -> Result<(Peer, Vec<SocketAddr>), P2pError> {
-        if *handshake.get_network_id() != NETWORK_ID {
+    // protocol_version is the one selected for this connection
+    async fn verify_handshake(&self, mut connection: Connection, handshake: Handshake, protocol_version: u8, out: bool, priority: bool) -> Result<(Peer, Vec<SocketAddr>), P2pError> {
+        // an all-zero network id is never valid, it's a misconfigured or probing client
+        if handshake.get_network_id().iter().all(|v| *v == 0) {
+            warn!("{} sent us an all-zero network ID", connection.get_address());
             return Err(P2pError::InvalidNetworkID);
         }
 
+        if handshake.get_network_id() != self.blockchain.get_network().get_network_id() {
+            debug!("{} sent us an invalid network ID: {}", connection.get_address(), hex::encode(handshake.get_network_id()));
+            return Err(P2pError::InvalidNetworkID);
+        }
+
+        // a peer at protocol version 0 doesn't send its genesis hash and hash algorithm
+        if handshake.has_extension() {
+            // a network id shared by mistake (two testnets for example) is told apart by the genesis block
+            let genesis_hash = self.blockchain.get_genesis_hash().await.unwrap_or_else(Hash::zero);
+            if *handshake.get_genesis_hash() != genesis_hash {
+                warn!("{} uses our network ID but is on another chain, expected genesis {} got {}", connection.get_address(), genesis_hash, handshake.get_genesis_hash());
+                return Err(P2pError::WrongChain { expected: genesis_hash, got: handshake.get_genesis_hash().clone() });
+            }
+
+            // peers using another hash algorithm can't validate our blocks
+            let hash_algorithm = self.blockchain.get_network().get_hash_algorithm();
+            if handshake.get_hash_algorithm() != hash_algorithm.get_id() {
+                let theirs = match HashAlgorithm::from_id(handshake.get_hash_algorithm()) {
+                    Some(algorithm) => algorithm.to_string(),
+                    None => format!("unknown ({})", handshake.get_hash_algorithm())
+                };
+                debug!("{} uses hash algorithm {}, expected {}", connection.get_address(), theirs, hash_algorithm);
+                return Err(P2pError::InvalidHashAlgorithm(theirs, hash_algorithm));
+            }
+        }
+
         if self.is_connected_to(&handshake.get_peer_id()).await? {
-            connection.close().await?;
+            connection.close_with_reason("peer id already used").await?;
             return Err(P2pError::PeerIdAlreadyUsed(handshake.get_peer_id()));
         }
 
@@ -153,50 +389,89 @@ impl P2pServer {
             }
         }
 
+        // enabled before sending anything else, our handshake is never sequenced
+        if self.packet_sequence && handshake.has_capability(CAPABILITY_PACKET_SEQUENCE) {
+            debug!("Packet sequence enabled with {}", connection.get_address());
+            connection.enable_packet_sequence();
+        }
+
         connection.set_state(State::Success);
-        let (peer, peers) = handshake.create_peer(connection, out, priority, Arc::clone(&self.peer_list));
+        let (peer, mut peers) = handshake.create_peer(connection, protocol_version, out, priority, Arc::clone(&self.peer_list));
+        // a peer may advertise our own address, never try to connect to ourself
+        peers.retain(|addr| {
+            let own = self.is_own_address(addr);
+            if own {
+                debug!("{} advertised our own address {}, ignoring it", peer.get_connection().get_address(), addr);
+            }
+            !own
+        });
         Ok((peer, peers))
     }
 
-    async fn build_handshake(&self) -> Result<Handshake, P2pError> {
-        let mut peers: Vec<SocketAddr> = Vec::new();
-        let peer_list = self.peer_list.lock().await;
-        let mut iter = peer_list.get_peers().iter();
-        while peers.len() < Handshake::MAX_LEN {
-            match iter.next() {
-                Some((_, v)) => {
-                    let mut addr: SocketAddr = v.get_connection().get_address().clone();
-                    if !v.is_out() {
-                        addr.set_port(v.get_local_port());
-                    }
-                    peers.push(addr);
-                },
-                None => break
-            };
-        }
+    // our handshake advertises the current chain state so peers can compare heights with us
+    async fn build_handshake(&self, protocol_version: u8) -> Result<Handshake, P2pError> {
+        let mut peers = self.peer_list.lock().await.snapshot();
+        peers.truncate(Handshake::MAX_LEN);
 
-        let block_height = self.blockchain.get_height();
-        let top_hash = self.blockchain.get_storage().lock().await.get_top_block_hash().clone();
-        Ok(Handshake::new(VERSION.to_owned(), self.get_tag().clone(), NETWORK_ID, self.get_peer_id(), self.bind_address.port(), get_current_time(), block_height, top_hash, peers))
+        let (block_height, top_hash) = self.blockchain.get_chain_state().await;
+        let genesis_hash = self.blockchain.get_genesis_hash().await.unwrap_or_else(Hash::zero);
+        HandshakeBuilder::new(self.get_peer_id(), self.bind_addresses[0].port())
+            .node_tag(self.get_tag().clone())
+            .network_id(*self.blockchain.get_network().get_network_id())
+            .protocol_version(protocol_version)
+            .genesis_hash(genesis_hash)
+            .hash_algorithm(self.blockchain.get_network().get_hash_algorithm())
+            .capabilities(self.get_capabilities())
+            .block_height(block_height)
+            .block_top_hash(top_hash)
+            .peers(peers)
+            .build()
     }
 
-    // this function handle all new connections
-    // A new connection have to send an Handshake
-    // if the handshake is valid, we accept it & register it on server
-    async fn handle_new_connection(self: Arc<Self>,mut connection: Connection, out: bool, priority: bool) -> Result<(), P2pError> {
-        trace!("New connection: {}", connection);
+    // A new connection have to send an Handshake as its first packet
+    async fn read_handshake(&self, connection: &Connection) -> Result<Handshake, P2pError> {
         let mut buf = [0u8; 1024];
-        let handshake: Handshake = match timeout(Duration::from_millis(800), connection.read_packet(&mut buf, 1024)).await?? {
-            Packet::Handshake(h) => h.into_owned(), // only allow handshake packet
-            _ => return Err(P2pError::ExpectedHandshake)
+        // no legitimate peer should be slow to send its handshake
+        // packet id + handshake
+        let max_size = 1 + Handshake::max_serialized_size() as u32;
+        let packet = match timeout(self.handshake_timeout, connection.read_packet(&mut buf, max_size, self.log_packet_bytes)).await {
+            // EOF before the handshake, nothing was registered yet so the connection is only dropped
+            Ok(Err(P2pError::Disconnected)) => {
+                debug!("{} closed the connection before sending its handshake", connection.get_address());
+                return Err(P2pError::Disconnected)
+            },
+            Ok(res) => res?,
+            Err(e) => {
+                warn!("{} didn't send its handshake in time ({}ms), dropping it", connection.get_address(), self.handshake_timeout.as_millis());
+                return Err(P2pError::AsyncTimeOut(e))
+            }
         };
+        match packet {
+            Packet::Handshake(h) => Ok(h.into_owned()), // only allow handshake packet
+            _ => Err(P2pError::ExpectedHandshake)
+        }
+    }
+
+    // this function handle all incoming connections
+    // we reply with the highest protocol version supported by both of us
+    async fn handle_new_connection(self: Arc<Self>, connection: Connection) -> Result<(), P2pError> {
+        trace!("New connection: {}", connection);
+        let handshake = self.read_handshake(&connection).await?;
+        let protocol_version = handshake.get_protocol_version().min(PROTOCOL_VERSION);
+        self.handle_handshake(connection, handshake, protocol_version, false, false, None).await
+    }
+
+    // if the handshake is valid, we accept it & register it on server
+    // the permit (if any) is released once the handshake is done
+    async fn handle_handshake(self: Arc<Self>, mut connection: Connection, handshake: Handshake, protocol_version: u8, out: bool, priority: bool, permit: Option<OwnedSemaphorePermit>) -> Result<(), P2pError> {
         trace!("received handshake packet!");
         connection.set_state(State::Handshake);
-        let (peer, peers) = self.verify_handshake(connection, handshake, out, priority).await?;
+        let (peer, peers) = self.verify_handshake(connection, handshake, protocol_version, out, priority).await?;
+        drop(permit);
         // if it's a outgoing connection, don't send the handshake back
         // because we have already sent it
         if !out {
-            self.send_handshake(peer.get_connection()).await?;
+            self.send_handshake(peer.get_connection(), protocol_version).await?;
         }
 
         // if we reach here, handshake is all good, we can start listening this new peer
@@ -206,18 +481,18 @@ impl P2pServer {
             peer_list.add_peer(peer_id, peer)
         };
 
-        // try to extend our peer list
-        for peer_addr in peers { // should we limit to X peers only ?
-            if !self.accept_new_connections().await {
-                break
-            }
+        // share more of our peers than the handshake allows
+        if let Err(e) = self.send_peer_exchange(&peer).await {
+            debug!("Error while sending peer exchange to {}: {}", peer.get_connection().get_address(), e);
+        }
 
-            if !self.is_connected_to_addr(&peer_addr).await? {
-                debug!("Trying to extend peer list with {}", peer_addr);
-                self.try_to_connect_to_peer(peer_addr, false);
-            }
+        // try to extend our peer list
+        // the peer is registered now, so an error here must not skip handle_connection which unregisters it
+        if let Err(e) = self.extend_peer_list(peers).await {
+            debug!("Error while extending peer list from {}: {}", peer.get_connection().get_address(), e);
         }
 
+        let mut buf = [0u8; 1024];
         self.handle_connection(&mut buf, peer).await
     }
 
@@ -226,63 +501,212 @@ impl P2pServer {
     pub fn try_to_connect_to_peer(self: &Arc<Self>, addr: SocketAddr, priority: bool) {
         let zelf = Arc::clone(self);
         tokio::spawn(async move {
-            if let Err(e) = zelf.connect_to_peer(addr, priority).await {
+            if let Err(e) = Arc::clone(&zelf).connect_to_peer(addr, priority).await {
                 debug!("Error occured on outgoing peer {}: {}", addr, e);
+                zelf.record_disconnect_error(&addr, None, None, e).await;
             }
         });
     }
 
+    // keep the reason of the disconnect if the error is caused by the peer
+    // a protocol violation is also logged and notified with the bytes of the invalid packet, if any
+    async fn record_disconnect_error(&self, addr: &SocketAddr, peer_id: Option<u64>, bytes: Option<Vec<u8>>, error: P2pError) {
+        if let Some(reason) = DisconnectReason::from_error(&error) {
+            self.peer_list.lock().await.record_disconnect(addr.ip(), reason);
+            if reason.is_violation() {
+                warn!("Protocol violation ({:?}) by {}: {}", reason, addr, error);
+                self.notify_violation(PeerViolation {
+                    peer_id,
+                    addr: *addr,
+                    reason,
+                    error,
+                    bytes: bytes.map(hex::encode)
+                });
+            }
+        }
+    }
+
+    // recent disconnect reasons of this address, oldest first
+    pub async fn get_disconnect_reasons(&self, ip: &IpAddr) -> Vec<(DisconnectReason, u64)> {
+        self.peer_list.lock().await.get_disconnect_reasons(ip)
+    }
+
     async fn connect_to_peer(self: Arc<Self>, addr: SocketAddr, priority: bool) -> Result<(), P2pError> {
+        let addr = canonical_addr(&addr);
         debug!("Trying to connect to {}", addr);
         if self.is_connected_to_addr(&addr).await? {
             return Err(P2pError::PeerAlreadyConnected(format!("{}", addr)));
         }
-        let stream = timeout(Duration::from_millis(800), TcpStream::connect(&addr)).await??; // allow maximum 800ms of latency
-        let connection = Connection::new(stream, addr);
-        self.send_handshake(&connection).await?;
-        self.handle_new_connection(connection, true, priority).await
+
+        // priority nodes are trusted, don't apply the subnet limit or the backoff on them
+        if !priority {
+            let peer_list = self.peer_list.lock().await;
+            if peer_list.is_in_backoff(&addr.ip()) {
+                return Err(P2pError::PeerInBackoff(format!("{}", addr)));
+            }
+
+            if peer_list.count_outgoing_peers_in_subnet(&addr.ip()) >= self.max_peers_per_subnet {
+                return Err(P2pError::SubnetLimitReached(format!("{}", addr)));
+            }
+        }
+        // wait for a free slot before dialing, the slot is released when the handshake is done or has failed
+        let permit = Arc::clone(&self.outgoing_handshakes).acquire_owned().await.map_err(|_| P2pError::Disconnected)?;
+        let connection = self.dial_with_handshake(&addr, PROTOCOL_VERSION).await?;
+        // a node at protocol version 0 closes the connection when it receives our handshake extension
+        // so it is dialed again with the original handshake. An address we can't connect to is only dialed once
+        let (connection, handshake, sent_version) = match self.read_handshake(&connection).await {
+            Ok(handshake) => (connection, handshake, PROTOCOL_VERSION),
+            Err(P2pError::Disconnected) | Err(P2pError::ErrorStd(_)) => {
+                debug!("{} closed the connection after our handshake, trying again with the protocol version 0", addr);
+                let connection = self.dial_with_handshake(&addr, 0).await?;
+                let handshake = self.read_handshake(&connection).await?;
+                (connection, handshake, 0)
+            },
+            Err(e) => return Err(e)
+        };
+        // the protocol version selected by the peer can't be above the one we sent
+        let protocol_version = handshake.get_protocol_version().min(sent_version);
+        self.handle_handshake(connection, handshake, protocol_version, true, priority, Some(permit)).await
+    }
+
+    // open a connection and send our handshake at this protocol version
+    async fn dial_with_handshake(&self, addr: &SocketAddr, protocol_version: u8) -> Result<Connection, P2pError> {
+        let stream = match &self.proxy {
+            Some(proxy) => timeout(Duration::from_millis(P2P_PROXY_CONNECT_TIMEOUT), socks::connect(proxy, addr)).await??,
+            None => timeout(Duration::from_millis(800), TcpStream::connect(addr)).await?? // allow maximum 800ms of latency
+        };
+        let connection = Connection::new(stream, *addr);
+        self.send_handshake(&connection, protocol_version).await?;
+        Ok(connection)
     }
 
-    async fn send_handshake(&self, connection: &Connection) -> Result<(), P2pError> {
-        let handshake: Handshake = self.build_handshake().await?;
+    async fn send_handshake(&self, connection: &Connection, protocol_version: u8) -> Result<(), P2pError> {
+        let handshake: Handshake = self.build_handshake(protocol_version).await?;
         let mut writer = Writer::new();
         Packet::Handshake(Cow::Owned(handshake)).write(&mut writer);
         connection.send_bytes(&writer.bytes()).await
     }
 
     async fn build_ping_packet(&self, peer: Option<&Arc<Peer>>) -> Ping<'_> {
-        let block_top_hash = self.blockchain.get_top_block_hash().await;
-        let block_height = self.blockchain.get_height();
+        let (block_height, block_top_hash) = self.blockchain.get_chain_state().await;
+        let new_peers = match peer {
+            Some(peer) => self.collect_peers_to_share(peer, P2P_PING_PEER_LIST_LIMIT).await,
+            None => Vec::new()
+        };
+        Ping::new(Cow::Owned(block_top_hash), block_height, new_peers)
+    }
+
+    // addresses of our peers that we haven't sent yet to this peer (up to `limit`)
+    // nothing is returned if we already sent our peerlist to this peer recently
+    async fn collect_peers_to_share(&self, peer: &Arc<Peer>, limit: usize) -> Vec<SocketAddr> {
         let mut new_peers = Vec::new();
-        if let Some(peer) = peer {
-            let current_time = get_current_time();
-            if current_time > peer.get_last_peer_list_update() + P2P_PING_PEER_LIST_DELAY {
-                peer.set_last_peer_list_update(current_time);
-                // all the peers of current peer
-                let mut peer_peers = peer.get_peers().lock().await;
-                // our peerlist
-                let peer_list = self.peer_list.lock().await;
-                for p in peer_list.get_peers().values() {
-                    if *p.get_connection().get_address() == *peer.get_connection().get_address() {
-                        continue;
-                    }
-                    let mut addr = p.get_connection().get_address().clone();
-                    if !p.is_out() { // if we are connected to it (outgoing connection), set the local port instead
-                        addr.set_port(p.get_local_port());
+        if has_elapsed(peer.get_last_peer_list_update(), P2P_PING_PEER_LIST_DELAY + 1) {
+            peer.set_last_peer_list_update(get_monotonic_time());
+            // our peerlist, the lock is only held to copy it
+            let snapshot = self.peer_list.lock().await.snapshot();
+            let peer_addr = peer.get_advertised_address();
+            // all the peers of current peer
+            let mut peer_peers = peer.get_peers().lock().await;
+            for addr in snapshot {
+                if addr == peer_addr {
+                    continue;
+                }
+
+                // if we haven't send him this peer addr, insert it
+                if !peer_peers.contains(&addr) {
+                    peer_peers.insert(addr.clone());
+                    new_peers.push(addr.clone());
+                    if new_peers.len() >= limit {
+                        break;
                     }
+                }
+            }
+        }
+        new_peers
+    }
 
-                    // if we haven't send him this peer addr, insert it
-                    if !peer_peers.contains(&addr) {
-                        peer_peers.insert(addr.clone());
-                        new_peers.push(addr.clone());
-                        if new_peers.len() >= P2P_PING_PEER_LIST_LIMIT {
-                            break;
-                        }
+    // send a peer exchange packet to this peer if we have new peers to share with it
+    // an older peer without the packet only gets our peers through the handshake and pings
+    async fn send_peer_exchange(&self, peer: &Arc<Peer>) -> Result<(), P2pError> {
+        if !peer.has_capability(CAPABILITY_PEER_EXCHANGE) {
+            return Ok(())
+        }
+
+        let peers = self.collect_peers_to_share(peer, P2P_PEER_EXCHANGE_LIMIT).await;
+        if peers.is_empty() {
+            return Ok(())
+        }
+
+        trace!("Sending {} peers to {}", peers.len(), peer.get_connection().get_address());
+        peer.send_packet(Packet::Peers(Cow::Owned(PeerExchange::new(peers)))).await
+    }
+
+    // try to connect to the addresses shared by a peer, up to the slots available
+    // the others are remembered to be dialed once slots are available
+    // our own address and already connected peers are ignored, peers in backoff are only remembered
+    async fn extend_peer_list(self: &Arc<Self>, peers: Vec<SocketAddr>) -> Result<(), P2pError> {
+        // in listen-only mode, we never dial the addresses shared by our peers
+        if !self.outbound_enabled {
+            return Ok(())
+        }
+
+        let mut slots = self.get_slots_available().await;
+        for peer_addr in peers {
+            if self.is_connected_to_addr(&peer_addr).await? {
+                continue
+            }
+
+            let mut peer_list = self.peer_list.lock().await;
+            if slots == 0 || peer_list.is_in_backoff(&peer_addr.ip()) {
+                trace!("Remembering shared peer {} for later", peer_addr);
+                peer_list.remember_address(peer_addr);
+                continue
+            }
+            drop(peer_list);
+
+            debug!("Trying to extend peer list with {}", peer_addr);
+            self.try_to_connect_to_peer(peer_addr, false);
+            slots -= 1;
+        }
+        Ok(())
+    }
+
+    // dial the addresses remembered when slots are available
+    async fn known_addresses_loop(self: Arc<Self>) {
+        let mut interval = interval(Duration::from_secs(P2P_KNOWN_ADDRESSES_DIAL_DELAY));
+        loop {
+            interval.tick().await;
+            let slots = self.get_slots_available().await;
+            let mut addresses = Vec::with_capacity(slots);
+            {
+                let mut peer_list = self.peer_list.lock().await;
+                // each address is tried once, in backoff ones are kept for later
+                let mut left = peer_list.get_known_addresses().len();
+                while addresses.len() < slots && left > 0 {
+                    left -= 1;
+                    let addr = match peer_list.take_known_address() {
+                        Some(addr) => addr,
+                        None => break
+                    };
+                    if peer_list.is_in_backoff(&addr.ip()) {
+                        peer_list.remember_address(addr);
+                    } else {
+                        addresses.push(addr);
                     }
                 }
             }
+
+            for addr in addresses {
+                match self.is_connected_to_addr(&addr).await {
+                    Ok(false) => {
+                        debug!("Trying to connect to known address {}", addr);
+                        self.try_to_connect_to_peer(addr, false);
+                    },
+                    Ok(true) => {},
+                    Err(e) => debug!("Error while checking known address {}: {}", addr, e)
+                };
+            }
         }
-        Ping::new(Cow::Owned(block_top_hash), block_height, new_peers)
     }
 
     // build a ping packet with a specific peerlist for the peer
@@ -298,6 +722,17 @@ impl P2pServer {
                 break;
             }
 
+            // peer is still connected but didn't send us anything for too long
+            let idle_time = get_monotonic_time().saturating_sub(peer.get_last_activity());
+            if idle_time > self.idle_timeout {
+                debug!("Peer {} is idle since {}s, disconnecting", peer.get_connection().get_address(), idle_time);
+                self.peer_list.lock().await.record_disconnect(peer.get_connection().get_address().ip(), DisconnectReason::Idle);
+                if let Err(e) = peer.close_with_reason("idle").await {
+                    debug!("Error while closing idle peer {}: {}", peer.get_connection().get_address(), e);
+                }
+                break;
+            }
+
             let packet = Packet::Ping(Cow::Owned(self.build_ping_packet_for_peer(&peer).await));
             trace!("Sending ping packet to peer: {}", peer.get_connection().get_address());
             if let Err(e) = peer.send_packet(packet).await {
@@ -307,10 +742,11 @@ impl P2pServer {
         }
     }
 
-    async fn select_random_best_peer(&self) -> Option<Arc<Peer>> {
+    // random peer ahead of us, the excluded peer is never selected
+    async fn select_random_best_peer(&self, exclude: Option<u64>) -> Option<Arc<Peer>> {
         let peer_list = self.peer_list.lock().await;
         let our_height = self.blockchain.get_height();
-        let peers: Vec<&Arc<Peer>> = peer_list.get_peers().values().filter(|p| p.get_block_height() > our_height).collect();
+        let peers: Vec<&Arc<Peer>> = peer_list.get_peers().values().filter(|p| p.is_ahead_of(our_height) && p.can_serve_chain() && Some(p.get_id()) != exclude).collect();
         let count = peers.len();
         trace!("peers available for random selection: {}", count);
         if count == 0 {
@@ -321,11 +757,149 @@ impl P2pServer {
         Some(Arc::clone(peers.get(selected)?))
     }
 
+    // highest block height known from connected peers (or our own height if greater)
+    // a height too far above the median of all peers is considered as a lie and ignored
+    pub async fn best_known_height(&self) -> u64 {
+        let our_height = self.blockchain.get_height();
+        let mut heights: Vec<u64> = {
+            let peer_list = self.peer_list.lock().await;
+            peer_list.get_peers().values().map(|p| p.get_block_height()).collect()
+        };
+        if heights.is_empty() {
+            return our_height
+        }
+
+        heights.sort_unstable();
+        let median = heights[heights.len() / 2];
+        let best = heights.into_iter()
+            .filter(|height| *height <= median.saturating_add(P2P_HEIGHT_OUTLIER_THRESHOLD))
+            .max()
+            .unwrap_or(median);
+        best.max(our_height)
+    }
+
+    // initial block download is done once we reached the best known height
+    pub async fn is_chain_synced(&self) -> bool {
+        self.blockchain.get_height() >= self.best_known_height().await
+    }
+
+    // top hashes reported by the peers at this height, with the number of peers reporting each of them
+    pub async fn chain_tip_consensus(&self, height: u64) -> HashMap<Hash, usize> {
+        let peers: Vec<Arc<Peer>> = {
+            let peer_list = self.peer_list.lock().await;
+            peer_list.get_peers().values().filter(|p| p.get_block_height() == height).cloned().collect()
+        };
+
+        let mut tips = HashMap::new();
+        for peer in peers {
+            let hash = peer.get_top_block_hash().lock().await.clone();
+            *tips.entry(hash).or_insert(0) += 1;
+        }
+        tips
+    }
+
+    // returns the top hash shared by most peers at this height and the number of peers agreeing on it
+    // a peer alone on its top hash is probably lying or on another chain
+    pub async fn count_peers_agreeing_at(&self, height: u64) -> Option<(Hash, usize)> {
+        let votes = self.chain_tip_consensus(height).await;
+        // sort by hash on equality so the result doesn't depend on the map order
+        votes.into_iter().max_by(|(a_hash, a), (b_hash, b)| a.cmp(b).then_with(|| b_hash.as_bytes().cmp(a_hash.as_bytes())))
+    }
+
+    // reconnect to all disconnected priority nodes
+    // they are trusted by the operator so they are retried faster than any other peer
+    async fn priority_nodes_loop(self: Arc<Self>) {
+        let mut interval = interval(Duration::from_secs(P2P_PRIORITY_NODES_RETRY_DELAY));
+        interval.tick().await; // first tick is immediate, nodes were just dialed
+        loop {
+            interval.tick().await;
+            let (_, pending) = self.get_priority_nodes_status().await;
+            for addr in pending {
+                trace!("Trying to reconnect to priority node {}", addr);
+                self.try_to_connect_to_peer(addr, true);
+            }
+        }
+    }
+
+    // keep the current sync source until it is behind us, disconnected or stalled
+    // a peer which doesn't deliver any new block for too long is penalized and replaced
+    async fn select_sync_source(&self) -> Option<Arc<Peer>> {
+        let mut sync_source = self.sync_source.lock().await;
+        let mut exclude = None;
+        if let Some(peer) = sync_source.as_ref() {
+            if !peer.get_connection().is_closed() && peer.is_ahead_of(self.blockchain.get_height()) {
+                let elapsed = get_monotonic_time().saturating_sub(self.get_last_sync_progress());
+                if elapsed <= CHAIN_SYNC_STALL_TIMEOUT {
+                    return Some(Arc::clone(peer))
+                }
+
+                warn!("No new block from sync peer {} since {}s, switching to another peer", peer.get_connection().get_address(), elapsed);
+                peer.increment_fail_count();
+                exclude = Some(peer.get_id());
+            }
+        }
+
+        *sync_source = self.select_random_best_peer(exclude).await;
+        if let Some(peer) = sync_source.as_ref() {
+            debug!("New sync peer selected: {}", peer.get_connection().get_address());
+            // give the new peer a full delay to deliver blocks
+            self.last_sync_progress.store(get_monotonic_time(), Ordering::Relaxed);
+        }
+        sync_source.clone()
+    }
+
+    // address of the peer we are currently syncing from
+    pub async fn get_sync_source(&self) -> Option<SocketAddr> {
+        self.sync_source.lock().await.as_ref().map(|peer| *peer.get_connection().get_address())
+    }
+
+    // last time in seconds a new block was added to the chain
+    pub fn get_last_sync_progress(&self) -> u64 {
+        self.last_sync_progress.load(Ordering::Relaxed)
+    }
+
+    // flush the tx hashes batched during the announcement delay
+    async fn announcements_loop(self: Arc<Self>) {
+        let mut interval = interval(Duration::from_millis(self.announcement_delay));
+        loop {
+            interval.tick().await;
+            self.flush_announcements().await;
+        }
+    }
+
+    // announce all pending tx hashes to our peers, in as few inventory packets as possible
+    async fn flush_announcements(&self) {
+        let txs: Vec<(Hash, bool)> = {
+            let mut pending = self.pending_announcements.lock().await;
+            if pending.is_empty() {
+                return;
+            }
+            pending.drain(..).collect()
+        };
+
+        trace!("Announcing {} transactions", txs.len());
+        let ping = self.build_ping_packet(None).await;
+        let peer_list = self.peer_list.lock().await;
+        // the inventory capability is only sent from the protocol version 1, so these peers know all tx formats
+        for chunk in txs.chunks(P2P_INVENTORY_MAX_SIZE) {
+            let inventory = Inventory::new(chunk.iter().map(|(hash, _)| hash.clone()).collect());
+            let packet = Packet::Inventory(PacketWrapper::new(Cow::Owned(inventory), Cow::Borrowed(&ping)));
+            peer_list.broadcast_filter(|(_, peer)| peer.has_capability(CAPABILITY_INVENTORY), Bytes::from(packet.to_bytes())).await;
+        }
+
+        // peers without the inventory packet get one propagation packet per tx
+        for (tx, extended) in &txs {
+            let packet = Packet::TransactionPropagation(PacketWrapper::new(Cow::Borrowed(tx), Cow::Borrowed(&ping)));
+            peer_list.broadcast_filter(|(_, peer)| !peer.has_capability(CAPABILITY_INVENTORY) && (!extended || peer.supports_extended_transactions()), Bytes::from(packet.to_bytes())).await;
+        }
+    }
+
     async fn chain_sync_loop(self: Arc<Self>) {
         let mut interval = interval(Duration::from_secs(CHAIN_SYNC_DELAY));
         loop {
             interval.tick().await;
-            if let Some(peer) = self.select_random_best_peer().await {
+            self.check_chain_tip_consensus().await;
+            if let Some(peer) = self.select_sync_source().await {
                 trace!("Peer selected for chain sync: {}", peer.get_connection().get_address());
                 if let Err(e) = self.request_sync_chain_for(&peer).await {
                     debug!("Error occured on chain sync: {}", e);
@@ -334,6 +908,26 @@ impl P2pServer {
         }
     }
 
+    // too many peers at our height reporting another top hash is an early sign that we are on a minority fork
+    async fn check_chain_tip_consensus(&self) {
+        let tips = self.chain_tip_consensus(self.blockchain.get_height()).await;
+        let total: usize = tips.values().sum();
+        if total == 0 {
+            return
+        }
+
+        let (_, top_hash) = self.blockchain.get_chain_state().await;
+        let disagreeing = total - tips.get(&top_hash).copied().unwrap_or(0);
+        let disagreement = disagreeing * 100 / total >= CHAIN_TIP_DISAGREEMENT_PERCENT;
+        if disagreement && !self.chain_tip_disagreement.swap(true, Ordering::Relaxed) {
+            warn!("{}/{} peers at our height report another top hash than ours ({}), we may be on a fork: {:?}", disagreeing, total, top_hash, tips.iter().map(|(hash, count)| format!("{}: {}", hash, count)).collect::<Vec<String>>());
+        } else if !disagreement && self.chain_tip_disagreement.swap(false, Ordering::Relaxed) {
+            info!("Peers at our height agree again with our top hash {}", top_hash);
+        }
+    }
+
+    // Each peer is handled in its own task, there is no shared listener loop:
+    // a slow or never-readable peer only blocks its own task and never stalls the other peers
     async fn handle_connection(self: Arc<Self>, buf: &mut [u8], peer: Arc<Peer>) -> Result<(), P2pError> {
         tokio::spawn(Arc::clone(&self).loop_ping(Arc::clone(&peer)));
         let mut rx = peer.get_connection().get_rx().lock().await;
@@ -342,7 +936,10 @@ impl P2pServer {
                 res = self.listen_connection(buf, &peer) => {
                     if let Err(e) = res { // close on any error
                         debug!("Error while reading packet from peer {}: {}", peer.get_connection().get_address(), e);
-                        peer.close().await?;
+                        let reason = e.to_string();
+                        let bytes = peer.get_connection().take_invalid_packet();
+                        self.record_disconnect_error(peer.get_connection().get_address(), Some(peer.get_id()), bytes, e).await;
+                        peer.close_with_reason(&reason).await?;
                         break;
                     }
                 }
@@ -351,7 +948,7 @@ impl P2pServer {
                         ConnectionMessage::Packet(bytes) => {
                             trace!("Data to send to {} received!", peer.get_connection().get_address());
                             debug!("Sending packet with ID {}, size sent: {}, real size: {}", bytes[5], u32::from_be_bytes(bytes[0..4].try_into()?), bytes.len() - 4);
-                            peer.get_connection().send_bytes(&bytes).await?;
+                            peer.get_connection().send_packet_bytes(&bytes).await?;
                             trace!("data sucessfully sent!");
                         }
                         ConnectionMessage::Exit => {
@@ -364,17 +961,61 @@ impl P2pServer {
 
             if peer.get_fail_count() >= 20 {
                 error!("High fail count detected for {}!", peer);
-                if let Err(e) = peer.close().await {
+                self.peer_list.lock().await.record_disconnect(peer.get_connection().get_address().ip(), DisconnectReason::TooManyFailures);
+                if let Err(e) = peer.close_with_reason("too many failures").await {
                     error!("Error while trying to close connection {} due to high fail count: {}", peer.get_connection().get_address(), e);
                 }
                 break;
             }
         }
         rx.close(); // clean shutdown
+        // wake up all tasks still waiting on an object from this peer
+        peer.clear_object_requests().await;
         Ok(())
     }
 
+    // request a transaction we don't have from the peer which announced it and add it to mempool
+    fn request_transaction(self: &Arc<Self>, peer: &Arc<Peer>, hash: Hash) {
+        let zelf = Arc::clone(self);
+        let peer = Arc::clone(peer);
+        tokio::spawn(async move {
+            let ping = zelf.build_ping_packet(None).await;
+            let response = match peer.request_blocking_object(ObjectRequest::Transaction(hash), &ping).await {
+                Ok(response) => response,
+                Err(err) => {
+                    error!("Error while requesting transaction: {}", err);
+                    peer.increment_fail_count();
+                    return;
+                }
+            };
+            if let OwnedObjectResponse::Transaction(tx) = response {
+                if let Err(e) = zelf.blockchain.add_tx_to_mempool(tx, true).await {
+                    match e {
+                        // another peer was faster than us
+                        BlockchainError::TxAlreadyInMempool(_) => {}, // TODO: synced request list
+                        // valid for the network, only below our policy (maybe an old client), so the peer isn't penalized
+                        BlockchainError::FeeBelowRelayMinimum(_, _) => {
+                            debug!("Dropping TX from {}: {}", peer, e);
+                        },
+                        e => {
+                            error!("Error while adding TX to mempool: {}", e);
+                            peer.increment_fail_count();
+                        }
+                    };
+                }
+            } else {
+                peer.increment_fail_count();
+                error!("Expected to receive a Transaction object from peer: {}", peer);
+            }
+        });
+    }
+
     async fn handle_incoming_packet(self: &Arc<Self>, peer: &Arc<Peer>, packet: Packet<'_>) -> Result<(), P2pError> {
+        let kind = packet.get_kind();
+        if !kind.is_allowed(peer.get_capabilities()) {
+            return Err(P2pError::PacketNotAllowed(kind))
+        }
+
         match packet {
             Packet::Handshake(_) => {
                 return Err(P2pError::InvalidPacket)
@@ -383,36 +1024,18 @@ impl P2pServer {
                 let (hash, ping) = packet_wrapper.consume();
                 let hash = hash.into_owned();
                 ping.into_owned().update_peer(peer).await;
-                let mempool = self.blockchain.get_mempool().lock().await;
-                if !mempool.contains_tx(&hash) {
-                    let zelf = Arc::clone(self);
-                    let peer = Arc::clone(peer);
-                    tokio::spawn(async move {
-                        let ping = zelf.build_ping_packet(None).await;
-                        let response = match peer.request_blocking_object(ObjectRequest::Transaction(hash), &ping).await {
-                            Ok(response) => response,
-                            Err(err) => {
-                                error!("Error while requesting transaction: {}", err);
-                                peer.increment_fail_count();
-                                return;
-                            }
-                        };
-                        if let OwnedObjectResponse::Transaction(tx) = response {
-                            if let Err(e) = zelf.blockchain.add_tx_to_mempool(tx, true).await {
-                                match e {
-                                    // another peer was faster than us
-                                    BlockchainError::TxAlreadyInMempool(_) => {}, // TODO: synced request list
-                                    e => {
-                                        error!("Error while adding TX to mempool: {}", e);
-                                        peer.increment_fail_count();
-                                    }
-                                };
-                            }
-                        } else {
-                            peer.increment_fail_count();
-                            error!("Expected to receive a Transaction object from peer: {}", peer);
-                        }
-                    });
+                if !self.blockchain.has_object(&ObjectRequest::Transaction(hash.clone())).await {
+                    self.request_transaction(peer, hash);
+                }
+            },
+            Packet::Inventory(packet_wrapper) => {
+                trace!("Received an inventory packet from {}", peer.get_connection().get_address());
+                let (inventory, ping) = packet_wrapper.consume();
+                ping.into_owned().update_peer(peer).await;
+                for hash in inventory.into_owned().into_txs() {
+                    if !self.blockchain.has_object(&ObjectRequest::Transaction(hash.clone())).await {
+                        self.request_transaction(peer, hash);
+                    }
                 }
             },
             Packet::BlockPropagation(packet_wrapper) => {
@@ -434,10 +1057,9 @@ impl P2pServer {
                 ping.into_owned().update_peer(peer).await;
                 let request = request.into_owned();
                 let last_request = peer.get_last_chain_sync();
-                let time = get_current_time();
-                peer.set_last_chain_sync(time);
+                peer.set_last_chain_sync(get_monotonic_time());
                 // Node is trying to ask too fast our chain
-                if  last_request + CHAIN_SYNC_DELAY > time {
+                if !has_elapsed(last_request, CHAIN_SYNC_DELAY) {
                     debug!("Peer requested sync chain too fast!");
                     return Err(P2pError::RequestSyncChainTooFast)
                 }
@@ -475,22 +1097,26 @@ impl P2pServer {
                     debug!("Peer found a common point for sync, received {} blocks", response.size());
                     let pop_count = {
                         let storage = self.blockchain.get_storage().lock().await;
-                        let common_block = match storage.get_block_by_hash(common_point.get_hash()) {
-                            Ok(block) => block,
-                            Err(e) => {
-                                warn!("Peer {} sent us an invalid common point: {}", peer.get_connection().get_address(), e);
+                        // the snapshot block of a bootstrapped chain is a valid common point
+                        let common_height = match storage.get_block_height_by_hash(common_point.get_hash()) {
+                            Some(height) => height,
+                            None => {
+                                warn!("Peer {} sent us an invalid common point: block {} not found", peer.get_connection().get_address(), common_point.get_hash());
                                 return Err(P2pError::InvalidPacket)
                             }
                         };
-                        if common_block.get_height() != common_point.get_height() {
-                            error!("Peer {} sent us a valid block hash, but at invalid height (expected: {}, got: {})!", peer.get_connection().get_address(), common_block.get_height(), common_point.get_height());
+                        if common_height != common_point.get_height() {
+                            error!("Peer {} sent us a valid block hash, but at invalid height (expected: {}, got: {})!", peer.get_connection().get_address(), common_height, common_point.get_height());
                             return Err(P2pError::InvalidPacket)
                         }
-                        self.blockchain.get_height() - common_block.get_height()
+                        self.blockchain.get_height() - common_height
                     };
 
-                    if pop_count > MAX_BLOCK_REWIND {
-                        warn!("We may have deviated too much! Pop count: {}", pop_count);
+                    // a reorg this deep is more likely an attack than a fork, the switch is refused
+                    let max_reorg_depth = self.blockchain.get_max_reorg_depth();
+                    if pop_count > max_reorg_depth {
+                        error!("Refusing a reorg of {} blocks from peer {}, maximum depth is {} blocks. The node may be under attack!", pop_count, peer.get_connection().get_address(), max_reorg_depth);
+                        return Err(P2pError::ReorgTooDeep(pop_count, max_reorg_depth))
                     }
 
                     let peer = Arc::clone(peer);
@@ -514,59 +1140,86 @@ impl P2pServer {
             },
             Packet::Ping(ping) => {
                 trace!("Received a ping packet from {}", peer.get_connection().get_address());
-                let current_time = get_current_time();
+                let current_time = get_monotonic_time();
                 // verify the respect of the coutdown to prevent massive packet incoming
-                if current_time - peer.get_last_ping() < P2P_PING_DELAY {
+                if !has_elapsed(peer.get_last_ping(), P2P_PING_DELAY) {
                     return Err(P2pError::PeerInvalidPingCoutdown)
                 }
                 peer.set_last_ping(current_time);
 
                 // we verify the respect of the countdown of peer list updates to prevent any spam
                 if ping.get_peers().len() > 0 {
-                    if current_time - peer.get_last_peer_list() < P2P_PING_PEER_LIST_DELAY {
+                    if !has_elapsed(peer.get_last_peer_list(), P2P_PING_PEER_LIST_DELAY) {
                         return Err(P2pError::PeerInvalidPeerListCountdown)
                     }
                     peer.set_last_peer_list(current_time);
                 }
 
-                for peer in ping.get_peers() {
-                    if !self.is_connected_to_addr(&peer).await? {
-                        let peer = peer.clone();
-                        self.try_to_connect_to_peer(peer, false);
+                self.extend_peer_list(ping.get_peers().clone()).await?;
+                ping.into_owned().update_peer(peer).await;
+            },
+            Packet::Peers(peers) => {
+                trace!("Received a peer exchange packet from {}", peer.get_connection().get_address());
+                // peer exchange share the same countdown as the peer list in ping packets
+                if !has_elapsed(peer.get_last_peer_list(), P2P_PING_PEER_LIST_DELAY) {
+                    return Err(P2pError::PeerInvalidPeerListCountdown)
+                }
+                peer.set_last_peer_list(get_monotonic_time());
+
+                let peers = peers.into_owned().into_peers();
+                {
+                    let mut peer_peers = peer.get_peers().lock().await;
+                    for addr in &peers {
+                        peer_peers.insert(*addr);
                     }
                 }
-                ping.into_owned().update_peer(peer).await;
+                self.extend_peer_list(peers).await?;
             },
             Packet::ObjectRequest(packet_wrapper) => {
                 trace!("Received a object request from {}", peer.get_connection().get_address());
                 let (request, ping) = packet_wrapper.consume();
                 ping.into_owned().update_peer(peer).await;
                 let request = request.into_owned();
-                match &request {
+                // storage & mempool locks are released before building a response for a missing object
+                let found = match &request {
                     ObjectRequest::Block(hash) => {
                         let storage = self.blockchain.get_storage().lock().await;
                         match storage.get_block_by_hash(hash) {
+                            Ok(block) if block.has_extended_transactions() && !peer.supports_extended_transactions() => {
+                                debug!("Peer {} asked block '{}' which can't be sent at its protocol version", peer.get_connection().get_address(), hash);
+                                false
+                            },
                             Ok(block) => {
                                 peer.send_packet(Packet::ObjectResponse(ObjectResponse::Block(Cow::Borrowed(block)))).await?;
+                                true
                             },
                             Err(e) => {
                                 debug!("Peer {} asked block '{}' but got on error while retrieving it: {}", peer.get_connection().get_address(), hash, e);
-                                peer.send_packet(Packet::ObjectResponse(ObjectResponse::NotFound(request))).await?;
+                                false
                             }
                         }
                     },
                     ObjectRequest::Transaction(hash) => {
                         let mempool = self.blockchain.get_mempool().lock().await;
                         match mempool.view_tx(hash) {
+                            Ok(tx) if tx.is_extended() && !peer.supports_extended_transactions() => {
+                                debug!("Peer {} asked tx '{}' which can't be sent at its protocol version", peer.get_connection().get_address(), hash);
+                                false
+                            },
                             Ok(tx) => {
                                 peer.send_packet(Packet::ObjectResponse(ObjectResponse::Transaction(Cow::Borrowed(tx)))).await?;
+                                true
                             },
                             Err(e) => {
                                 debug!("Peer {} asked tx '{}' but got on error while retrieving it: {}", peer.get_connection().get_address(), hash, e);
-                                peer.send_packet(Packet::ObjectResponse(ObjectResponse::NotFound(request))).await?;
+                                false
                             }
                         }
                     }
+                };
+
+                if !found {
+                    peer.send_packet(Packet::ObjectResponse(self.build_missing_object_response(peer, request).await)).await?;
                 }
             },
             Packet::ObjectResponse(response) => {
@@ -584,9 +1237,50 @@ impl P2pServer {
         Ok(())
     }
 
+    // check if we are currently downloading this object from one of our peers
+    pub async fn is_object_requested(&self, request: &ObjectRequest) -> bool {
+        let peer_list = self.peer_list.lock().await;
+        for peer in peer_list.get_peers().values() {
+            if peer.get_objects_requested().lock().await.contains_key(request) {
+                return true
+            }
+        }
+        false
+    }
+
+    // response for an object we don't have: pending if we are downloading it, not found otherwise
+    // a peer that doesn't know the pending response always gets not found
+    async fn build_missing_object_response(&self, peer: &Arc<Peer>, request: ObjectRequest) -> ObjectResponse<'_> {
+        if peer.has_capability(CAPABILITY_PENDING_OBJECT) && self.is_object_requested(&request).await {
+            ObjectResponse::Pending(request)
+        } else {
+            ObjectResponse::NotFound(request)
+        }
+    }
+
     // Listen to incoming packets from a connection
     async fn listen_connection(self: &Arc<Self>, buf: &mut [u8], peer: &Arc<Peer>) -> Result<(), P2pError> {
-        let packet = peer.get_connection().read_packet(buf, MAX_BLOCK_SIZE as u32).await?;
+        let packet = match peer.get_connection().read_packet(buf, MAX_BLOCK_SIZE as u32, self.log_packet_bytes).await {
+            Ok(packet) => packet,
+            // a few unknown packets may come from a newer version, a peer sending too many of them is dropped
+            Err(P2pError::UnknownPacketId(id)) => {
+                let count = peer.increment_unknown_packets();
+                debug!("Ignoring packet with unknown id {} from {} ({}/{})", id, peer.get_connection().get_address(), count, P2P_UNKNOWN_PACKETS_LIMIT);
+                if count >= P2P_UNKNOWN_PACKETS_LIMIT {
+                    return Err(P2pError::TooManyUnknownPackets(count))
+                }
+                return Ok(())
+            },
+            Err(e) => return Err(e)
+        };
+        peer.set_last_activity(get_monotonic_time());
+        // the handshake is done only once, a peer sending it again is confused or malicious and is dropped
+        if let Packet::Handshake(_) = packet {
+            warn!("{} sent a handshake again after the connection was established", peer);
+            peer.increment_fail_count();
+            return Err(P2pError::HandshakeAlreadyDone)
+        }
+
         if let Err(e) = self.handle_incoming_packet(peer, packet).await {
             error!("Error occured while handling incoming packet from {}: {}", peer.get_connection().get_address(), e);
             peer.increment_fail_count();
@@ -621,46 +1315,231 @@ impl P2pServer {
     }
 
     async fn handle_chain_response(self: Arc<Self>, peer: &Arc<Peer>, blocks_request: Vec<Hash>, pop_count: u64) -> Result<(), BlockchainError> {
-        let ping = self.build_ping_packet_for_peer(peer).await;
+        // all peers ahead of us can serve the blocks, the peer which sent the chain response is always one of them
+        let peers: Vec<Arc<Peer>> = {
+            let peer_list = self.peer_list.lock().await;
+            let our_height = self.blockchain.get_height();
+            let mut peers: Vec<Arc<Peer>> = peer_list.get_peers().values().filter(|p| p.get_id() != peer.get_id() && p.is_ahead_of(our_height) && p.can_serve_chain()).cloned().collect();
+            peers.push(Arc::clone(peer));
+            peers
+        };
+        let (block_height, block_top_hash) = self.blockchain.get_chain_state().await;
+        let ping = Arc::new(Ping::new(Cow::Owned(block_top_hash), block_height, Vec::new()));
         let mut storage = self.blockchain.get_storage().lock().await; // lock until we get all blocks
-        let mut blocks: Vec<CompleteBlock> = Vec::with_capacity(blocks_request.len());
-
-        for hash in blocks_request { // Request all complete blocks now
-            let object_request = ObjectRequest::Block(hash);
-            let response = peer.request_blocking_object(object_request, &ping).await?;
-            if let OwnedObjectResponse::Block(block) = response {
-                let hash = block.hash();
-                debug!("Received block {} from peer {}", hash, peer.get_connection().get_address());
-                blocks.push(block);
-            } else {
-                error!("Peer {} sent us an invalid block response", peer.get_connection().get_address());
-                return Err(P2pError::ExpectedBlock.into())
-            }
-        }
+        let blocks = self.request_blocks(peer, &peers, blocks_request, ping).await?;
 
-        if pop_count > 0 && (pop_count <= MAX_BLOCK_REWIND && peer.is_priority()) {
+        let mut disconnected = None;
+        if pop_count > 0 && (pop_count <= self.blockchain.get_max_reorg_depth() && peer.is_priority()) {
             warn!("Rewinding chain because of peer {} (priority: {}, pop count: {})", peer.get_connection().get_address(), peer.is_priority(), pop_count);
-            if let Err(e) = self.blockchain.rewind_chain_for_storage(&mut storage, pop_count as usize).await {
-                error!("Error on rewind chain: pop count: {}, error: {}", pop_count, e);
+            match self.blockchain.rewind_chain_for_storage(&mut storage, pop_count as usize).await {
+                Ok(hashes) => disconnected = Some((storage.get_top_block_hash().clone(), hashes)),
+                Err(e) => error!("Error on rewind chain: pop count: {}, error: {}", pop_count, e)
+            };
+        }
+
+        // the reorg is reported even if a block of the new branch is invalid, blocks were disconnected anyway
+        // the download reservation of a block is released once it's added
+        let mut connected = Vec::new();
+        let mut result = Ok(());
+        for (block, _reservation) in blocks {
+            let hash = block.hash();
+            if let Err(e) = self.blockchain.add_new_block_for_storage(&mut storage, block, false).await {
+                result = Err(e);
+                break;
             }
+            connected.push(hash);
         }
 
-        for block in blocks {
-            self.blockchain.add_new_block_for_storage(&mut storage, block, false).await?;
+        if let Some((common_ancestor, disconnected)) = disconnected {
+            self.notify_reorg(ReorgEvent {
+                common_ancestor,
+                disconnected,
+                connected
+            });
+        }
+        result
+    }
+
+    // request all blocks, spread over the peers available so no single peer is a bottleneck
+    // a peer never has more than `max_requests_per_peer` requests in flight
+    // a failed request is sent again to the peer which sent us the chain response
+    // blocks are returned in the same order as requested
+    // each request reserves the max block size in the download budget, then the real size once received
+    // when the budget is full with nothing left in flight, the remaining blocks are left for the next sync round
+    async fn request_blocks(&self, source: &Arc<Peer>, peers: &[Arc<Peer>], hashes: Vec<Hash>, ping: Arc<Ping<'static>>) -> Result<Vec<(CompleteBlock, DownloadReservation)>, P2pError> {
+        let total = hashes.len();
+        let mut blocks: Vec<(CompleteBlock, DownloadReservation)> = Vec::with_capacity(total);
+        let mut handles = VecDeque::with_capacity(total);
+        for hash in hashes {
+            let reservation = loop {
+                if let Some(reservation) = self.download_budget.try_reserve(MAX_BLOCK_SIZE) {
+                    break Some(reservation)
+                }
+                // budget is full, wait on the oldest request
+                match handles.pop_front() {
+                    Some(handle) => blocks.push(handle.await??),
+                    None => break None
+                };
+            };
+            let mut reservation = match reservation {
+                Some(reservation) => reservation,
+                None => {
+                    debug!("Download budget of {} bytes is full, {} blocks will be requested later", self.download_budget.get_max_bytes(), total - blocks.len());
+                    break;
+                }
+            };
+
+            let peer = loop {
+                if let Some(peer) = self.select_peer_for_request(peers) {
+                    break Arc::clone(peer)
+                }
+                // all peers are busy, wait on the oldest request
+                match handles.pop_front() {
+                    Some(handle) => blocks.push(handle.await??),
+                    None => break Arc::clone(source)
+                };
+            };
+
+            peer.increment_requests_in_flight();
+            let source = Arc::clone(source);
+            let ping = Arc::clone(&ping);
+            handles.push_back(tokio::spawn(async move {
+                let res = Self::request_block(&peer, hash.clone(), &ping).await;
+                peer.decrement_requests_in_flight();
+                let block = match res {
+                    Err(e) if !Arc::ptr_eq(&peer, &source) => {
+                        debug!("Error while requesting block {} from {}: {}, trying with {}", hash, peer.get_connection().get_address(), e, source.get_connection().get_address());
+                        peer.increment_fail_count();
+                        Self::request_block(&source, hash, &ping).await
+                    },
+                    res => res
+                }?;
+                reservation.resize(block.size());
+                Ok::<_, P2pError>((block, reservation))
+            }));
+        }
+
+        for handle in handles {
+            blocks.push(handle.await??);
+        }
+        Ok(blocks)
+    }
+
+    // peer with the fewest requests in flight, None if all peers reached the limit
+    fn select_peer_for_request<'a>(&self, peers: &'a [Arc<Peer>]) -> Option<&'a Arc<Peer>> {
+        peers.iter()
+            .filter(|peer| !peer.get_connection().is_closed() && peer.get_requests_in_flight() < self.max_requests_per_peer)
+            .min_by_key(|peer| peer.get_requests_in_flight())
+    }
+
+    async fn request_block(peer: &Arc<Peer>, hash: Hash, ping: &Ping<'_>) -> Result<CompleteBlock, P2pError> {
+        let response = peer.request_blocking_object(ObjectRequest::Block(hash), ping).await?;
+        if let OwnedObjectResponse::Block(block) = response {
+            debug!("Received block {} from peer {}", block.hash(), peer.get_connection().get_address());
+            Ok(block)
+        } else {
+            error!("Peer {} sent us an invalid block response", peer.get_connection().get_address());
+            Err(P2pError::ExpectedBlock)
         }
-        Ok(())
     }
 
     pub fn get_tag(&self) -> &Option<String> {
         &self.tag
     }
 
+    // optional features advertised in our handshake
+    pub fn get_capabilities(&self) -> u8 {
+        let mut capabilities = CAPABILITY_PEER_EXCHANGE | CAPABILITY_INVENTORY | CAPABILITY_PENDING_OBJECT;
+        if self.packet_sequence {
+            capabilities |= CAPABILITY_PACKET_SEQUENCE;
+        }
+        capabilities
+    }
+
     pub fn get_max_peers(&self) -> usize {
         self.max_peers
     }
 
+    pub fn get_max_outgoing_handshakes(&self) -> usize {
+        self.max_outgoing_handshakes
+    }
+
+    // number of outgoing handshakes currently in progress
+    pub fn get_outgoing_handshakes_in_progress(&self) -> usize {
+        self.max_outgoing_handshakes - self.outgoing_handshakes.available_permits()
+    }
+
+    pub fn get_handshake_timeout(&self) -> &Duration {
+        &self.handshake_timeout
+    }
+
+    pub fn get_max_peers_per_subnet(&self) -> usize {
+        self.max_peers_per_subnet
+    }
+
+    pub fn get_max_requests_per_peer(&self) -> usize {
+        self.max_requests_per_peer
+    }
+
+    // bytes reserved by the blocks downloaded or in download and not yet added to the chain
+    pub fn get_download_bytes(&self) -> usize {
+        self.download_budget.get_bytes()
+    }
+
+    pub fn get_max_download_bytes(&self) -> usize {
+        self.download_budget.get_max_bytes()
+    }
+
+    // object requests in flight for each peer
+    pub async fn get_requests_in_flight(&self) -> HashMap<SocketAddr, usize> {
+        let peer_list = self.peer_list.lock().await;
+        peer_list.get_peers().values().map(|peer| (*peer.get_connection().get_address(), peer.get_requests_in_flight())).collect()
+    }
+
+    // current distribution of outgoing peers by subnet
+    pub async fn get_subnets_distribution(&self) -> HashMap<IpAddr, usize> {
+        self.peer_list.lock().await.get_subnets_distribution()
+    }
+
+    pub fn get_idle_timeout(&self) -> u64 {
+        self.idle_timeout
+    }
+
     pub fn get_peer_id(&self) -> u64 {
-        self.peer_id
+        self.peer_id.load(Ordering::Relaxed)
+    }
+
+    // generate a new peer id, for example if the current one was banned by other nodes
+    // all peers are dropped so they see the new id, the ones we dialed are connected again
+    // and the others have to reconnect to us. Returns the new peer id
+    pub async fn rotate_identity(self: &Arc<Self>) -> u64 {
+        let peer_id: u64 = rand::thread_rng().gen();
+        let old_peer_id = self.peer_id.swap(peer_id, Ordering::Relaxed);
+        info!("Peer id rotated from {} to {}", old_peer_id, peer_id);
+
+        let outgoing: Vec<(SocketAddr, bool)> = {
+            let mut peer_list = self.peer_list.lock().await;
+            let outgoing = peer_list.get_peers().values()
+                .filter(|peer| peer.is_out())
+                .map(|peer| (*peer.get_connection().get_address(), peer.is_priority()))
+                .collect();
+            peer_list.close_all("peer id rotated").await;
+            outgoing
+        };
+
+        for (addr, priority) in outgoing {
+            self.try_to_connect_to_peer(addr, priority);
+        }
+        peer_id
+    }
+
+    // number of new peers we can accept
+    pub async fn get_slots_available(&self) -> usize {
+        self.get_max_peers().saturating_sub(self.get_peer_count().await)
+    }
+
+    pub async fn get_known_addresses_count(&self) -> usize {
+        self.peer_list.lock().await.get_known_addresses().len()
     }
 
     pub async fn accept_new_connections(&self) -> bool {
@@ -672,6 +1551,16 @@ impl P2pServer {
         peer_list.size()
     }
 
+    // info of all connected peers, the peer list is not locked while reading them
+    pub async fn get_peers_info(&self) -> Vec<PeerInfo> {
+        let peers: Vec<Arc<Peer>> = self.peer_list.lock().await.get_peers().values().cloned().collect();
+        let mut infos = Vec::with_capacity(peers.len());
+        for peer in peers {
+            infos.push(peer.info().await);
+        }
+        infos
+    }
+
     pub async fn get_best_height(&self) -> u64 {
         let our_height = self.blockchain.get_height();
         let peer_list = self.peer_list.lock().await;
@@ -685,30 +1574,86 @@ impl P2pServer {
 
     pub async fn is_connected_to(&self, peer_id: &u64) -> Result<bool, P2pError> {
         let peer_list = self.peer_list.lock().await;
-        Ok(self.peer_id == *peer_id || peer_list.has_peer(peer_id))
+        Ok(self.get_peer_id() == *peer_id || peer_list.has_peer(peer_id))
     }
 
     pub async fn is_connected_to_addr(&self, peer_addr: &SocketAddr) -> Result<bool, P2pError> {
-        if *peer_addr == *self.get_bind_address() { // don't try to connect to ourself
+        let peer_addr = &canonical_addr(peer_addr);
+        if self.is_own_address(peer_addr) { // don't try to connect to ourself
             debug!("Trying to connect to ourself, ignoring.");
             return Ok(true)
         }
         let peer_list = self.peer_list.lock().await;
         for peer in peer_list.get_peers().values() {
-            if *peer.get_connection().get_address() == *peer_addr {
+            let addr = peer.get_connection().get_address();
+            if *addr == *peer_addr {
+                return Ok(true)
+            }
+
+            // incoming connection use an ephemeral port, compare with the port advertised in its handshake
+            if !peer.is_out() && addr.ip() == peer_addr.ip() && peer.get_local_port() == peer_addr.port() {
                 return Ok(true)
             }
         }
         Ok(false)
     }
 
-    pub fn get_bind_address(&self) -> &SocketAddr {
-        &self.bind_address
+    // check if we are connected to any peer using this IP, whatever its port
+    pub async fn is_connected_to_ip(&self, ip: &IpAddr) -> bool {
+        let peer_list = self.peer_list.lock().await;
+        peer_list.get_peers().values().any(|p| p.get_connection().get_address().ip() == *ip)
+    }
+
+    pub fn get_priority_nodes(&self) -> &Vec<SocketAddr> {
+        &self.priority_nodes
+    }
+
+    // returns the priority nodes currently connected and the ones still pending
+    pub async fn get_priority_nodes_status(&self) -> (Vec<SocketAddr>, Vec<SocketAddr>) {
+        let mut connected = Vec::new();
+        let mut pending = Vec::new();
+        for addr in &self.priority_nodes {
+            if self.is_connected_to_addr(addr).await.unwrap_or(false) {
+                connected.push(*addr);
+            } else {
+                pending.push(*addr);
+            }
+        }
+        (connected, pending)
+    }
+
+    // check if this address is one of our bind addresses
+    // a bind address on all interfaces (0.0.0.0 or ::) also matches any local address on the same port
+    pub fn is_own_address(&self, addr: &SocketAddr) -> bool {
+        let addr = canonical_addr(addr);
+        self.bind_addresses.iter().any(|bind| {
+            bind.port() == addr.port() && (bind.ip() == addr.ip() || (bind.ip().is_unspecified() && (addr.ip().is_loopback() || addr.ip().is_unspecified())))
+        })
+    }
+
+    pub fn is_outbound_enabled(&self) -> bool {
+        self.outbound_enabled
+    }
+
+    pub fn get_bind_addresses(&self) -> &Vec<SocketAddr> {
+        &self.bind_addresses
     }
 
-    pub async fn broadcast_tx_hash(&self, tx: &Hash) {
+    // an extended tx is not announced to peers at protocol version 0, they couldn't read it
+    pub async fn broadcast_tx_hash(&self, tx: &Hash, extended: bool) {
+        // batch it with the other txs seen during the announcement delay
+        if self.announcement_delay > 0 {
+            let mut pending = self.pending_announcements.lock().await;
+            if !pending.iter().any(|(hash, _)| hash == tx) {
+                pending.push((tx.clone(), extended));
+            }
+            return;
+        }
+
         let ping = self.build_ping_packet(None).await;
-        self.broadcast_packet(Packet::TransactionPropagation(PacketWrapper::new(Cow::Borrowed(tx), Cow::Owned(ping)))).await;
+        let packet = Packet::TransactionPropagation(PacketWrapper::new(Cow::Borrowed(tx), Cow::Owned(ping)));
+        let peer_list = self.peer_list.lock().await;
+        peer_list.broadcast_filter(|(_, peer)| !extended || peer.supports_extended_transactions(), Bytes::from(packet.to_bytes())).await;
     }
 
     // broadcast block to all peers that can accept directly this new block
@@ -721,10 +1666,11 @@ impl P2pServer {
         let packet = Packet::BlockPropagation(PacketWrapper::new(Cow::Borrowed(block), Cow::Owned(ping)));
         let bytes = Bytes::from(packet.to_bytes());
         // TODO should we move it in another async task ?
+        let extended = block.has_extended_transactions();
         let peer_list = self.peer_list.lock().await;
         for (_, peer) in peer_list.get_peers() {
             // if the peer can directly accept this new block, send it
-            if peer.get_block_height() == block_height - 1 {
+            if peer.get_block_height() == block_height - 1 && (!extended || peer.supports_extended_transactions()) {
                 trace!("Broadcast block to {}", peer);
                 peer_list.send_bytes_to_peer(peer, bytes.clone()).await;
                 peer.set_block_height(block_height); // we suppose peer will accept the block like us
@@ -742,8 +1688,10 @@ impl P2pServer {
         {
             let storage = self.blockchain.get_storage().lock().await;
             let height = self.blockchain.get_height();
+            // blocks before the snapshot of a bootstrapped chain are not stored
+            let base_height = storage.get_base_height();
             let mut i = 0;
-            while i < height && request.size() + 1 < CHAIN_SYNC_REQUEST_MAX_BLOCKS {
+            while i < height - base_height && request.size() + 1 < CHAIN_SYNC_REQUEST_MAX_BLOCKS {
                 let block = storage.get_block_at_height(height - i)?;
                 request.add_block_id(block.hash(), height - i); // TODO get hash from DB
                 match request.size() {
@@ -765,9 +1713,13 @@ impl P2pServer {
                 };
             }
     
-            // add genesis block
-            let genesis_block = storage.get_block_at_height(1)?;
-            request.add_block_id(genesis_block.hash(), 1);
+            // add genesis block, or the snapshot block we started from
+            if base_height != 0 {
+                request.add_block_id(storage.get_base_hash().clone(), base_height);
+            } else {
+                let genesis_block = storage.get_block_at_height(1)?;
+                request.add_block_id(genesis_block.hash(), 1);
+            }
             trace!("Sending a chain request with {} blocks", request.size());
             peer.set_chain_sync_requested(true);
         }
@@ -775,4 +1727,649 @@ impl P2pServer {
         peer.send_packet(Packet::ChainRequest(PacketWrapper::new(Cow::Owned(request), Cow::Owned(ping)))).await?;
         Ok(())
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{P2P_DEFAULT_MAX_PEERS, P2P_DEFAULT_MAX_OUTGOING_HANDSHAKES, P2P_DEFAULT_HANDSHAKE_TIMEOUT, P2P_DEFAULT_IDLE_TIMEOUT, P2P_DEFAULT_MAX_PEERS_PER_SUBNET, P2P_DEFAULT_MAX_REQUESTS_PER_PEER, P2P_DEFAULT_MAX_DOWNLOAD_BYTES};
+    use crate::core::blockchain::tests::{create_test_chain, create_test_chain_on, create_test_network, add_test_block};
+    use crate::core::network::Network;
+    use crate::core::block::Block;
+    use crate::core::reader::Reader;
+    use crate::core::transaction::{Transaction, TransactionVariant};
+    use crate::crypto::key::KeyPair;
+    use crate::p2p::client;
+    use crate::p2p::peer::PeerDetails;
+    use crate::p2p::peer::tests::test_details;
+    use crate::p2p::packet::PacketKind;
+    use std::sync::atomic::AtomicUsize;
+    use crate::p2p::packet::handshake::{CAPABILITY_PRUNED, CAPABILITY_LIGHT_CLIENT};
+    use tokio::io::AsyncReadExt;
+
+    // the advertised port can't be 0, so a free port is picked before starting the server
+    fn free_port() -> u16 {
+        StdTcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port()
+    }
+
+    // listen-only server on localhost, with the default settings
+    fn test_config() -> P2pConfig {
+        P2pConfig {
+            tag: None,
+            max_peers: P2P_DEFAULT_MAX_PEERS,
+            bind_addresses: vec![format!("127.0.0.1:{}", free_port())],
+            max_outgoing_handshakes: P2P_DEFAULT_MAX_OUTGOING_HANDSHAKES,
+            handshake_timeout: P2P_DEFAULT_HANDSHAKE_TIMEOUT,
+            idle_timeout: P2P_DEFAULT_IDLE_TIMEOUT,
+            max_peers_per_subnet: P2P_DEFAULT_MAX_PEERS_PER_SUBNET,
+            max_requests_per_peer: P2P_DEFAULT_MAX_REQUESTS_PER_PEER,
+            max_download_bytes: P2P_DEFAULT_MAX_DOWNLOAD_BYTES,
+            log_packet_bytes: false,
+            packet_sequence: false,
+            announcement_delay: 0,
+            outbound_enabled: false,
+            proxy: None,
+            priority_nodes: Vec::new()
+        }
+    }
+
+    async fn create_test_server(config: P2pConfig) -> Arc<P2pServer> {
+        let blockchain = Arc::new(create_test_chain(&KeyPair::new(), 1).await);
+        P2pServer::new(config, blockchain).unwrap()
+    }
+
+    fn get_address(server: &P2pServer) -> SocketAddr {
+        server.bind_addresses[0]
+    }
+
+    // handshake accepted by the test server, for a remote node at the same chain state listening on local_port
+    async fn test_handshake(server: &P2pServer, peer_id: u64, local_port: u16) -> HandshakeBuilder {
+        let blockchain = &server.blockchain;
+        let (block_height, top_hash) = blockchain.get_chain_state().await;
+        HandshakeBuilder::new(peer_id, local_port)
+            .block_height(block_height)
+            .block_top_hash(top_hash)
+            .network_id(*blockchain.get_network().get_network_id())
+            .genesis_hash(blockchain.get_genesis_hash().await.unwrap())
+            .hash_algorithm(blockchain.get_network().get_hash_algorithm())
+    }
+
+    // wait until the server registered the expected number of peers
+    async fn wait_for_peer_count(server: &P2pServer, count: usize) {
+        timeout(Duration::from_secs(2), async {
+            while server.get_peer_count().await != count {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        }).await.expect("peer count not reached");
+    }
+
+    #[tokio::test]
+    async fn test_silent_connection_is_dropped_after_handshake_timeout() {
+        let mut config = test_config();
+        config.handshake_timeout = 200;
+        let server = create_test_server(config).await;
+        assert_eq!(*server.get_handshake_timeout(), Duration::from_millis(200));
+
+        let mut stream = TcpStream::connect(get_address(&server)).await.unwrap();
+        let mut buf = [0u8; 16];
+        // still open before the timeout
+        assert!(timeout(Duration::from_millis(100), stream.read(&mut buf)).await.is_err());
+        // then closed by the server without sending anything
+        let read = timeout(Duration::from_secs(2), stream.read(&mut buf)).await.unwrap();
+        assert!(matches!(read, Ok(0) | Err(_)));
+    }
+
+    #[tokio::test]
+    async fn test_incoming_peer_matched_on_advertised_port() {
+        let server = create_test_server(test_config()).await;
+        let local_port = free_port();
+        let handshake = test_handshake(&server, 42, local_port).await.build().unwrap();
+        let _peer = client::connect(get_address(&server), handshake).await.unwrap();
+        wait_for_peer_count(&server, 1).await;
+
+        // the connection comes from an ephemeral port, but the peer is reachable on its advertised port
+        let localhost: IpAddr = "127.0.0.1".parse().unwrap();
+        assert!(server.is_connected_to_addr(&SocketAddr::new(localhost, local_port)).await.unwrap());
+        assert!(!server.is_connected_to_addr(&SocketAddr::new(localhost, free_port())).await.unwrap());
+        // same host whatever the port
+        assert!(server.is_connected_to_ip(&localhost).await);
+        assert!(!server.is_connected_to_ip(&"127.0.0.2".parse().unwrap()).await);
+    }
+
+    #[tokio::test]
+    async fn test_packet_sequence_negotiation() {
+        let mut config = test_config();
+        config.packet_sequence = true;
+        let server = create_test_server(config).await;
+
+        // enabled only for the peer advertising the capability
+        let handshake = test_handshake(&server, 1, free_port()).await.capabilities(CAPABILITY_PACKET_SEQUENCE).build().unwrap();
+        let sequenced = client::connect(get_address(&server), handshake).await.unwrap();
+        let handshake = test_handshake(&server, 2, free_port()).await.build().unwrap();
+        let plain = client::connect(get_address(&server), handshake).await.unwrap();
+        wait_for_peer_count(&server, 2).await;
+        assert!(sequenced.get_connection().is_packet_sequence_enabled());
+        assert!(!plain.get_connection().is_packet_sequence_enabled());
+
+        let peer_list = server.peer_list.lock().await;
+        let peers = peer_list.get_peers();
+        assert!(peers[&1].get_connection().is_packet_sequence_enabled());
+        assert!(!peers[&2].get_connection().is_packet_sequence_enabled());
+    }
+
+    #[tokio::test]
+    async fn test_peer_sending_unknown_packets_is_dropped() {
+        let server = create_test_server(test_config()).await;
+        let handshake = test_handshake(&server, 1, free_port()).await.build().unwrap();
+        let client = client::connect(get_address(&server), handshake).await.unwrap();
+        wait_for_peer_count(&server, 1).await;
+        let peer = Arc::clone(&server.peer_list.lock().await.get_peers()[&1]);
+
+        // size, unknown packet id and its payload
+        let packet = [0, 0, 0, 2, 250, 0];
+        for _ in 1..P2P_UNKNOWN_PACKETS_LIMIT {
+            client.get_connection().send_packet_bytes(&packet).await.unwrap();
+        }
+        timeout(Duration::from_secs(2), async {
+            while peer.get_unknown_packets() != P2P_UNKNOWN_PACKETS_LIMIT - 1 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        }).await.expect("unknown packets not counted");
+        // tolerated until the limit
+        assert_eq!(server.get_peer_count().await, 1);
+
+        client.get_connection().send_packet_bytes(&packet).await.unwrap();
+        wait_for_peer_count(&server, 0).await;
+        let reasons = server.get_disconnect_reasons(&client.get_connection().get_address().ip()).await;
+        assert!(reasons.iter().any(|(reason, _)| *reason == DisconnectReason::ProtocolViolation));
+    }
+
+    #[tokio::test]
+    async fn test_peer_violation_event() {
+        let mut config = test_config();
+        config.log_packet_bytes = true;
+        let server = create_test_server(config).await;
+        let mut violations = server.subscribe_violations();
+        let handshake = test_handshake(&server, 1, free_port()).await.build().unwrap();
+        let client = client::connect(get_address(&server), handshake).await.unwrap();
+        wait_for_peer_count(&server, 1).await;
+
+        // size, ping packet id and a truncated ping
+        client.get_connection().send_packet_bytes(&[0, 0, 0, 3, 5, 1, 2]).await.unwrap();
+        let violation = timeout(Duration::from_secs(2), violations.recv()).await.unwrap().unwrap();
+        assert_eq!(violation.peer_id, Some(1));
+        assert_eq!(violation.addr.ip(), client.get_connection().get_address().ip());
+        assert_eq!(violation.reason, DisconnectReason::InvalidPacket);
+        assert!(matches!(violation.error, P2pError::ReaderError(_)));
+        assert_eq!(violation.bytes.as_deref(), Some("050102"));
+        wait_for_peer_count(&server, 0).await;
+    }
+
+    #[tokio::test]
+    async fn test_second_handshake_is_rejected() {
+        let server = create_test_server(test_config()).await;
+        let handshake = test_handshake(&server, 1, free_port()).await.build().unwrap();
+        let client = client::connect(get_address(&server), handshake.clone()).await.unwrap();
+        wait_for_peer_count(&server, 1).await;
+        let peer = Arc::clone(&server.peer_list.lock().await.get_peers()[&1]);
+        assert_eq!(peer.get_fail_count(), 0);
+
+        client.send_packet(Packet::Handshake(Cow::Owned(handshake))).await.unwrap();
+        wait_for_peer_count(&server, 0).await;
+        assert_eq!(peer.get_fail_count(), 1);
+        let reasons = server.get_disconnect_reasons(&client.get_connection().get_address().ip()).await;
+        assert!(reasons.iter().any(|(reason, _)| *reason == DisconnectReason::ProtocolViolation));
+    }
+
+    #[tokio::test]
+    async fn test_mapped_addr_is_same_peer() {
+        let server = create_test_server(test_config()).await;
+        let (peer, _remote) = add_test_peer(&server, 1, 0, Hash::zero()).await;
+        let addr = *peer.get_connection().get_address();
+        let mapped: SocketAddr = format!("[::ffff:{}]:{}", addr.ip(), addr.port()).parse().unwrap();
+        assert!(server.is_connected_to_addr(&mapped).await.unwrap());
+        // our own address is also detected when IPv4-mapped
+        let own = get_address(&server);
+        assert!(server.is_own_address(&format!("[::ffff:{}]:{}", own.ip(), own.port()).parse().unwrap()));
+    }
+
+    #[tokio::test]
+    async fn test_own_address_is_removed_from_handshake_peers() {
+        let mut config = test_config();
+        let port = free_port();
+        config.bind_addresses.push(format!("0.0.0.0:{}", port));
+        let server = create_test_server(config).await;
+
+        let other: SocketAddr = "10.0.0.1:2125".parse().unwrap();
+        let peers = vec![get_address(&server), format!("127.0.0.2:{}", port).parse().unwrap(), other];
+        let handshake = test_handshake(&server, 42, free_port()).await.peers(peers).build().unwrap();
+        let (local, _remote) = tokio::io::duplex(4096);
+        let connection = Connection::new(local, "127.0.0.1:2125".parse().unwrap());
+        let (_, peers) = server.verify_handshake(connection, handshake, PROTOCOL_VERSION, false, false).await.unwrap();
+        // both our bind address and a local address on the port bound on all interfaces are removed
+        assert_eq!(peers, vec![other]);
+    }
+
+    #[tokio::test]
+    async fn test_wrong_chain_with_same_network_id() {
+        let server = create_test_server(test_config()).await;
+        let genesis = server.blockchain.get_genesis_hash().await.unwrap();
+
+        // same network id, another genesis block
+        let handshake = test_handshake(&server, 42, free_port()).await.genesis_hash(Hash::max()).build().unwrap();
+        let (local, _remote) = tokio::io::duplex(4096);
+        let connection = Connection::new(local, "127.0.0.1:2125".parse().unwrap());
+        let error = match server.verify_handshake(connection, handshake, PROTOCOL_VERSION, false, false).await {
+            Err(e) => e,
+            Ok(_) => panic!("handshake of another chain was accepted")
+        };
+        assert!(matches!(&error, P2pError::WrongChain { expected, got } if *expected == genesis && *got == Hash::max()));
+        assert_eq!(DisconnectReason::from_error(&error), Some(DisconnectReason::WrongNetwork));
+
+        // another network id is still reported as such
+        let handshake = test_handshake(&server, 42, free_port()).await.network_id([2u8; 16]).genesis_hash(Hash::max()).build().unwrap();
+        let (local, _remote) = tokio::io::duplex(4096);
+        let connection = Connection::new(local, "127.0.0.1:2125".parse().unwrap());
+        assert!(matches!(server.verify_handshake(connection, handshake, PROTOCOL_VERSION, false, false).await, Err(P2pError::InvalidNetworkID)));
+    }
+
+    #[tokio::test]
+    async fn test_priority_nodes_dialed_first() {
+        let mut network = create_test_network(&KeyPair::new(), 1);
+        network.set_seed_nodes(vec!["10.0.0.1:2125".to_owned(), "10.0.0.2:2125".to_owned()]);
+        let blockchain = Arc::new(create_test_chain_on(network).await);
+
+        let mut config = test_config();
+        config.outbound_enabled = true;
+        config.priority_nodes = vec!["10.0.0.3:2125".parse().unwrap(), "10.0.0.2:2125".parse().unwrap()];
+        let server = P2pServer::new(config, Arc::clone(&blockchain)).unwrap();
+        let expected: Vec<SocketAddr> = ["10.0.0.3:2125", "10.0.0.2:2125", "10.0.0.1:2125"].iter().map(|addr| addr.parse().unwrap()).collect();
+        assert_eq!(server.get_startup_nodes(), expected);
+
+        // seed nodes are not dialed without outbound connections, priority nodes are
+        let mut config = test_config();
+        config.priority_nodes = vec!["10.0.0.3:2125".parse().unwrap()];
+        let server = P2pServer::new(config, blockchain).unwrap();
+        assert_eq!(server.get_startup_nodes(), vec!["10.0.0.3:2125".parse().unwrap()]);
+    }
+
+    #[tokio::test]
+    async fn test_priority_node_status() {
+        let node = create_test_server(test_config()).await;
+        let unreachable: SocketAddr = format!("127.0.0.1:{}", free_port()).parse().unwrap();
+        let mut config = test_config();
+        config.priority_nodes = vec![get_address(&node), unreachable];
+        // both servers must be on the same chain
+        let server = P2pServer::new(config, Arc::clone(&node.blockchain)).unwrap();
+
+        wait_for_peer_count(&server, 1).await;
+        let (connected, pending) = server.get_priority_nodes_status().await;
+        assert_eq!(connected, vec![get_address(&node)]);
+        assert_eq!(pending, vec![unreachable]);
+    }
+
+    // accept connections and close them right away, after reading the first bytes if `read` is set
+    // returns the address and the count of accepted connections
+    async fn spawn_closing_listener(read: bool) -> (SocketAddr, Arc<AtomicUsize>) {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let accepted = Arc::new(AtomicUsize::new(0));
+        let count = Arc::clone(&accepted);
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                count.fetch_add(1, Ordering::SeqCst);
+                if read {
+                    let mut buf = [0u8; 1];
+                    let _ = stream.read(&mut buf).await;
+                }
+            }
+        });
+        (addr, accepted)
+    }
+
+    #[tokio::test]
+    async fn test_protocol_zero_fallback_only_after_handshake() {
+        // the peer closes the connection after reading our handshake, it's dialed again with the protocol version 0
+        let server = create_test_server(test_config()).await;
+        let (addr, accepted) = spawn_closing_listener(true).await;
+        assert!(Arc::clone(&server).connect_to_peer(addr, false).await.is_err());
+        assert_eq!(accepted.load(Ordering::SeqCst), 2);
+
+        // the connection can't be opened (the proxy drops us), so the address is dialed only once
+        let (proxy, accepted) = spawn_closing_listener(false).await;
+        let mut config = test_config();
+        config.proxy = Some(proxy);
+        let server = create_test_server(config).await;
+        assert!(matches!(Arc::clone(&server).connect_to_peer("10.0.0.1:2125".parse().unwrap(), false).await, Err(P2pError::ErrorStd(_))));
+        assert_eq!(accepted.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_rotate_identity() {
+        let node = create_test_server(test_config()).await;
+        let mut config = test_config();
+        config.priority_nodes = vec![get_address(&node)];
+        let server = P2pServer::new(config, Arc::clone(&node.blockchain)).unwrap();
+        wait_for_peer_count(&node, 1).await;
+        let old_peer_id = server.get_peer_id();
+        assert!(node.peer_list.lock().await.has_peer(&old_peer_id));
+
+        let peer_id = server.rotate_identity().await;
+        assert_ne!(peer_id, old_peer_id);
+        assert_eq!(server.get_peer_id(), peer_id);
+        // the node is dialed again and sees our new peer id in the new handshake
+        timeout(Duration::from_secs(2), async {
+            loop {
+                {
+                    let peer_list = node.peer_list.lock().await;
+                    if peer_list.has_peer(&peer_id) && !peer_list.has_peer(&old_peer_id) {
+                        break
+                    }
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        }).await.unwrap();
+        wait_for_peer_count(&server, 1).await;
+    }
+
+    // register a peer at this chain state, without any connection behind it
+    // the remote side is returned as it must be kept alive for the connection to stay open
+    async fn add_test_peer(server: &P2pServer, id: u64, block_height: u64, top_hash: Hash) -> (Arc<Peer>, tokio::io::DuplexStream) {
+        add_test_peer_with_capabilities(server, id, block_height, top_hash, 0).await
+    }
+
+    async fn add_test_peer_with_capabilities(server: &P2pServer, id: u64, block_height: u64, top_hash: Hash, capabilities: u8) -> (Arc<Peer>, tokio::io::DuplexStream) {
+        let (local, remote) = tokio::io::duplex(4096);
+        let addr = SocketAddr::new("10.0.0.1".parse().unwrap(), 2000 + id as u16);
+        let connection = Connection::new(local, addr);
+        let peer = Peer::new(connection, PeerDetails { protocol_version: PROTOCOL_VERSION, capabilities, block_top_hash: top_hash, block_height, ..test_details(id, addr.port()) }, false, false, Arc::clone(&server.peer_list), std::collections::HashSet::new());
+        let peer = server.peer_list.lock().await.add_peer(id, peer);
+        (peer, remote)
+    }
+
+    #[tokio::test]
+    async fn test_packets_not_allowed_for_role() {
+        let server = create_test_server(test_config()).await;
+        let genesis = server.blockchain.get_top_block_hash().await;
+        let (full, _full_remote) = add_test_peer(&server, 1, 100, Hash::zero()).await;
+        let (pruned, _pruned_remote) = add_test_peer_with_capabilities(&server, 2, 100, Hash::zero(), CAPABILITY_PRUNED).await;
+        let (light, _light_remote) = add_test_peer_with_capabilities(&server, 3, 100, Hash::zero(), CAPABILITY_LIGHT_CLIENT).await;
+        let chain_response = || Packet::ChainResponse(ChainResponse::new(Some(CommonPoint::new(Cow::Owned(genesis.clone()), 1)), Vec::new()));
+
+        // only a full node can send us its chain
+        for peer in [&full, &pruned, &light].iter() {
+            peer.set_chain_sync_requested(true);
+        }
+        assert!(server.handle_incoming_packet(&full, chain_response()).await.is_ok());
+        let error = server.handle_incoming_packet(&pruned, chain_response()).await.unwrap_err();
+        assert!(matches!(error, P2pError::PacketNotAllowed(PacketKind::ChainResponse)));
+        assert_eq!(DisconnectReason::from_error(&error), Some(DisconnectReason::ProtocolViolation));
+        assert!(matches!(server.handle_incoming_packet(&light, chain_response()).await, Err(P2pError::PacketNotAllowed(PacketKind::ChainResponse))));
+        // the packet was refused before being handled
+        assert!(pruned.chain_sync_requested());
+
+        // a light client doesn't relay blocks, a pruned node does
+        let block = server.blockchain.get_storage().lock().await.get_block_by_hash(&genesis).unwrap().clone();
+        let ping = Ping::new(Cow::Owned(genesis.clone()), 1, Vec::new());
+        let propagation = || Packet::BlockPropagation(PacketWrapper::new(Cow::Borrowed(&block), Cow::Borrowed(&ping)));
+        assert!(matches!(server.handle_incoming_packet(&light, propagation()).await, Err(P2pError::PacketNotAllowed(PacketKind::BlockPropagation))));
+        assert!(!matches!(server.handle_incoming_packet(&pruned, propagation()).await, Err(P2pError::PacketNotAllowed(_))));
+
+        // and peers which can't serve the chain are never selected to sync from
+        for _ in 0..10 {
+            assert_eq!(server.select_random_best_peer(None).await.unwrap().get_id(), 1);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_best_known_height_ignores_outliers() {
+        let server = create_test_server(test_config()).await;
+        // no peers, we are the best known chain
+        assert_eq!(server.best_known_height().await, server.blockchain.get_height());
+        assert!(server.is_chain_synced().await);
+
+        let mut remotes = Vec::new();
+        for (id, height) in [(1, 100), (2, 110), (3, 105), (4, 110 + P2P_HEIGHT_OUTLIER_THRESHOLD * 10)].iter() {
+            remotes.push(add_test_peer(&server, *id, *height, Hash::zero()).await.1);
+        }
+        // the lying peer is too far above the median
+        assert_eq!(server.best_known_height().await, 110);
+        assert!(!server.is_chain_synced().await);
+    }
+
+    #[tokio::test]
+    async fn test_peers_agreeing_on_top_hash() {
+        let server = create_test_server(test_config()).await;
+        assert!(server.count_peers_agreeing_at(10).await.is_none());
+
+        let mut remotes = Vec::new();
+        for (id, height, hash) in vec![(1, 10, Hash::zero()), (2, 10, Hash::max()), (3, 10, Hash::zero()), (4, 11, Hash::max())] {
+            remotes.push(add_test_peer(&server, id, height, hash).await.1);
+        }
+        // only the peers at this height are counted, the peer alone on its hash is outvoted
+        assert_eq!(server.count_peers_agreeing_at(10).await, Some((Hash::zero(), 2)));
+        assert_eq!(server.count_peers_agreeing_at(11).await, Some((Hash::max(), 1)));
+    }
+
+    #[tokio::test]
+    async fn test_chain_tip_disagreement() {
+        let server = create_test_server(test_config()).await;
+        let (height, top_hash) = server.blockchain.get_chain_state().await;
+        let mut remotes = Vec::new();
+        for (id, peer_height, hash) in vec![(1, height, top_hash.clone()), (2, height, Hash::max()), (3, height, Hash::max()), (4, height + 1, Hash::zero())] {
+            remotes.push(add_test_peer(&server, id, peer_height, hash).await.1);
+        }
+
+        // only the peers at our height are counted
+        let tips = server.chain_tip_consensus(height).await;
+        assert_eq!(tips.len(), 2);
+        assert_eq!(tips[&top_hash], 1);
+        assert_eq!(tips[&Hash::max()], 2);
+        server.check_chain_tip_consensus().await;
+        assert!(server.chain_tip_disagreement.load(Ordering::Relaxed));
+
+        // disagreement is resolved once most peers report our top hash
+        for id in 5..8 {
+            remotes.push(add_test_peer(&server, id, height, top_hash.clone()).await.1);
+        }
+        server.check_chain_tip_consensus().await;
+        assert!(!server.chain_tip_disagreement.load(Ordering::Relaxed));
+    }
+
+    // packets queued for this peer, as no write task is running for the test peers
+    async fn get_queued_packets(peer: &Peer) -> Vec<Vec<u8>> {
+        let mut rx = peer.get_connection().get_rx().lock().await;
+        let mut packets = Vec::new();
+        while let Ok(ConnectionMessage::Packet(bytes)) = rx.try_recv() {
+            packets.push(bytes.to_vec());
+        }
+        packets
+    }
+
+    // decode a packet as queued on a connection, after its size
+    fn read_packet(bytes: &[u8]) -> Packet<'_> {
+        Packet::read(&mut Reader::new(&bytes[4..])).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_announcements_are_batched() {
+        let mut config = test_config();
+        config.announcement_delay = 50;
+        let server = create_test_server(config).await;
+        let (inventory_peer, _inventory_remote) = add_test_peer_with_capabilities(&server, 1, 0, Hash::zero(), CAPABILITY_INVENTORY).await;
+        let (plain_peer, _plain_remote) = add_test_peer(&server, 2, 0, Hash::zero()).await;
+
+        let txs: Vec<Hash> = (1..=3u8).map(|i| Hash::new([i; 32])).collect();
+        for tx in txs.iter().chain(txs.iter()) {
+            server.broadcast_tx_hash(tx, false).await;
+        }
+        // nothing is sent before the end of the delay
+        assert!(get_queued_packets(&inventory_peer).await.is_empty());
+        tokio::time::sleep(Duration::from_millis(150)).await;
+
+        // all the announcements are coalesced in a single inventory, without duplicates
+        let packets = get_queued_packets(&inventory_peer).await;
+        assert_eq!(packets.len(), 1);
+        match read_packet(&packets[0]) {
+            Packet::Inventory(wrapper) => assert_eq!(*wrapper.consume().0.get_txs(), txs),
+            _ => panic!("expected an inventory packet")
+        }
+
+        // a peer without the inventory capability gets one propagation packet per tx
+        let packets = get_queued_packets(&plain_peer).await;
+        assert_eq!(packets.len(), txs.len());
+        assert!(packets.iter().all(|bytes| matches!(read_packet(bytes), Packet::TransactionPropagation(_))));
+    }
+
+    #[tokio::test]
+    async fn test_announcements_without_delay() {
+        let server = create_test_server(test_config()).await;
+        let (peer, _remote) = add_test_peer_with_capabilities(&server, 1, 0, Hash::zero(), CAPABILITY_INVENTORY).await;
+        for i in 1..=3u8 {
+            server.broadcast_tx_hash(&Hash::new([i; 32]), false).await;
+        }
+        // each tx is announced immediately
+        let packets = get_queued_packets(&peer).await;
+        assert_eq!(packets.len(), 3);
+        assert!(packets.iter().all(|bytes| matches!(read_packet(bytes), Packet::TransactionPropagation(_))));
+    }
+
+    #[tokio::test]
+    async fn test_reorg_event() {
+        // two chains from the same genesis block
+        let miner = KeyPair::new();
+        let network = create_test_network(&miner, 1);
+        let fork_network = Network::regtest(network.get_genesis_block().unwrap().clone(), *network.get_network_id());
+        let server = P2pServer::new(test_config(), Arc::new(create_test_chain_on(network).await)).unwrap();
+        let fork = create_test_chain_on(fork_network).await;
+        let genesis = server.blockchain.get_top_block_hash().await;
+        assert_eq!(genesis, fork.get_top_block_hash().await);
+
+        let mut disconnected = Vec::new();
+        for _ in 0..2 {
+            disconnected.insert(0, add_test_block(&server.blockchain, &miner).await);
+        }
+        // the fork starts with another block, including a registration
+        fork.add_tx_to_mempool(Transaction::new(KeyPair::new().get_public_key().clone(), TransactionVariant::Registration), false).await.unwrap();
+        let mut connected = Vec::new();
+        for _ in 0..4 {
+            connected.push(add_test_block(&fork, &miner).await);
+        }
+
+        // the fork is announced by a priority peer, so we rewind to the genesis block
+        let (local, _remote) = tokio::io::duplex(4096);
+        let connection = Connection::new(local, "10.0.0.1:2125".parse().unwrap());
+        let peer = Peer::new(connection, PeerDetails { protocol_version: PROTOCOL_VERSION, block_top_hash: connected[3].clone(), block_height: 5, ..test_details(1, 2125) }, true, true, Arc::clone(&server.peer_list), std::collections::HashSet::new());
+        let peer = server.peer_list.lock().await.add_peer(1, peer);
+        let mut reorgs = server.subscribe_reorgs();
+
+        let handle = {
+            let server = Arc::clone(&server);
+            let peer = Arc::clone(&peer);
+            let hashes = connected.clone();
+            tokio::spawn(async move {
+                server.handle_chain_response(&peer, hashes, 2).await
+            })
+        };
+        // serve the blocks of the fork as they are requested
+        timeout(Duration::from_secs(5), async {
+            let mut served = 0;
+            while served < connected.len() {
+                let requests: Vec<ObjectRequest> = peer.get_objects_requested().lock().await.keys().cloned().collect();
+                for request in requests {
+                    let block = fork.get_storage().lock().await.get_block_by_hash(request.get_hash()).unwrap().clone();
+                    let sender = peer.remove_object_request(request).await.unwrap();
+                    assert!(sender.send(OwnedObjectResponse::Block(block)).is_ok());
+                    served += 1;
+                }
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+        }).await.expect("blocks of the fork not requested");
+        handle.await.unwrap().unwrap();
+
+        let event = reorgs.recv().await.unwrap();
+        assert_eq!(event.common_ancestor, genesis);
+        assert_eq!(event.disconnected, disconnected);
+        assert_eq!(event.connected, connected);
+        assert_eq!(server.blockchain.get_top_block_hash().await, connected[3]);
+    }
+
+    #[tokio::test]
+    async fn test_reorg_deeper_than_limit_is_refused() {
+        let miner = KeyPair::new();
+        let server = P2pServer::new(test_config(), Arc::new(create_test_chain(&miner, 1).await)).unwrap();
+        let genesis = server.blockchain.get_top_block_hash().await;
+        let max_reorg_depth = server.blockchain.get_max_reorg_depth();
+        for _ in 0..max_reorg_depth {
+            add_test_block(&server.blockchain, &miner).await;
+        }
+        let (peer, _remote) = add_test_peer(&server, 1, 100, Hash::zero()).await;
+        let chain_response = |hash: &Hash, height: u64| Packet::ChainResponse(ChainResponse::new(Some(CommonPoint::new(Cow::Owned(hash.clone()), height)), Vec::new()));
+
+        // the genesis block as common point is exactly at the limit
+        peer.set_chain_sync_requested(true);
+        server.handle_incoming_packet(&peer, chain_response(&genesis, 1)).await.unwrap();
+
+        // one block more and the switch is refused before requesting any block
+        let top_hash = add_test_block(&server.blockchain, &miner).await;
+        peer.set_chain_sync_requested(true);
+        let result = server.handle_incoming_packet(&peer, chain_response(&genesis, 1)).await;
+        assert!(matches!(result, Err(P2pError::ReorgTooDeep(depth, max)) if depth == max_reorg_depth + 1 && max == max_reorg_depth));
+        assert!(peer.get_objects_requested().lock().await.is_empty());
+        assert_eq!(server.blockchain.get_top_block_hash().await, top_hash);
+    }
+
+    #[tokio::test]
+    async fn test_block_requests_spread_across_peers() {
+        let mut config = test_config();
+        config.max_requests_per_peer = 2;
+        let server = create_test_server(config).await;
+        let mut peers = Vec::new();
+        let mut remotes = Vec::new();
+        for id in 1..=3 {
+            let (peer, remote) = add_test_peer(&server, id, 10, Hash::zero()).await;
+            peers.push(peer);
+            remotes.push(remote);
+        }
+
+        let miner = KeyPair::new();
+        let blocks: Vec<CompleteBlock> = (0..6).map(|i| {
+            let coinbase = Transaction::new(miner.get_public_key().clone(), TransactionVariant::Coinbase);
+            CompleteBlock::new(Block::new(i + 2, i as u128, Hash::zero(), [0u8; 32], coinbase, Vec::new()), 1, Vec::new())
+        }).collect();
+        let hashes: Vec<Hash> = blocks.iter().map(|block| block.hash()).collect();
+
+        let handle = {
+            let server = Arc::clone(&server);
+            let peers = peers.clone();
+            let ping = Arc::new(Ping::new(Cow::Owned(Hash::zero()), 1, Vec::new()));
+            tokio::spawn(async move {
+                server.request_blocks(&peers[0], &peers, hashes, ping).await
+            })
+        };
+
+        // each peer receives its share of the requests, none above the limit
+        timeout(Duration::from_secs(2), async {
+            while peers.iter().any(|peer| peer.get_requests_in_flight() != 2) {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        }).await.expect("requests not spread across all peers");
+        assert!(server.get_requests_in_flight().await.values().all(|count| *count == 2));
+
+        // answer each request from the peer it was sent to
+        for peer in &peers {
+            let requests: Vec<ObjectRequest> = peer.get_objects_requested().lock().await.keys().cloned().collect();
+            assert_eq!(requests.len(), 2);
+            for request in requests {
+                let block = blocks.iter().find(|block| block.hash() == *request.get_hash()).unwrap().clone();
+                let sender = peer.remove_object_request(request).await.unwrap();
+                assert!(sender.send(OwnedObjectResponse::Block(block)).is_ok());
+            }
+        }
+
+        // blocks are returned in the requested order
+        let received = handle.await.unwrap().unwrap();
+        assert_eq!(received.iter().map(|(block, _)| block.hash()).collect::<Vec<Hash>>(), blocks.iter().map(|block| block.hash()).collect::<Vec<Hash>>());
+        assert!(peers.iter().all(|peer| peer.get_requests_in_flight() == 0));
+    }
+}
@@ -0,0 +1,116 @@
+use super::error::P2pError;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use std::net::SocketAddr;
+
+// Minimal SOCKS5 client (RFC 1928) used to dial peers through a proxy (Tor for example)
+// only the CONNECT command without authentication is supported
+const SOCKS_VERSION: u8 = 5;
+const NO_AUTHENTICATION: u8 = 0;
+const COMMAND_CONNECT: u8 = 1;
+const ADDRESS_IPV4: u8 = 1;
+const ADDRESS_DOMAIN: u8 = 3;
+const ADDRESS_IPV6: u8 = 4;
+const REPLY_SUCCEEDED: u8 = 0;
+
+// open a TCP stream to the target through the proxy, the stream can then be used as a direct connection
+pub async fn connect(proxy: &SocketAddr, target: &SocketAddr) -> Result<TcpStream, P2pError> {
+    let mut stream = TcpStream::connect(proxy).await?;
+    stream.write_all(&[SOCKS_VERSION, 1, NO_AUTHENTICATION]).await?;
+    let mut method = [0u8; 2];
+    stream.read_exact(&mut method).await?;
+    if method != [SOCKS_VERSION, NO_AUTHENTICATION] {
+        return Err(P2pError::ProxyError("authentication method refused"))
+    }
+
+    let mut request = vec![SOCKS_VERSION, COMMAND_CONNECT, 0];
+    match target {
+        SocketAddr::V4(addr) => {
+            request.push(ADDRESS_IPV4);
+            request.extend(addr.ip().octets());
+        },
+        SocketAddr::V6(addr) => {
+            request.push(ADDRESS_IPV6);
+            request.extend(addr.ip().octets());
+        }
+    };
+    request.extend(target.port().to_be_bytes());
+    stream.write_all(&request).await?;
+
+    let mut header = [0u8; 4];
+    stream.read_exact(&mut header).await?;
+    if header[0] != SOCKS_VERSION {
+        return Err(P2pError::ProxyError("invalid version in reply"))
+    }
+    if header[1] != REPLY_SUCCEEDED {
+        return Err(P2pError::ProxyConnectFailed(header[1]))
+    }
+
+    // skip the address bound by the proxy and its port
+    let size = match header[3] {
+        ADDRESS_IPV4 => 4,
+        ADDRESS_IPV6 => 16,
+        ADDRESS_DOMAIN => stream.read_u8().await? as usize,
+        _ => return Err(P2pError::ProxyError("invalid address type in reply"))
+    };
+    let mut bound = vec![0u8; size + 2];
+    stream.read_exact(&mut bound).await?;
+    Ok(stream)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    // mock proxy accepting one connection, it checks the CONNECT request to `target` and answers with `reply`
+    // on success, the proxied stream echoes everything it receives
+    async fn spawn_proxy(target: SocketAddr, reply: u8) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut greeting = [0u8; 3];
+            stream.read_exact(&mut greeting).await.unwrap();
+            assert_eq!(greeting, [SOCKS_VERSION, 1, NO_AUTHENTICATION]);
+            stream.write_all(&[SOCKS_VERSION, NO_AUTHENTICATION]).await.unwrap();
+
+            let mut request = [0u8; 10];
+            stream.read_exact(&mut request).await.unwrap();
+            let mut expected = vec![SOCKS_VERSION, COMMAND_CONNECT, 0, ADDRESS_IPV4];
+            if let SocketAddr::V4(target) = target {
+                expected.extend(target.ip().octets());
+            }
+            expected.extend(target.port().to_be_bytes());
+            assert_eq!(request.to_vec(), expected);
+            // bound address is a domain, to check it's skipped with its length
+            stream.write_all(&[SOCKS_VERSION, reply, 0, ADDRESS_DOMAIN, 5, b'p', b'r', b'o', b'x', b'y', 0x1f, 0x90]).await.unwrap();
+            if reply == REPLY_SUCCEEDED {
+                let mut buf = [0u8; 4];
+                stream.read_exact(&mut buf).await.unwrap();
+                stream.write_all(&buf).await.unwrap();
+            }
+        });
+        addr
+    }
+
+    #[tokio::test]
+    async fn test_connect_through_proxy() {
+        let target: SocketAddr = "10.0.0.1:2125".parse().unwrap();
+        let proxy = spawn_proxy(target, REPLY_SUCCEEDED).await;
+        let mut stream = connect(&proxy, &target).await.unwrap();
+        // the bound address was fully read, the stream only carries our data
+        stream.write_all(b"ping").await.unwrap();
+        let mut buf = [0u8; 4];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"ping");
+    }
+
+    #[tokio::test]
+    async fn test_connect_refused_by_proxy() {
+        let target: SocketAddr = "10.0.0.1:2125".parse().unwrap();
+        // 5 is "connection refused"
+        let proxy = spawn_proxy(target, 5).await;
+        assert!(matches!(connect(&proxy, &target).await, Err(P2pError::ProxyConnectFailed(5))));
+    }
+}
@@ -36,7 +36,9 @@ pub enum RpcError {
     #[error("Error: {}", _0)]
     AnyError(#[from] AnyError),
     #[error("Error, expected a normal wallet address")]
-    ExpectedNormalAddress
+    ExpectedNormalAddress,
+    #[error("Error, address is not from this network")]
+    InvalidAddressNetwork
 }
 
 impl RpcError {
@@ -1,7 +1,8 @@
-use crate::{core::{blockchain::Blockchain, block::Block, serializer::Serializer, message::MessageData, transaction::Transaction}, crypto::{hash::Hash, address::Address}};
+use crate::{core::{blockchain::Blockchain, block::Block, serializer::Serializer, message::MessageData, transaction::Transaction}, crypto::{hash::Hash, address::Address}, p2p::packet::object::ObjectRequest};
 use super::{RpcError, RpcServer};
 use serde::{Deserialize, Serialize, de::DeserializeOwned};
 use serde_json::{json, Value};
+use std::collections::HashMap;
 use std::sync::Arc;
 use log::info;
 
@@ -43,6 +44,19 @@ pub struct GetAccountParams<'a> {
     pub address: Address<'a>
 }
 
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ObjectKind {
+    Block,
+    Transaction
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct HasObjectParams {
+    pub kind: ObjectKind,
+    pub hash: Hash
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct SubmitTransactionParams {
     pub data: String // should be in hex format
@@ -70,6 +84,11 @@ pub fn register_methods(server: &mut RpcServer) {
     server.register_method("get_messages", method!(get_messages));
     server.register_method("get_account", method!(get_account));
     server.register_method("submit_transaction", method!(submit_transaction));
+    server.register_method("get_peers", method!(get_peers));
+    server.register_method("get_chain_tip_consensus", method!(get_chain_tip_consensus));
+    server.register_method("get_fee_policy", method!(get_fee_policy));
+    server.register_method("has_object", method!(has_object));
+    server.register_method("get_dust_threshold", method!(get_dust_threshold));
 }
 
 async fn get_height(blockchain: Arc<Blockchain>, body: Value) -> Result<Value, RpcError> {
@@ -98,6 +117,9 @@ async fn get_block_template(blockchain: Arc<Blockchain>, body: Value) -> Result<
     if !params.address.is_normal() {
         return Err(RpcError::ExpectedNormalAddress)
     }
+    if params.address.is_mainnet() != blockchain.get_network().is_mainnet() {
+        return Err(RpcError::InvalidAddressNetwork)
+    }
     let block = blockchain.get_block_template(params.address.to_public_key()).await?;
     Ok(json!(GetBlockTemplateResult { template: block.to_hex(), difficulty: blockchain.get_difficulty() }))
 }
@@ -133,4 +155,57 @@ async fn submit_transaction(blockchain: Arc<Blockchain>, body: Value) -> Result<
     let transaction = Transaction::from_hex(params.data)?;
     blockchain.add_tx_to_mempool(transaction, true).await?;
     Ok(json!(true))
+}
+
+async fn get_peers(blockchain: Arc<Blockchain>, body: Value) -> Result<Value, RpcError> {
+    if body != Value::Null {
+        return Err(RpcError::UnexpectedParams)
+    }
+    let p2p = blockchain.get_p2p().lock().await.as_ref().map(Arc::clone);
+    let peers = match p2p {
+        Some(p2p) => p2p.get_peers_info().await,
+        None => Vec::new()
+    };
+    Ok(json!(peers))
+}
+
+// top hashes reported by the peers at our height and the number of peers for each of them
+async fn get_chain_tip_consensus(blockchain: Arc<Blockchain>, body: Value) -> Result<Value, RpcError> {
+    if body != Value::Null {
+        return Err(RpcError::UnexpectedParams)
+    }
+    let p2p = blockchain.get_p2p().lock().await.as_ref().map(Arc::clone);
+    let tips = match p2p {
+        Some(p2p) => p2p.chain_tip_consensus(blockchain.get_height()).await,
+        None => HashMap::new()
+    };
+    Ok(json!(tips))
+}
+
+// name of the policy used by this node to accept and relay a tx
+async fn get_fee_policy(blockchain: Arc<Blockchain>, body: Value) -> Result<Value, RpcError> {
+    if body != Value::Null {
+        return Err(RpcError::UnexpectedParams)
+    }
+    let mempool = blockchain.get_mempool().lock().await;
+    Ok(json!(mempool.get_fee_policy().get_name()))
+}
+
+// minimum amount of a tx output accepted and relayed by this node, wallets should not create smaller ones
+async fn get_dust_threshold(blockchain: Arc<Blockchain>, body: Value) -> Result<Value, RpcError> {
+    if body != Value::Null {
+        return Err(RpcError::UnexpectedParams)
+    }
+    let mempool = blockchain.get_mempool().lock().await;
+    Ok(json!(mempool.get_dust_threshold()))
+}
+
+// check if a block or a tx (in mempool or confirmed) is known by this node
+async fn has_object(blockchain: Arc<Blockchain>, body: Value) -> Result<Value, RpcError> {
+    let params: HasObjectParams = parse_params(body)?;
+    let request = match params.kind {
+        ObjectKind::Block => ObjectRequest::Block(params.hash),
+        ObjectKind::Transaction => ObjectRequest::Transaction(params.hash)
+    };
+    Ok(json!(blockchain.has_object(&request).await))
 }
\ No newline at end of file
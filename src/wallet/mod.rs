@@ -4,7 +4,7 @@ use crate::core::blockchain::calculate_tx_fee;
 use crate::core::json_rpc::{JsonRPCClient, JsonRPCError};
 use crate::core::serializer::Serializer;
 use crate::crypto::address::{Address, AddressType};
-use crate::crypto::key::{KeyPair, SIGNATURE_LENGTH};
+use crate::crypto::key::{KeyPair, CryptoError, SIGNATURE_LENGTH};
 use crate::core::transaction::{Transaction, TransactionData, TransactionVariant};
 use crate::core::error::BlockchainError;
 use crate::rpc::rpc::SubmitTransactionParams;
@@ -18,14 +18,14 @@ pub struct Wallet {
 }
 
 impl Wallet {
-    pub fn new(daemon_address: String) -> Self {
-        Wallet {
-            keypair: KeyPair::new(),
+    pub fn new(daemon_address: String) -> Result<Self, CryptoError> {
+        Ok(Wallet {
+            keypair: KeyPair::try_new()?,
             balance: 0,
             nonce: 0,
             transactions: Vec::new(),
             client: JsonRPCClient::new(daemon_address)
-        }
+        })
     }
 
     pub fn get_address(&self) -> Address {